@@ -0,0 +1,566 @@
+use std::{fmt, io};
+use std::collections::HashMap;
+
+use vfat::{VFat, Shared, Cluster, Status};
+use vfat::dir::{raw_children, EntryLocation, RawEntry};
+
+/// The FAT entry value written to end a chain. It is truncated to the width
+/// of the volume's FAT entries when written.
+const END_OF_CHAIN: u32 = 0x0FFFFFFF;
+
+/// Whether `check()` only reports problems or also repairs them.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Mode {
+    /// Report problems without writing to the volume.
+    ReadOnly,
+    /// Report problems and repair those that can be repaired without losing
+    /// reachable data.
+    Repair,
+}
+
+/// An inconsistency found by `check()`. Clusters are identified by their raw
+/// cluster number.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Problem {
+    /// A chain of `clusters` allocated clusters, starting at `start`, that no
+    /// directory entry refers to. Repaired by freeing the clusters.
+    LostChain { start: u32, clusters: u32 },
+    /// The chain of `path` runs into `cluster`, which already belongs to
+    /// another chain. Repaired by ending the chain before `cluster`.
+    CrossLinked { path: String, cluster: u32 },
+    /// The chain of `path` loops back to `cluster`, which it already contains.
+    /// Repaired by ending the chain before the loop.
+    Cycle { path: String, cluster: u32 },
+    /// The chain of `path` refers to `cluster`, which is out of range or is
+    /// free, reserved, or bad. Repaired by ending the chain before `cluster`.
+    InvalidCluster { path: String, cluster: u32 },
+    /// The file at `path` is `size` bytes long, but its chain is `clusters`
+    /// clusters long. Repaired by freeing the clusters past the end of the
+    /// file or, if the chain is too short, by shrinking the file to the
+    /// chain's length.
+    SizeMismatch { path: String, size: u64, clusters: u32 },
+    /// The `entries` LFN entries before the entry for `path` have a checksum
+    /// that does not match its short name. Repaired by deleting the LFN
+    /// entries, leaving the entry with only its short name.
+    BadLfnChecksum { path: String, entries: usize },
+    /// `sectors` sectors of the FAT copy `fat` differ from the first FAT.
+    /// Repaired by copying the first FAT over the copy.
+    FatMismatch { fat: u8, sectors: u32 },
+}
+
+impl fmt::Display for Problem {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        use self::Problem::*;
+
+        match *self {
+            LostChain { start, clusters } => {
+                write!(f, "lost chain of {} clusters starting at cluster {}", clusters, start)
+            }
+            CrossLinked { ref path, cluster } => {
+                write!(f, "{}: cross-linked with another chain at cluster {}", path, cluster)
+            }
+            Cycle { ref path, cluster } => {
+                write!(f, "{}: chain loops back to cluster {}", path, cluster)
+            }
+            InvalidCluster { ref path, cluster } => {
+                write!(f, "{}: chain refers to invalid cluster {}", path, cluster)
+            }
+            SizeMismatch { ref path, size, clusters } => {
+                write!(f, "{}: size is {} bytes but chain is {} clusters long",
+                       path, size, clusters)
+            }
+            BadLfnChecksum { ref path, entries } => {
+                write!(f, "{}: {} long file name entries have a bad checksum", path, entries)
+            }
+            FatMismatch { fat, sectors } => {
+                write!(f, "FAT {}: {} sectors differ from the first FAT", fat, sectors)
+            }
+        }
+    }
+}
+
+/// A problem found by `check()` and whether it was repaired.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Issue {
+    pub problem: Problem,
+    pub repaired: bool,
+}
+
+impl fmt::Display for Issue {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.repaired {
+            true => write!(f, "{} (repaired)", self.problem),
+            false => write!(f, "{}", self.problem),
+        }
+    }
+}
+
+/// The result of checking a volume.
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct Report {
+    /// Every problem found, in the order it was found.
+    pub issues: Vec<Issue>,
+    /// The number of files checked.
+    pub files: u64,
+    /// The number of directories checked, including the root directory.
+    pub dirs: u64,
+}
+
+impl Report {
+    /// Returns `true` if no problems were found.
+    pub fn is_clean(&self) -> bool {
+        self.issues.is_empty()
+    }
+
+    /// The number of problems that were found but not repaired.
+    pub fn unrepaired(&self) -> usize {
+        self.issues.iter().filter(|issue| !issue.repaired).count()
+    }
+}
+
+impl fmt::Display for Report {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for issue in &self.issues {
+            writeln!(f, "{}", issue)?;
+        }
+
+        write!(f, "{} files, {} directories, {} problems, {} unrepaired",
+               self.files, self.dirs, self.issues.len(), self.unrepaired())
+    }
+}
+
+/// Checks the FAT volume `vfat` for consistency, walking every directory and
+/// chain from the root directory and every copy of the FAT.
+///
+/// In `Mode::Repair`, problems are repaired as they are found, every copy of
+/// the FAT is made identical to the first, and the volume is flushed.
+///
+/// # Errors
+///
+/// Returns an error if reading from or writing to the volume fails. A volume
+/// may be partially repaired when an error is returned.
+pub fn check(vfat: &Shared<VFat>, mode: Mode) -> io::Result<Report> {
+    let mut checker = Checker::new(vfat, mode);
+
+    let fat_mismatches = checker.record_fat_mismatches()?;
+    checker.check_root()?;
+    checker.check_lost_clusters()?;
+
+    if checker.repair {
+        vfat.borrow_mut().mirror_fats()?;
+        for index in fat_mismatches {
+            checker.report.issues[index].repaired = true;
+        }
+
+        vfat.borrow_mut().flush()?;
+    }
+
+    Ok(checker.report)
+}
+
+/// Compares every copy of the FAT of `vfat` with the first, returning a
+/// `Problem::FatMismatch` for each copy that differs. When FAT mirroring is
+/// disabled, only the active copy is in use, so nothing is compared.
+///
+/// # Errors
+///
+/// Returns an error if reading from the volume fails.
+pub fn compare_fats(vfat: &Shared<VFat>) -> io::Result<Vec<Problem>> {
+    let mut vfat = vfat.borrow_mut();
+    if vfat.active_fat().is_some() {
+        return Ok(Vec::new());
+    }
+
+    let mut problems = Vec::new();
+    for fat in 1..vfat.num_fats() {
+        let mut sectors = 0;
+        for index in 0..vfat.sectors_per_fat() {
+            let first = vfat.fat_sector(0, index)?.to_vec();
+            if vfat.fat_sector(fat, index)? != &first[..] {
+                sectors += 1;
+            }
+        }
+
+        if sectors > 0 {
+            problems.push(Problem::FatMismatch { fat: fat, sectors: sectors });
+        }
+    }
+
+    Ok(problems)
+}
+
+/// A set of cluster numbers stored as a bitmap.
+struct ClusterSet(Vec<u64>);
+
+impl ClusterSet {
+    /// Returns an empty set that can hold clusters `0` through `limit - 1`.
+    fn new(limit: u32) -> ClusterSet {
+        ClusterSet(vec![0; (limit as usize + 63) / 64])
+    }
+
+    fn contains(&self, cluster: Cluster) -> bool {
+        let n = cluster.number() as usize;
+        self.0[n / 64] & (1 << (n % 64)) != 0
+    }
+
+    fn insert(&mut self, cluster: Cluster) {
+        let n = cluster.number() as usize;
+        self.0[n / 64] |= 1 << (n % 64);
+    }
+
+    fn remove(&mut self, cluster: Cluster) {
+        let n = cluster.number() as usize;
+        self.0[n / 64] &= !(1 << (n % 64));
+    }
+}
+
+/// Why walking a chain stopped before its end.
+enum Fault {
+    CrossLinked(Cluster),
+    Cycle(Cluster),
+    Invalid(Cluster),
+}
+
+impl Fault {
+    fn problem(&self, path: &str) -> Problem {
+        let path = path.to_string();
+        match *self {
+            Fault::CrossLinked(c) => Problem::CrossLinked { path: path, cluster: c.number() },
+            Fault::Cycle(c) => Problem::Cycle { path: path, cluster: c.number() },
+            Fault::Invalid(c) => Problem::InvalidCluster { path: path, cluster: c.number() },
+        }
+    }
+}
+
+/// The clusters of a chain up to its end or up to the first fault.
+struct Walk {
+    clusters: Vec<Cluster>,
+    fault: Option<Fault>,
+}
+
+struct Checker<'a> {
+    vfat: &'a Shared<VFat>,
+    repair: bool,
+    /// Clusters that belong to a chain reachable from the root directory.
+    used: ClusterSet,
+    cluster_count: u32,
+    bytes_per_cluster: u64,
+    report: Report,
+}
+
+impl<'a> Checker<'a> {
+    fn new(vfat: &'a Shared<VFat>, mode: Mode) -> Checker<'a> {
+        let (cluster_count, bytes_per_cluster) = {
+            let vfat = vfat.borrow();
+            (vfat.cluster_count(), vfat.bytes_per_cluster() as u64)
+        };
+
+        Checker {
+            vfat: vfat,
+            repair: mode == Mode::Repair,
+            used: ClusterSet::new(cluster_count + 2),
+            cluster_count: cluster_count,
+            bytes_per_cluster: bytes_per_cluster,
+            report: Report::default(),
+        }
+    }
+
+    /// Records `problem`, returning its index in the report.
+    fn record(&mut self, problem: Problem, repaired: bool) -> usize {
+        self.report.issues.push(Issue { problem: problem, repaired: repaired });
+        self.report.issues.len() - 1
+    }
+
+    /// Returns `true` if `cluster` is a data cluster of this volume.
+    fn in_range(&self, cluster: Cluster) -> bool {
+        cluster.is_data() && cluster.number() < self.cluster_count + 2
+    }
+
+    /// Records a problem for each copy of the FAT that differs from the first.
+    /// Returns the indices of the recorded problems.
+    fn record_fat_mismatches(&mut self) -> io::Result<Vec<usize>> {
+        let mut recorded = Vec::new();
+        for problem in compare_fats(self.vfat)? {
+            recorded.push(self.record(problem, false));
+        }
+
+        Ok(recorded)
+    }
+
+    /// Follows the chain starting at `start`, marking its clusters as used,
+    /// until its end or a cluster that can't be part of it.
+    fn walk(&mut self, start: Cluster) -> io::Result<Walk> {
+        let mut clusters = Vec::new();
+        let mut current = start;
+        let fault = loop {
+            if !self.in_range(current) {
+                break Fault::Invalid(current);
+            }
+
+            if self.used.contains(current) {
+                match clusters.contains(&current) {
+                    true => break Fault::Cycle(current),
+                    false => break Fault::CrossLinked(current),
+                }
+            }
+
+            let status = self.vfat.borrow_mut().fat_entry(current)?.status();
+            match status {
+                Status::Data(next) => {
+                    self.used.insert(current);
+                    clusters.push(current);
+                    current = next;
+                }
+                Status::Eoc(_) => {
+                    self.used.insert(current);
+                    clusters.push(current);
+                    return Ok(Walk { clusters: clusters, fault: None });
+                }
+                _ => break Fault::Invalid(current),
+            }
+        };
+
+        Ok(Walk { clusters: clusters, fault: Some(fault) })
+    }
+
+    /// Checks the root directory and, recursively, every entry below it.
+    fn check_root(&mut self) -> io::Result<()> {
+        let root = self.vfat.borrow().root_dir_cluster();
+        if root.is_data() {
+            let walk = self.walk(root)?;
+            if let Some(fault) = walk.fault {
+                let repaired = self.repair && self.end_chain(&walk.clusters)?;
+                self.record(fault.problem("/"), repaired);
+                if !repaired {
+                    return Ok(());
+                }
+            }
+        }
+
+        self.check_dir(root, "/")
+    }
+
+    /// Ends the chain whose clusters up to a fault are `clusters` after its
+    /// last cluster. Returns `false` if the chain has no clusters to end.
+    fn end_chain(&mut self, clusters: &[Cluster]) -> io::Result<bool> {
+        match clusters.last() {
+            Some(&last) => {
+                self.vfat.borrow_mut().set_fat_entry(last, END_OF_CHAIN)?;
+                Ok(true)
+            }
+            None => Ok(false),
+        }
+    }
+
+    /// Checks every entry of the directory starting at `cluster` found at
+    /// `path`, descending into its subdirectories.
+    fn check_dir(&mut self, cluster: Cluster, path: &str) -> io::Result<()> {
+        self.report.dirs += 1;
+
+        for (child_path, raw) in raw_children(self.vfat, cluster, path)? {
+            if raw.orphaned_lfn_entries > 0 {
+                self.check_lfn_entries(&raw, &child_path)?;
+            }
+
+            if raw.regular.metadata().attributes.directory() {
+                if self.check_subdir_chain(&raw, &child_path)? {
+                    self.check_dir(raw.regular.cluster(), &child_path)?;
+                }
+            } else {
+                self.check_file(&raw, &child_path)?;
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Records, and in repair mode deletes, the LFN entries before `raw` that
+    /// don't belong to it.
+    fn check_lfn_entries(&mut self, raw: &RawEntry, path: &str) -> io::Result<()> {
+        let entries = raw.orphaned_lfn_entries;
+        if self.repair {
+            let location = raw.location;
+            for i in 1..entries as u64 + 1 {
+                self.vfat.borrow_mut().write_raw_dir_entry(location.dir, location.offset - i * 32,
+                                                           &[0xE5])?;
+            }
+        }
+
+        let problem = Problem::BadLfnChecksum { path: path.to_string(), entries: entries };
+        let repaired = self.repair;
+        self.record(problem, repaired);
+        Ok(())
+    }
+
+    /// Checks the chain of the subdirectory `raw`. Returns `true` if the
+    /// chain is, or has been repaired to be, intact so that the subdirectory
+    /// can be read.
+    fn check_subdir_chain(&mut self, raw: &RawEntry, path: &str) -> io::Result<bool> {
+        let walk = self.walk(raw.regular.cluster())?;
+        match walk.fault {
+            None => Ok(true),
+            Some(fault) => {
+                let repaired = self.repair && self.end_chain(&walk.clusters)?;
+                self.record(fault.problem(path), repaired);
+                Ok(repaired)
+            }
+        }
+    }
+
+    /// Checks the chain of the file `raw` and that its size agrees with the
+    /// chain's length.
+    fn check_file(&mut self, raw: &RawEntry, path: &str) -> io::Result<()> {
+        self.report.files += 1;
+
+        let start = raw.regular.cluster();
+        let mut clusters = Vec::new();
+        if start.number() != 0 {
+            let walk = self.walk(start)?;
+            if let Some(fault) = walk.fault {
+                // A chain that is faulty from its first cluster holds none of
+                // the file's data, so the file is detached from it.
+                let detached = walk.clusters.is_empty();
+                if self.repair {
+                    match detached {
+                        true => self.set_file_chain(raw.location, None, 0)?,
+                        false => { self.end_chain(&walk.clusters)?; }
+                    }
+                }
+
+                let repaired = self.repair;
+                self.record(fault.problem(path), repaired);
+                if detached && repaired {
+                    return Ok(());
+                }
+            }
+
+            clusters = walk.clusters;
+        }
+
+        let size = raw.regular.size() as u64;
+        let needed = (size + self.bytes_per_cluster - 1) / self.bytes_per_cluster;
+        let actual = clusters.len() as u64;
+        if needed == actual {
+            return Ok(());
+        }
+
+        if self.repair {
+            if actual > needed {
+                self.free_tail(raw.location, &clusters, needed as usize)?;
+            } else {
+                let size = actual * self.bytes_per_cluster;
+                let first = clusters.first().cloned();
+                self.set_file_chain(raw.location, first, size as u32)?;
+            }
+        }
+
+        let problem = Problem::SizeMismatch {
+            path: path.to_string(),
+            size: size,
+            clusters: actual as u32,
+        };
+        let repaired = self.repair;
+        self.record(problem, repaired);
+        Ok(())
+    }
+
+    /// Sets the first cluster and size of the file whose entry is at
+    /// `location`.
+    fn set_file_chain(
+        &mut self,
+        location: EntryLocation,
+        first: Option<Cluster>,
+        size: u32
+    ) -> io::Result<()> {
+        let mut vfat = self.vfat.borrow_mut();
+        let mut entry = vfat.read_dir_entry(location)?;
+        entry.set_cluster(first.unwrap_or(Cluster::from(0)));
+        entry.set_size(size);
+        vfat.write_dir_entry(location, &entry)
+    }
+
+    /// Frees the clusters of the file chain `clusters` past the first `keep`,
+    /// ending the chain or, if `keep` is zero, detaching it from the file's
+    /// entry at `location`.
+    fn free_tail(
+        &mut self,
+        location: EntryLocation,
+        clusters: &[Cluster],
+        keep: usize
+    ) -> io::Result<()> {
+        match keep {
+            0 => {
+                let size = self.vfat.borrow_mut().read_dir_entry(location)?.size();
+                self.set_file_chain(location, None, size)?;
+            }
+            _ => {
+                self.end_chain(&clusters[..keep])?;
+            }
+        }
+
+        for &cluster in &clusters[keep..] {
+            self.vfat.borrow_mut().set_fat_entry(cluster, 0)?;
+            self.used.remove(cluster);
+        }
+
+        Ok(())
+    }
+
+    /// Records, and in repair mode frees, every chain of allocated clusters
+    /// that is not reachable from the root directory.
+    fn check_lost_clusters(&mut self) -> io::Result<()> {
+        let mut lost = HashMap::new();
+        for number in 2..self.cluster_count + 2 {
+            let cluster = Cluster::from(number);
+            if self.used.contains(cluster) {
+                continue;
+            }
+
+            match self.vfat.borrow_mut().fat_entry(cluster)?.status() {
+                Status::Data(next) => { lost.insert(number, Some(next.number())); }
+                Status::Eoc(_) => { lost.insert(number, None); }
+                _ => {}
+            }
+        }
+
+        let mut pointed_to = ClusterSet::new(self.cluster_count + 2);
+        for next in lost.values() {
+            if let Some(next) = *next {
+                if lost.contains_key(&next) {
+                    pointed_to.insert(Cluster::from(next));
+                }
+            }
+        }
+
+        // Chains are followed from their heads first so that each is reported
+        // once from its start; what remains afterwards are loops.
+        let mut starts: Vec<u32> = lost.keys().cloned().collect();
+        starts.sort();
+        let (heads, rest): (Vec<u32>, Vec<u32>) = starts.into_iter()
+            .partition(|&number| !pointed_to.contains(Cluster::from(number)));
+
+        for start in heads.into_iter().chain(rest) {
+            let mut clusters = 0;
+            let mut current = Some(start);
+            while let Some(number) = current {
+                current = match lost.remove(&number) {
+                    Some(next) => next,
+                    None => break,
+                };
+
+                if self.repair {
+                    self.vfat.borrow_mut().set_fat_entry(Cluster::from(number), 0)?;
+                }
+
+                clusters += 1;
+            }
+
+            if clusters > 0 {
+                let repaired = self.repair;
+                self.record(Problem::LostChain { start: start, clusters: clusters }, repaired);
+            }
+        }
+
+        Ok(())
+    }
+}
@@ -0,0 +1,239 @@
+use std::{fmt, io};
+
+use vfat::{VFat, Shared, Cluster};
+use vfat::dir::{raw_children, RawEntry};
+
+/// The FAT entry value written to end a chain. It is truncated to the width
+/// of the volume's FAT entries when written.
+const END_OF_CHAIN: u32 = 0x0FFFFFFF;
+
+/// The fragmentation of a file's or directory's cluster chain.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Fragments {
+    /// The absolute path of the file or directory.
+    pub path: String,
+    pub directory: bool,
+    /// The number of clusters in the chain.
+    pub clusters: u32,
+    /// The number of runs of consecutive clusters the chain is made of. A
+    /// contiguous chain has one fragment; an empty one has none.
+    pub fragments: u32,
+}
+
+/// The fragmentation of a FAT volume, as returned by `report()`.
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct Report {
+    /// Every file and directory reachable from the root directory, in the
+    /// order found. The fixed root directory region of FAT12 and FAT16 volumes
+    /// is not included.
+    pub files: Vec<Fragments>,
+    /// Every run of consecutive free clusters as its first cluster and
+    /// length, in cluster order.
+    pub free: Vec<(Cluster, u32)>,
+}
+
+impl Report {
+    /// The files and directories whose chains are made of more than one run.
+    pub fn fragmented(&self) -> impl Iterator<Item = &Fragments> {
+        self.files.iter().filter(|file| file.fragments > 1)
+    }
+
+    /// The length of the longest run of free clusters.
+    pub fn largest_free_run(&self) -> u32 {
+        self.free.iter().map(|run| run.1).max().unwrap_or(0)
+    }
+}
+
+impl fmt::Display for Report {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for file in self.fragmented() {
+            writeln!(f, "{}: {} clusters in {} fragments", file.path, file.clusters,
+                     file.fragments)?;
+        }
+
+        let free: u32 = self.free.iter().map(|run| run.1).sum();
+        write!(f, "{} of {} chains fragmented, {} free clusters in {} runs",
+               self.fragmented().count(), self.files.len(), free, self.free.len())
+    }
+}
+
+/// A file or directory found while walking the volume.
+struct Found {
+    path: String,
+    raw: Option<RawEntry>,
+    chain: Vec<Cluster>,
+}
+
+impl Found {
+    fn fragments(&self) -> u32 {
+        let breaks = self.chain.windows(2)
+            .filter(|pair| pair[1].number() != pair[0].number() + 1)
+            .count();
+        match self.chain.is_empty() {
+            true => 0,
+            false => breaks as u32 + 1,
+        }
+    }
+}
+
+/// Reports the fragmentation of every cluster chain reachable from the root
+/// directory of `vfat` and the volume's free space.
+///
+/// # Errors
+///
+/// Returns an error if reading from the volume fails or if a chain is
+/// malformed, in which case the volume should be repaired with
+/// `check::check()` first.
+pub fn report(vfat: &Shared<VFat>) -> io::Result<Report> {
+    let files = walk(vfat)?.into_iter()
+        .map(|found| Fragments {
+            directory: found.raw.as_ref().map_or(true, |raw| {
+                raw.regular.metadata().attributes.directory()
+            }),
+            clusters: found.chain.len() as u32,
+            fragments: found.fragments(),
+            path: found.path,
+        })
+        .collect();
+
+    Ok(Report {
+        files: files,
+        free: vfat.borrow_mut().free_runs()?,
+    })
+}
+
+/// Why `defragment()` left a fragmented chain in place.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Skip {
+    /// The chain belongs to a directory. Moving it would also mean rewriting
+    /// the `..` entries of its subdirectories, or the BPB for the root
+    /// directory.
+    Directory,
+    /// No run of free clusters is large enough to hold the whole chain.
+    NoFreeRun,
+}
+
+/// The result of `defragment()`.
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct Defragmented {
+    /// The paths of the files whose chains were moved, in the order found.
+    pub moved: Vec<String>,
+    /// The paths of the fragmented chains that were left in place, with the
+    /// reason each was skipped.
+    pub skipped: Vec<(String, Skip)>,
+}
+
+/// Moves the chain of every fragmented file on `vfat` into a single run of
+/// free clusters, when there is a run large enough. Returns the files that
+/// were moved and the fragmented chains that were skipped: those of
+/// directories and those that no single free run can hold.
+///
+/// The volume must not be in use while it is defragmented. Files and
+/// directories opened before the call keep the first cluster and cached
+/// extents of their old chain, which is freed, so they must be reopened. Each
+/// file is moved in steps, with every change flushed to the device before the
+/// next step:
+///
+///   1. The file's data is copied into the free run.
+///   2. The run is linked into a chain.
+///   3. The file's directory entry is pointed at the new chain.
+///   4. The old chain is freed.
+///
+/// An interruption before step 3 leaves the file intact, in its old chain; one
+/// after it leaves it intact in its new chain. At worst, the clusters of one
+/// of the chains are left allocated but unreferenced, or the FAT copies
+/// differ, both of which `check::check()` repairs.
+///
+/// # Errors
+///
+/// Returns the errors of `report()` and any error writing to the volume. The
+/// files moved before the error remain moved.
+pub fn defragment(vfat: &Shared<VFat>) -> io::Result<Defragmented> {
+    let mut result = Defragmented::default();
+    for found in walk(vfat)? {
+        if found.fragments() <= 1 {
+            continue;
+        }
+
+        let raw = match found.raw {
+            Some(ref raw) if !raw.regular.metadata().attributes.directory() => raw,
+            _ => {
+                result.skipped.push((found.path, Skip::Directory));
+                continue;
+            }
+        };
+
+        let mut vfat = vfat.borrow_mut();
+        let count = found.chain.len() as u32;
+        let run = vfat.free_runs()?.into_iter()
+            .filter(|run| run.1 >= count)
+            .min_by_key(|run| run.1);
+        let start = match run {
+            Some((start, _)) => start.number(),
+            None => {
+                result.skipped.push((found.path, Skip::NoFreeRun));
+                continue;
+            }
+        };
+
+        let mut data = vec![0u8; vfat.bytes_per_cluster()];
+        for (i, &cluster) in found.chain.iter().enumerate() {
+            vfat.read_cluster(cluster, 0, &mut data)?;
+            vfat.write_cluster(Cluster::from(start + i as u32), 0, &data)?;
+        }
+        vfat.flush()?;
+
+        for number in start..start + count {
+            let next = match number + 1 {
+                next if next < start + count => next,
+                _ => END_OF_CHAIN,
+            };
+            vfat.set_fat_entry(Cluster::from(number), next)?;
+        }
+        vfat.flush()?;
+
+        let mut entry = vfat.read_dir_entry(raw.location)?;
+        entry.set_cluster(Cluster::from(start));
+        vfat.write_dir_entry(raw.location, &entry)?;
+        vfat.flush()?;
+
+        for &cluster in &found.chain {
+            vfat.set_fat_entry(cluster, 0)?;
+        }
+        vfat.flush()?;
+
+        result.moved.push(found.path);
+    }
+
+    Ok(result)
+}
+
+/// Returns every file and directory reachable from the root directory of
+/// `vfat`, starting with the root directory itself, with its chain.
+fn walk(vfat: &Shared<VFat>) -> io::Result<Vec<Found>> {
+    let root = vfat.borrow().root_dir_cluster();
+    let mut found = Vec::new();
+    if root.is_data() {
+        let chain = vfat.borrow_mut().chain(root)?;
+        found.push(Found { path: "/".to_string(), raw: None, chain: chain });
+    }
+
+    let mut dirs = vec![(root, "/".to_string())];
+    while let Some((cluster, path)) = dirs.pop() {
+        for (child_path, raw) in raw_children(vfat, cluster, &path)? {
+            let first = raw.regular.cluster();
+            let chain = match first.number() {
+                0 => Vec::new(),
+                _ => vfat.borrow_mut().chain(first)?,
+            };
+
+            if raw.regular.metadata().attributes.directory() && first.is_data() {
+                dirs.push((first, child_path.clone()));
+            }
+
+            found.push(Found { path: child_path, raw: Some(raw), chain: chain });
+        }
+    }
+
+    Ok(found)
+}
@@ -0,0 +1,107 @@
+use std::{fmt, mem};
+
+use traits::BlockDevice;
+use exfat::Error;
+
+/// The file system name of every exFAT boot sector.
+const FS_NAME: &[u8; 8] = b"EXFAT   ";
+
+/// The exFAT main boot sector.
+#[repr(C, packed)]
+pub struct BootSector {
+    jump: [u8; 3],
+    fs_name: [u8; 8],
+    must_be_zero: [u8; 53],
+    pub partition_offset: u64,
+    pub volume_length: u64,
+    pub fat_offset: u32,
+    pub fat_length: u32,
+    pub cluster_heap_offset: u32,
+    pub cluster_count: u32,
+    pub root_dir_cluster: u32,
+    pub volume_serial: u32,
+    pub revision: u16,
+    pub volume_flags: u16,
+    pub bytes_per_sector_shift: u8,
+    pub sectors_per_cluster_shift: u8,
+    pub num_fats: u8,
+    pub drive_select: u8,
+    pub percent_in_use: u8,
+    reserved: [u8; 7],
+    boot_code: [u8; 390],
+    signature: [u8; 2],
+}
+
+impl BootSector {
+    /// Reads the exFAT boot sector from sector `sector` of device `device`.
+    ///
+    /// # Errors
+    ///
+    /// If the boot signature or file system name is invalid, or if the sector
+    /// and cluster sizes are out of the range allowed by the specification,
+    /// returns an error of `BadSignature`.
+    pub fn from<T: BlockDevice>(mut device: T, sector: u64) -> Result<BootSector, Error> {
+        let mut buf = [0u8; 512];
+        device.read_sector(sector, &mut buf)?;
+        if !BootSector::is_exfat(&buf) {
+            return Err(Error::BadSignature);
+        }
+
+        let boot: BootSector = unsafe { mem::transmute(buf) };
+        let bytes_shift = boot.bytes_per_sector_shift;
+        if bytes_shift < 9 || bytes_shift > 12
+            || boot.sectors_per_cluster_shift > 25 - bytes_shift
+            || boot.num_fats == 0 || boot.num_fats > 2 {
+            return Err(Error::BadSignature);
+        }
+
+        Ok(boot)
+    }
+
+    /// Returns `true` if `sector`, the first sector of a volume, carries the
+    /// exFAT boot signature and file system name.
+    pub fn is_exfat(sector: &[u8]) -> bool {
+        sector.len() >= 512
+            && &sector[3..11] == FS_NAME
+            && sector[510..512] == [0x55, 0xAA]
+    }
+
+    /// The size of a sector in bytes.
+    pub fn bytes_per_sector(&self) -> u64 {
+        1 << self.bytes_per_sector_shift
+    }
+
+    /// The number of sectors in a cluster.
+    pub fn sectors_per_cluster(&self) -> u64 {
+        1 << self.sectors_per_cluster_shift
+    }
+
+    /// The index of the FAT in use: `1` if the second FAT is active, `0`
+    /// otherwise.
+    pub fn active_fat(&self) -> u8 {
+        match self.num_fats {
+            2 => (self.volume_flags & 0x1) as u8,
+            _ => 0,
+        }
+    }
+}
+
+impl fmt::Debug for BootSector {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("BootSector")
+            .field("partition_offset", &{ self.partition_offset })
+            .field("volume_length", &{ self.volume_length })
+            .field("fat_offset", &{ self.fat_offset })
+            .field("fat_length", &{ self.fat_length })
+            .field("cluster_heap_offset", &{ self.cluster_heap_offset })
+            .field("cluster_count", &{ self.cluster_count })
+            .field("root_dir_cluster", &{ self.root_dir_cluster })
+            .field("volume_serial", &{ self.volume_serial })
+            .field("revision", &{ self.revision })
+            .field("volume_flags", &{ self.volume_flags })
+            .field("bytes_per_sector", &self.bytes_per_sector())
+            .field("sectors_per_cluster", &self.sectors_per_cluster())
+            .field("num_fats", &self.num_fats)
+            .finish()
+    }
+}
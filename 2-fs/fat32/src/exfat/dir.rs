@@ -0,0 +1,298 @@
+use std::ffi::OsStr;
+use std::io;
+use std::cmp::min;
+
+use traits;
+use vfat::Shared;
+use exfat::{ExFat, Entry, File, Metadata, Attributes, Timestamp, Chain};
+
+/// Marks the end of the directory; no entries follow.
+pub(crate) const END_OF_DIR: u8 = 0x00;
+/// The allocation bitmap critical primary entry.
+pub(crate) const ENTRY_BITMAP: u8 = 0x81;
+/// The up-case table critical primary entry.
+pub(crate) const ENTRY_UPCASE: u8 = 0x82;
+/// The volume label critical primary entry.
+pub(crate) const ENTRY_VOLUME_LABEL: u8 = 0x83;
+/// The file critical primary entry, which starts a file entry set.
+pub(crate) const ENTRY_FILE: u8 = 0x85;
+/// The stream extension critical secondary entry.
+pub(crate) const ENTRY_STREAM: u8 = 0xC0;
+/// The file name critical secondary entry.
+pub(crate) const ENTRY_NAME: u8 = 0xC1;
+
+/// Stream extension flag: the data is stored in consecutive clusters and the
+/// FAT is not consulted.
+const NO_FAT_CHAIN: u8 = 0x02;
+
+#[repr(C, packed)]
+#[derive(Copy, Clone)]
+pub struct ExFatFileDirEntry {
+    pub entry_type: u8,
+    pub secondary_count: u8,
+    pub set_checksum: u16,
+    pub attributes: u16,
+    reserved_1: u16,
+    pub created: u32,
+    pub modified: u32,
+    pub accessed: u32,
+    pub created_10ms: u8,
+    pub modified_10ms: u8,
+    pub created_utc_offset: u8,
+    pub modified_utc_offset: u8,
+    pub accessed_utc_offset: u8,
+    reserved_2: [u8; 7],
+}
+
+#[repr(C, packed)]
+#[derive(Copy, Clone)]
+pub struct ExFatStreamDirEntry {
+    pub entry_type: u8,
+    pub flags: u8,
+    reserved_1: u8,
+    pub name_length: u8,
+    pub name_hash: u16,
+    reserved_2: u16,
+    pub valid_data_length: u64,
+    reserved_3: u32,
+    pub first_cluster: u32,
+    pub data_length: u64,
+}
+
+#[repr(C, packed)]
+#[derive(Copy, Clone)]
+pub struct ExFatNameDirEntry {
+    pub entry_type: u8,
+    pub flags: u8,
+    pub name: [u16; 15],
+}
+
+#[repr(C, packed)]
+#[derive(Copy, Clone)]
+pub struct ExFatBitmapDirEntry {
+    pub entry_type: u8,
+    pub flags: u8,
+    reserved: [u8; 18],
+    pub first_cluster: u32,
+    pub data_length: u64,
+}
+
+#[repr(C, packed)]
+#[derive(Copy, Clone)]
+pub struct ExFatUpcaseDirEntry {
+    pub entry_type: u8,
+    reserved_1: [u8; 3],
+    pub checksum: u32,
+    reserved_2: [u8; 12],
+    pub first_cluster: u32,
+    pub data_length: u64,
+}
+
+#[repr(C, packed)]
+#[derive(Copy, Clone)]
+pub struct ExFatVolumeLabelDirEntry {
+    pub entry_type: u8,
+    pub character_count: u8,
+    pub label: [u16; 11],
+    reserved: [u8; 8],
+}
+
+#[repr(C)]
+#[derive(Copy, Clone)]
+pub union ExFatDirEntry {
+    pub raw: [u8; 32],
+    pub file: ExFatFileDirEntry,
+    pub stream: ExFatStreamDirEntry,
+    pub name: ExFatNameDirEntry,
+    pub bitmap: ExFatBitmapDirEntry,
+    pub upcase: ExFatUpcaseDirEntry,
+    pub volume_label: ExFatVolumeLabelDirEntry,
+}
+
+/// Computes the checksum of the entry set whose raw bytes are `set`. The
+/// checksum field itself, bytes 2 and 3 of the first entry, is skipped.
+pub(crate) fn entry_set_checksum(set: &[u8]) -> u16 {
+    set.iter()
+        .enumerate()
+        .filter(|&(i, _)| i != 2 && i != 3)
+        .fold(0u16, |sum, (_, &byte)| sum.rotate_right(1).wrapping_add(byte as u16))
+}
+
+#[derive(Debug)]
+pub struct Dir {
+    pub(crate) exfat: Shared<ExFat>,
+    pub(crate) chain: Chain,
+    /// The size of the directory in bytes. `None` for the root directory,
+    /// whose size is given by its FAT chain.
+    pub(crate) size: Option<u64>,
+    pub(crate) name: String,
+    pub(crate) metadata: Metadata,
+}
+
+/// An iterator over the file entry sets in a directory.
+pub struct EntryIter {
+    exfat: Shared<ExFat>,
+    entries: Vec<ExFatDirEntry>,
+    index: usize,
+}
+
+impl EntryIter {
+    /// Builds an `Entry` from the file entry `file`, its stream extension
+    /// `stream`, and its decoded name `name`.
+    fn entry(
+        &self,
+        file: &ExFatFileDirEntry,
+        stream: &ExFatStreamDirEntry,
+        name: String
+    ) -> Entry {
+        let metadata = Metadata {
+            attributes: Attributes::new(file.attributes),
+            created: Timestamp::new(file.created, file.created_10ms, file.created_utc_offset),
+            accessed: Timestamp::new(file.accessed, 0, file.accessed_utc_offset),
+            modified: Timestamp::new(file.modified, file.modified_10ms, file.modified_utc_offset),
+        };
+
+        let chain = Chain {
+            first: stream.first_cluster,
+            contiguous: stream.flags & NO_FAT_CHAIN != 0,
+        };
+
+        if metadata.attributes.directory() {
+            Entry::Dir(Dir {
+                exfat: self.exfat.clone(),
+                chain: chain,
+                size: Some(stream.data_length),
+                name: name,
+                metadata: metadata,
+            })
+        } else {
+            Entry::File(File::new(
+                self.exfat.clone(),
+                chain,
+                name,
+                metadata,
+                stream.data_length,
+                stream.valid_data_length,
+            ))
+        }
+    }
+}
+
+impl Iterator for EntryIter {
+    type Item = io::Result<Entry>;
+
+    /// Returns the next valid file entry set. Unused entries, critical primary
+    /// entries other than files, and entry sets that are truncated or whose
+    /// checksum does not match are skipped. The directory is read in full by
+    /// `entries()`, so no error is ever returned.
+    fn next(&mut self) -> Option<io::Result<Entry>> {
+        while self.index < self.entries.len() {
+            let index = self.index;
+            self.index += 1;
+
+            let raw = unsafe { self.entries[index].raw };
+            match raw[0] {
+                END_OF_DIR => {
+                    self.index = self.entries.len();
+                    return None;
+                }
+                ENTRY_FILE => {}
+                _ => continue,
+            }
+
+            let file = unsafe { self.entries[index].file };
+            let count = file.secondary_count as usize;
+            if count < 2 || index + count >= self.entries.len() {
+                continue;
+            }
+
+            let set = &self.entries[index..index + count + 1];
+            let bytes: Vec<u8> = set.iter().flat_map(|entry| unsafe { entry.raw }.to_vec()).collect();
+            if entry_set_checksum(&bytes) != file.set_checksum {
+                continue;
+            }
+
+            let stream = unsafe { set[1].stream };
+            if stream.entry_type != ENTRY_STREAM {
+                continue;
+            }
+
+            let name_length = stream.name_length as usize;
+            let mut units = Vec::with_capacity(name_length);
+            for entry in set[2..].iter() {
+                let name = unsafe { entry.name };
+                if name.entry_type != ENTRY_NAME || units.len() >= name_length {
+                    break;
+                }
+
+                let chars = name.name;
+                let take = min(name_length - units.len(), chars.len());
+                units.extend_from_slice(&chars[..take]);
+            }
+
+            if units.len() != name_length {
+                continue;
+            }
+
+            self.index = index + count + 1;
+            return Some(Ok(self.entry(&file, &stream, String::from_utf16_lossy(&units))));
+        }
+
+        None
+    }
+}
+
+impl Dir {
+    /// Returns the root directory of the volume `exfat`.
+    pub(crate) fn root(exfat: Shared<ExFat>) -> Dir {
+        let first = exfat.borrow().root_dir_cluster();
+        Dir {
+            exfat: exfat,
+            chain: Chain { first: first, contiguous: false },
+            size: None,
+            name: String::from("/"),
+            metadata: Metadata::default(),
+        }
+    }
+
+    /// Finds the entry named `name` in `self` and returns it. Comparison is
+    /// case-insensitive according to the volume's up-case table.
+    ///
+    /// # Errors
+    ///
+    /// If no entry with name `name` exists in `self`, an error of `NotFound` is
+    /// returned.
+    ///
+    /// If `name` contains invalid UTF-8 characters, an error of `InvalidInput`
+    /// is returned.
+    pub fn find<P: AsRef<OsStr>>(&self, name: P) -> io::Result<Entry> {
+        use traits::{Dir, Entry};
+
+        let name = name.as_ref().to_str().ok_or_else(|| {
+            io::Error::new(io::ErrorKind::InvalidInput, "name is not valid UTF-8")
+        })?;
+
+        let mut entries = self.entries()?;
+        let exfat = self.exfat.borrow();
+        let upcase = exfat.upcase_table();
+        let wanted = upcase.upcase_str(name);
+
+        entries.find(|entry| {
+            entry.as_ref().map_or(true, |entry| upcase.upcase_str(entry.name()) == wanted)
+        }).unwrap_or_else(|| Err(io::Error::new(io::ErrorKind::NotFound, "entry not found")))
+    }
+}
+
+impl traits::Dir for Dir {
+    type Entry = Entry;
+    type Iter = EntryIter;
+
+    fn entries(&self) -> io::Result<EntryIter> {
+        let entries = self.exfat.borrow_mut().read_dir_entries(self.chain, self.size)?;
+        Ok(EntryIter {
+            exfat: self.exfat.clone(),
+            entries: entries,
+            index: 0,
+        })
+    }
+}
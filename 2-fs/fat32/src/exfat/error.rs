@@ -0,0 +1,33 @@
+use std::io;
+
+use mbr;
+use gpt;
+
+#[derive(Debug)]
+pub enum Error {
+    Mbr(mbr::Error),
+    Gpt(gpt::Error),
+    Io(io::Error),
+    BadSignature,
+    /// The up-case table's contents do not match its recorded checksum.
+    BadChecksum,
+    NotFound
+}
+
+impl From<mbr::Error> for Error {
+    fn from(error: mbr::Error) -> Error {
+        Error::Mbr(error)
+    }
+}
+
+impl From<gpt::Error> for Error {
+    fn from(error: gpt::Error) -> Error {
+        Error::Gpt(error)
+    }
+}
+
+impl From<io::Error> for Error {
+    fn from(error: io::Error) -> Error {
+        Error::Io(error)
+    }
+}
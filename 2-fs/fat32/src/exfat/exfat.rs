@@ -0,0 +1,487 @@
+use std::io;
+use std::path::{Path, Component};
+use std::cmp::min;
+
+use mbr::MasterBootRecord;
+use gpt::GuidPartitionTable;
+use exfat::{BootSector, Dir, Entry, File, Error, UpcaseTable};
+use exfat::dir::{ExFatDirEntry, ENTRY_BITMAP, ENTRY_UPCASE, ENTRY_VOLUME_LABEL, END_OF_DIR};
+use exfat::upcase::table_checksum;
+use vfat::{Shared, CachedDevice, CacheStats};
+use partition::PartitionDevice;
+use traits::{FileSystem, BlockDevice};
+
+/// The FAT entry marking the end of a cluster chain.
+const END_OF_CHAIN: u32 = 0xFFFFFFFF;
+
+/// The clusters holding a stream of data: a file, a directory, or one of the
+/// volume's metadata structures.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub(crate) struct Chain {
+    /// The first cluster of the stream, or `0` if it has none.
+    pub first: u32,
+    /// Whether the clusters are consecutive and the FAT is not consulted.
+    pub contiguous: bool,
+}
+
+impl Chain {
+    /// The empty chain.
+    pub fn empty() -> Chain {
+        Chain { first: 0, contiguous: false }
+    }
+}
+
+#[derive(Debug)]
+pub struct ExFat {
+    device: CachedDevice,
+    bytes_per_sector: u64,
+    sectors_per_cluster: u64,
+    fat_start_sector: u64,
+    cluster_heap_start_sector: u64,
+    cluster_count: u32,
+    root_dir_cluster: u32,
+    volume_serial: u32,
+    volume_label: String,
+    bitmap: Chain,
+    bitmap_length: u64,
+    upcase: UpcaseTable,
+}
+
+impl ExFat {
+    pub fn from<T>(mut device: T) -> Result<Shared<ExFat>, Error>
+        where T: BlockDevice + 'static
+    {
+        let start = ExFat::volume_start(&mut device)?;
+        let boot = BootSector::from(&mut device, start)?;
+
+        let bytes_per_sector = boot.bytes_per_sector();
+        let physical_size = device.sector_size();
+        if bytes_per_sector < physical_size || bytes_per_sector % physical_size != 0 {
+            return Err(Error::BadSignature);
+        }
+
+        // Sectors are numbered from the start of the volume from here on.
+        let fat_start_sector = boot.fat_offset as u64
+            + boot.active_fat() as u64 * boot.fat_length as u64;
+        let fat_entries = boot.fat_length as u64 * bytes_per_sector / 4;
+        let cluster_count = min(boot.cluster_count as u64, fat_entries.saturating_sub(2));
+
+        let sectors = boot.volume_length * (bytes_per_sector / physical_size);
+        let device = PartitionDevice::with_sector_size(device, start, sectors, bytes_per_sector);
+
+        let mut exfat = ExFat {
+            device: CachedDevice::new(device),
+            bytes_per_sector: bytes_per_sector,
+            sectors_per_cluster: boot.sectors_per_cluster(),
+            fat_start_sector: fat_start_sector,
+            cluster_heap_start_sector: boot.cluster_heap_offset as u64,
+            cluster_count: cluster_count as u32,
+            root_dir_cluster: boot.root_dir_cluster,
+            volume_serial: boot.volume_serial,
+            volume_label: String::new(),
+            bitmap: Chain::empty(),
+            bitmap_length: 0,
+            upcase: UpcaseTable::from_bytes(&[]),
+        };
+
+        exfat.load_root_metadata()?;
+        Ok(Shared::new(exfat))
+    }
+
+    /// Returns the first sector of the exFAT volume on `device`.
+    ///
+    /// A device whose first sector is an exFAT boot sector has no partition
+    /// table and the volume starts at sector 0. Otherwise, the volume is the
+    /// first exFAT partition in the MBR or, for a protective MBR, in the GPT.
+    fn volume_start<T: BlockDevice>(device: &mut T) -> Result<u64, Error> {
+        let mut buf = [0u8; 512];
+        device.read_sector(0, &mut buf)?;
+        if BootSector::is_exfat(&buf) {
+            return Ok(0);
+        }
+
+        let mbr = MasterBootRecord::from(&mut *device)?;
+        if mbr.is_protective() {
+            let gpt = GuidPartitionTable::from(&mut *device)?;
+            let partition = gpt.first_exfat(device)?.ok_or(Error::NotFound)?;
+            return Ok(partition.first_lba);
+        }
+
+        let partition = mbr.first_exfat(device)?.ok_or(Error::NotFound)?;
+        Ok(partition.relative_sector as u64)
+    }
+
+    /// Locates the allocation bitmap, loads the up-case table, and reads the
+    /// volume label from the critical entries in the root directory.
+    ///
+    /// # Errors
+    ///
+    /// Returns `NotFound` if the allocation bitmap or up-case table entry is
+    /// missing and `BadChecksum` if the up-case table is corrupt.
+    fn load_root_metadata(&mut self) -> Result<(), Error> {
+        let root = Chain { first: self.root_dir_cluster, contiguous: false };
+        let entries = self.read_dir_entries(root, None)?;
+
+        let mut upcase = None;
+        for entry in entries.iter() {
+            let raw = unsafe { entry.raw };
+            match raw[0] {
+                END_OF_DIR => break,
+                ENTRY_BITMAP if self.bitmap.first == 0 => {
+                    let bitmap = unsafe { entry.bitmap };
+                    self.bitmap = Chain { first: bitmap.first_cluster, contiguous: false };
+                    self.bitmap_length = bitmap.data_length;
+                }
+                ENTRY_UPCASE if upcase.is_none() => {
+                    upcase = Some(unsafe { entry.upcase });
+                }
+                ENTRY_VOLUME_LABEL => {
+                    let label = unsafe { entry.volume_label };
+                    let length = min(label.character_count as usize, 11);
+                    let units = label.label;
+                    self.volume_label = String::from_utf16_lossy(&units[..length]);
+                }
+                _ => {}
+            }
+        }
+
+        let upcase = upcase.ok_or(Error::NotFound)?;
+        if self.bitmap.first == 0 {
+            return Err(Error::NotFound);
+        }
+
+        let chain = Chain { first: upcase.first_cluster, contiguous: false };
+        let mut data = Vec::new();
+        self.read_chain(chain, Some(upcase.data_length), &mut data)?;
+        if table_checksum(&data) != upcase.checksum {
+            return Err(Error::BadChecksum);
+        }
+
+        self.upcase = UpcaseTable::from_bytes(&data);
+        Ok(())
+    }
+
+    /// The volume serial number.
+    pub fn volume_serial(&self) -> u32 {
+        self.volume_serial
+    }
+
+    /// The volume label. Empty if the volume has none.
+    pub fn volume_label(&self) -> &str {
+        &self.volume_label
+    }
+
+    /// The number of clusters in the cluster heap.
+    pub fn cluster_count(&self) -> u32 {
+        self.cluster_count
+    }
+
+    /// The sector cache's hit, miss, eviction, and write-back counters.
+    pub fn cache_stats(&self) -> CacheStats {
+        self.device.stats()
+    }
+
+    /// Sets the number of sectors the sector cache may hold, evicting sectors
+    /// if it currently holds more.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `capacity` is zero.
+    pub fn set_cache_capacity(&mut self, capacity: usize) -> io::Result<()> {
+        self.device.set_capacity(capacity)
+    }
+
+    /// Sets the number of sectors read ahead on sequential access. Zero
+    /// disables read-ahead.
+    pub fn set_read_ahead(&mut self, sectors: usize) {
+        self.device.set_read_ahead(sectors)
+    }
+
+    /// The volume's up-case table.
+    pub(crate) fn upcase_table(&self) -> &UpcaseTable {
+        &self.upcase
+    }
+
+    /// The first cluster of the root directory.
+    pub(crate) fn root_dir_cluster(&self) -> u32 {
+        self.root_dir_cluster
+    }
+
+    /// The size of a cluster in bytes.
+    pub(crate) fn bytes_per_cluster(&self) -> usize {
+        (self.bytes_per_sector * self.sectors_per_cluster) as usize
+    }
+
+    /// Returns an `InvalidData` error unless `cluster` is in the cluster heap.
+    fn check_cluster(&self, cluster: u32) -> io::Result<()> {
+        match cluster >= 2 && cluster - 2 < self.cluster_count {
+            true => Ok(()),
+            false => Err(io::Error::new(io::ErrorKind::InvalidData,
+                                        "cluster is outside of the cluster heap")),
+        }
+    }
+
+    /// Reads from `offset` bytes into the cluster `cluster` into `buf`. Reading
+    /// stops at the end of the cluster or when `buf` is full, whichever comes
+    /// first. Returns the number of bytes read.
+    pub(crate) fn read_cluster(
+        &mut self,
+        cluster: u32,
+        offset: usize,
+        buf: &mut [u8]
+    ) -> io::Result<usize> {
+        self.check_cluster(cluster)?;
+
+        let sector_size = self.bytes_per_sector as usize;
+        let amount = min(buf.len(), self.bytes_per_cluster().saturating_sub(offset));
+        let start_sector = self.cluster_heap_start_sector
+            + (cluster - 2) as u64 * self.sectors_per_cluster;
+
+        let mut read = 0;
+        while read < amount {
+            let position = offset + read;
+            let sector = self.device.get(start_sector + (position / sector_size) as u64)?;
+            let sector_offset = position % sector_size;
+            let n = min(amount - read, sector_size - sector_offset);
+            buf[read..read + n].copy_from_slice(&sector[sector_offset..sector_offset + n]);
+            read += n;
+        }
+
+        Ok(read)
+    }
+
+    /// Returns the cluster following `cluster` in its FAT chain, or `None` if
+    /// `cluster` is the last cluster in the chain.
+    ///
+    /// # Errors
+    ///
+    /// Returns an `InvalidData` error if the FAT entry for `cluster` is
+    /// neither an end-of-chain marker nor a cluster in the heap.
+    pub(crate) fn next_cluster(&mut self, cluster: u32) -> io::Result<Option<u32>> {
+        self.check_cluster(cluster)?;
+
+        let offset = cluster as u64 * 4;
+        let sector = self.fat_start_sector + offset / self.bytes_per_sector;
+        let index = (offset % self.bytes_per_sector) as usize;
+        let data = self.device.get(sector)?;
+        let next = data[index] as u32
+            | (data[index + 1] as u32) << 8
+            | (data[index + 2] as u32) << 16
+            | (data[index + 3] as u32) << 24;
+
+        match next {
+            END_OF_CHAIN => Ok(None),
+            next => self.check_cluster(next).map(|_| Some(next)),
+        }
+    }
+
+    /// Returns the `index`th cluster of `chain`, or `None` if the chain ends
+    /// before it. `cursor` is a previously visited `(index, cluster)` pair from
+    /// which the walk may resume.
+    pub(crate) fn cluster_at(
+        &mut self,
+        chain: Chain,
+        index: u64,
+        cursor: Option<(u64, u32)>
+    ) -> io::Result<Option<u32>> {
+        if chain.first == 0 {
+            return Ok(None);
+        }
+
+        if chain.contiguous {
+            let cluster = chain.first as u64 + index;
+            return match cluster <= u32::max_value() as u64 {
+                true => self.check_cluster(cluster as u32).map(|_| Some(cluster as u32)),
+                false => Ok(None),
+            };
+        }
+
+        let (mut i, mut cluster) = match cursor {
+            Some((i, cluster)) if i <= index => (i, cluster),
+            _ => (0, chain.first),
+        };
+
+        while i < index {
+            cluster = match self.next_cluster(cluster)? {
+                Some(next) => next,
+                None => return Ok(None),
+            };
+
+            i += 1;
+        }
+
+        Ok(Some(cluster))
+    }
+
+    /// Reads the data of `chain` into `buf`. If `length` is `Some`, at most
+    /// that many bytes are read; otherwise the whole FAT chain is read. Returns
+    /// the number of bytes read.
+    ///
+    /// # Errors
+    ///
+    /// Returns an `InvalidData` error if a FAT chain is longer than the number
+    /// of clusters in the volume.
+    pub(crate) fn read_chain(
+        &mut self,
+        chain: Chain,
+        length: Option<u64>,
+        buf: &mut Vec<u8>
+    ) -> io::Result<usize> {
+        let cluster_size = self.bytes_per_cluster();
+        let limit = length.unwrap_or(u64::max_value());
+        let start = buf.len();
+
+        let mut cluster = match chain.first {
+            0 => None,
+            first => Some(first),
+        };
+
+        let mut visited = 0u64;
+        while let Some(current) = cluster {
+            let read = (buf.len() - start) as u64;
+            if read >= limit {
+                break;
+            }
+
+            visited += 1;
+            if visited > self.cluster_count as u64 {
+                return Err(io::Error::new(io::ErrorKind::InvalidData,
+                                          "cluster chain is longer than the volume"));
+            }
+
+            let amount = min(cluster_size as u64, limit - read) as usize;
+            let end = buf.len();
+            buf.resize(end + amount, 0);
+            self.read_cluster(current, 0, &mut buf[end..])?;
+
+            cluster = match chain.contiguous {
+                true => Some(current + 1),
+                false => self.next_cluster(current)?,
+            };
+        }
+
+        Ok(buf.len() - start)
+    }
+
+    /// Reads the raw directory entries of the directory stored in `chain`.
+    pub(crate) fn read_dir_entries(
+        &mut self,
+        chain: Chain,
+        length: Option<u64>
+    ) -> io::Result<Vec<ExFatDirEntry>> {
+        use std::mem;
+        use util::VecExt;
+
+        let mut data = Vec::new();
+        self.read_chain(chain, length, &mut data)?;
+
+        let entry_size = mem::size_of::<ExFatDirEntry>();
+        let whole_entries = data.len() - data.len() % entry_size;
+        data.truncate(whole_entries);
+        data.shrink_to_fit();
+        Ok(unsafe { data.cast() })
+    }
+
+    /// Returns `true` if `cluster` is marked as in use in the allocation
+    /// bitmap.
+    pub fn is_allocated(&mut self, cluster: u32) -> io::Result<bool> {
+        self.check_cluster(cluster)?;
+
+        let bit = (cluster - 2) as u64;
+        let cluster_size = self.bytes_per_cluster() as u64;
+        let byte = bit / 8;
+        let bitmap_cluster = self.cluster_at(self.bitmap, byte / cluster_size, None)?
+            .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData,
+                                          "allocation bitmap is too short"))?;
+
+        let mut value = [0u8; 1];
+        self.read_cluster(bitmap_cluster, (byte % cluster_size) as usize, &mut value)?;
+        Ok(value[0] & (1 << (bit % 8)) != 0)
+    }
+
+    /// The number of clusters marked as free in the allocation bitmap.
+    pub fn free_clusters(&mut self) -> io::Result<u32> {
+        let mut bitmap = Vec::new();
+        let (chain, length) = (self.bitmap, self.bitmap_length);
+        self.read_chain(chain, Some(length), &mut bitmap)?;
+
+        let count = self.cluster_count as usize;
+        let used: usize = bitmap.iter()
+            .take(count / 8)
+            .map(|byte| byte.count_ones() as usize)
+            .sum();
+        let tail = match (count % 8, bitmap.get(count / 8)) {
+            (0, _) | (_, None) => 0,
+            (bits, Some(&byte)) => (byte & ((1u8 << bits) - 1)).count_ones() as usize,
+        };
+
+        Ok((count - used - tail) as u32)
+    }
+}
+
+impl<'a> FileSystem for &'a Shared<ExFat> {
+    type File = File;
+    type Dir = Dir;
+    type Entry = Entry;
+
+    fn open<P: AsRef<Path>>(self, path: P) -> io::Result<Self::Entry> {
+        use traits::Entry;
+
+        let path = path.as_ref();
+        if !path.is_absolute() {
+            return Err(io::Error::new(io::ErrorKind::InvalidInput,
+                                      "path is not absolute"));
+        }
+
+        let mut names = Vec::new();
+        for component in path.components() {
+            match component {
+                Component::Normal(name) => names.push(name),
+                Component::ParentDir => { names.pop(); }
+                _ => continue,
+            }
+        }
+
+        let mut entry = ::exfat::Entry::Dir(Dir::root(self.clone()));
+        for (i, name) in names.iter().enumerate() {
+            let dir = entry.into_dir().ok_or_else(|| {
+                io::Error::new(io::ErrorKind::InvalidInput, "not a directory")
+            })?;
+
+            entry = match dir.find(name) {
+                Err(ref e) if e.kind() == io::ErrorKind::NotFound && i + 1 < names.len() => {
+                    return Err(io::Error::new(io::ErrorKind::InvalidInput,
+                                              "parent directory not found"));
+                }
+                result => result?,
+            };
+        }
+
+        Ok(entry)
+    }
+
+    fn create_file<P: AsRef<Path>>(self, _path: P) -> io::Result<Self::File> {
+        Err(read_only())
+    }
+
+    fn create_dir<P>(self, _path: P, _parents: bool) -> io::Result<Self::Dir>
+        where P: AsRef<Path>
+    {
+        Err(read_only())
+    }
+
+    fn rename<P, Q>(self, _from: P, _to: Q) -> io::Result<()>
+        where P: AsRef<Path>, Q: AsRef<Path>
+    {
+        Err(read_only())
+    }
+
+    fn remove<P: AsRef<Path>>(self, _path: P, _children: bool) -> io::Result<()> {
+        Err(read_only())
+    }
+}
+
+/// The error returned by every operation that would modify the volume.
+pub(crate) fn read_only() -> io::Error {
+    io::Error::new(io::ErrorKind::PermissionDenied, "exFAT volumes are read-only")
+}
@@ -0,0 +1,141 @@
+use std::cmp::min;
+use std::io::{self, SeekFrom};
+
+use traits;
+use vfat::Shared;
+use exfat::{ExFat, Metadata, Chain};
+use exfat::exfat::read_only;
+
+#[derive(Debug)]
+pub struct File {
+    pub(crate) exfat: Shared<ExFat>,
+    chain: Chain,
+    pub(crate) name: String,
+    pub(crate) metadata: Metadata,
+    size: u64,
+    /// The number of bytes that have been written. Reads between this and
+    /// `size` return zeroes.
+    valid_size: u64,
+    pos: u64,
+    /// The index in the chain and the cluster of the last cluster accessed.
+    cursor: Option<(u64, u32)>,
+}
+
+impl File {
+    pub(crate) fn new(
+        exfat: Shared<ExFat>,
+        chain: Chain,
+        name: String,
+        metadata: Metadata,
+        size: u64,
+        valid_size: u64
+    ) -> File {
+        File {
+            exfat: exfat,
+            chain: chain,
+            name: name,
+            metadata: metadata,
+            size: size,
+            valid_size: min(valid_size, size),
+            pos: 0,
+            cursor: None,
+        }
+    }
+}
+
+impl traits::File for File {
+    /// exFAT volumes are mounted read-only, so there is never anything to
+    /// write back.
+    fn sync(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+
+    fn size(&self) -> u64 {
+        self.size
+    }
+
+    fn set_len(&mut self, _size: u64) -> io::Result<()> {
+        Err(read_only())
+    }
+}
+
+impl io::Read for File {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let amount = min(buf.len() as u64, self.size - self.pos) as usize;
+        if amount == 0 {
+            return Ok(0);
+        }
+
+        let exfat = self.exfat.clone();
+        let mut exfat = exfat.borrow_mut();
+        let cluster_size = exfat.bytes_per_cluster() as u64;
+
+        let mut read = 0;
+        while read < amount {
+            if self.pos >= self.valid_size {
+                for byte in buf[read..amount].iter_mut() {
+                    *byte = 0;
+                }
+
+                self.pos += (amount - read) as u64;
+                read = amount;
+                break;
+            }
+
+            let index = self.pos / cluster_size;
+            let cluster = exfat.cluster_at(self.chain, index, self.cursor)?
+                .ok_or_else(|| io::Error::new(io::ErrorKind::UnexpectedEof,
+                                              "cluster chain is shorter than file"))?;
+            self.cursor = Some((index, cluster));
+
+            let end = read + min((amount - read) as u64, self.valid_size - self.pos) as usize;
+            let offset = (self.pos % cluster_size) as usize;
+            let n = exfat.read_cluster(cluster, offset, &mut buf[read..end])?;
+            read += n;
+            self.pos += n as u64;
+        }
+
+        Ok(read)
+    }
+}
+
+impl io::Write for File {
+    fn write(&mut self, _buf: &[u8]) -> io::Result<usize> {
+        Err(read_only())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+impl io::Seek for File {
+    /// Seek to offset `pos` in the file.
+    ///
+    /// A seek to the end of the file is allowed. A seek _beyond_ the end of the
+    /// file returns an `InvalidInput` error.
+    ///
+    /// If the seek operation completes successfully, this method returns the
+    /// new position from the start of the stream. That position can be used
+    /// later with SeekFrom::Start.
+    ///
+    /// # Errors
+    ///
+    /// Seeking before the start of a file or beyond the end of the file results
+    /// in an `InvalidInput` error.
+    fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+        let new_pos = match pos {
+            SeekFrom::Start(offset) => offset as i64,
+            SeekFrom::End(offset) => self.size as i64 + offset,
+            SeekFrom::Current(offset) => self.pos as i64 + offset,
+        };
+
+        if new_pos < 0 || new_pos as u64 > self.size {
+            return Err(io::Error::new(io::ErrorKind::InvalidInput,
+                                      "seek outside of file bounds"));
+        }
+
+        self.pos = new_pos as u64;
+        Ok(self.pos)
+    }
+}
@@ -0,0 +1,154 @@
+use std::fmt;
+
+use traits;
+
+/// File attributes as stored in an exFAT file directory entry.
+#[derive(Default, Copy, Clone, Debug, PartialEq, Eq)]
+pub struct Attributes(u16);
+
+impl Attributes {
+    pub const READ_ONLY: u16 = 0x01;
+    pub const HIDDEN: u16 = 0x02;
+    pub const SYSTEM: u16 = 0x04;
+    pub const DIRECTORY: u16 = 0x10;
+    pub const ARCHIVE: u16 = 0x20;
+
+    /// Creates a new set of attributes from the raw bits `bits`.
+    pub fn new(bits: u16) -> Attributes {
+        Attributes(bits)
+    }
+
+    /// The raw attribute bits.
+    pub fn bits(&self) -> u16 {
+        self.0
+    }
+
+    /// Returns `true` if every bit in `bits` is set.
+    pub fn contains(&self, bits: u16) -> bool {
+        self.0 & bits == bits
+    }
+
+    /// Returns `true` if the read-only bit is set.
+    pub fn read_only(&self) -> bool {
+        self.contains(Self::READ_ONLY)
+    }
+
+    /// Returns `true` if the hidden bit is set.
+    pub fn hidden(&self) -> bool {
+        self.contains(Self::HIDDEN)
+    }
+
+    /// Returns `true` if the directory bit is set.
+    pub fn directory(&self) -> bool {
+        self.contains(Self::DIRECTORY)
+    }
+}
+
+/// An exFAT timestamp: a FAT-style packed date and time, refined by a 10ms
+/// increment and qualified by an optional UTC offset.
+#[derive(Default, Copy, Clone, Debug, PartialEq, Eq)]
+pub struct Timestamp {
+    raw: u32,
+    increment_10ms: u8,
+    utc_offset: u8,
+}
+
+impl Timestamp {
+    /// Creates a timestamp from the raw packed date and time `raw`, the 10ms
+    /// increment `increment_10ms`, and the raw UTC offset field `utc_offset`.
+    pub(crate) fn new(raw: u32, increment_10ms: u8, utc_offset: u8) -> Timestamp {
+        Timestamp {
+            raw: raw,
+            increment_10ms: increment_10ms,
+            utc_offset: utc_offset,
+        }
+    }
+
+    /// The offset from UTC in minutes, or `None` if the timestamp is in an
+    /// unspecified local time.
+    pub fn utc_offset_minutes(&self) -> Option<i16> {
+        match self.utc_offset & 0x80 {
+            0 => None,
+            _ => Some((((self.utc_offset << 1) as i8) >> 1) as i16 * 15),
+        }
+    }
+}
+
+/// Metadata for an exFAT directory entry.
+#[derive(Default, Debug, Clone)]
+pub struct Metadata {
+    pub attributes: Attributes,
+    pub created: Timestamp,
+    pub accessed: Timestamp,
+    pub modified: Timestamp,
+}
+
+impl traits::Timestamp for Timestamp {
+    fn year(&self) -> usize {
+        (self.raw >> 25) as usize + 1980
+    }
+
+    fn month(&self) -> u8 {
+        ((self.raw >> 21) & 0b1111) as u8
+    }
+
+    fn day(&self) -> u8 {
+        ((self.raw >> 16) & 0b11111) as u8
+    }
+
+    fn hour(&self) -> u8 {
+        ((self.raw >> 11) & 0b11111) as u8
+    }
+
+    fn minute(&self) -> u8 {
+        ((self.raw >> 5) & 0b111111) as u8
+    }
+
+    fn second(&self) -> u8 {
+        (self.raw & 0b11111) as u8 * 2 + self.increment_10ms / 100
+    }
+}
+
+impl traits::Metadata for Metadata {
+    type Timestamp = Timestamp;
+
+    fn read_only(&self) -> bool {
+        self.attributes.read_only()
+    }
+
+    fn hidden(&self) -> bool {
+        self.attributes.hidden()
+    }
+
+    fn created(&self) -> Self::Timestamp {
+        self.created
+    }
+
+    fn accessed(&self) -> Self::Timestamp {
+        self.accessed
+    }
+
+    fn modified(&self) -> Self::Timestamp {
+        self.modified
+    }
+}
+
+impl fmt::Display for Timestamp {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        use traits::Timestamp;
+        write!(f, "{:02}/{:02}/{} {:02}:{:02}:{:02}",
+               self.month(), self.day(), self.year(),
+               self.hour(), self.minute(), self.second())
+    }
+}
+
+impl fmt::Display for Metadata {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let flag = |set: bool, c: char| if set { c } else { '-' };
+        write!(f, "{}{}{} {} {}",
+               flag(self.attributes.directory(), 'd'),
+               flag(self.attributes.read_only(), 'r'),
+               flag(self.attributes.hidden(), 'h'),
+               self.created, self.modified)
+    }
+}
@@ -0,0 +1,63 @@
+use std::fmt;
+
+/// The volume's up-case table, mapping each UTF-16 code unit to its upper
+/// case form. File names are compared case-insensitively through this table.
+#[derive(Clone)]
+pub struct UpcaseTable {
+    map: Vec<u16>,
+}
+
+impl UpcaseTable {
+    /// Decodes the up-case table stored in `data`. Both the compressed form,
+    /// in which `0xFFFF` followed by a count denotes a run of identity
+    /// mappings, and the uncompressed form are accepted.
+    pub fn from_bytes(data: &[u8]) -> UpcaseTable {
+        let units: Vec<u16> = data.chunks(2)
+            .filter(|chunk| chunk.len() == 2)
+            .map(|chunk| chunk[0] as u16 | (chunk[1] as u16) << 8)
+            .collect();
+
+        let mut map = Vec::with_capacity(0x10000);
+        let mut i = 0;
+        while i < units.len() && map.len() < 0x10000 {
+            if units[i] == 0xFFFF && i + 1 < units.len() {
+                for _ in 0..units[i + 1] {
+                    let unit = map.len() as u16;
+                    map.push(unit);
+                }
+                i += 2;
+            } else {
+                map.push(units[i]);
+                i += 1;
+            }
+        }
+
+        map.truncate(0x10000);
+        UpcaseTable { map: map }
+    }
+
+    /// Returns the upper case form of `unit`. Units beyond the end of the
+    /// table map to themselves.
+    pub fn upcase(&self, unit: u16) -> u16 {
+        self.map.get(unit as usize).cloned().unwrap_or(unit)
+    }
+
+    /// Returns the upper case UTF-16 encoding of `name`.
+    pub fn upcase_str(&self, name: &str) -> Vec<u16> {
+        name.encode_utf16().map(|unit| self.upcase(unit)).collect()
+    }
+}
+
+impl fmt::Debug for UpcaseTable {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("UpcaseTable")
+            .field("len", &self.map.len())
+            .finish()
+    }
+}
+
+/// Computes the checksum of the up-case table `data` as recorded in its
+/// directory entry.
+pub(crate) fn table_checksum(data: &[u8]) -> u32 {
+    data.iter().fold(0u32, |sum, &byte| sum.rotate_right(1).wrapping_add(byte as u32))
+}
@@ -0,0 +1,296 @@
+use std::io;
+use std::cmp::{min, max};
+
+use traits::BlockDevice;
+use vfat::FsInfo;
+
+/// The sector where the partition created by `format()` begins, aligning the
+/// volume to 1 MiB on devices with 512-byte sectors.
+const PARTITION_START: u64 = 2048;
+/// The number of reserved sectors before the first FAT.
+const RESERVED_SECTORS: u64 = 32;
+/// The sector, relative to the volume, of the FSInfo structure.
+const FSINFO_SECTOR: u64 = 1;
+/// The sector, relative to the volume, of the backup boot sector. The backup
+/// FSInfo structure follows it.
+const BACKUP_BOOT_SECTOR: u64 = 6;
+/// The number of copies of the FAT.
+const NUM_FATS: u64 = 2;
+/// The first cluster of the root directory.
+const ROOT_CLUSTER: u32 = 2;
+/// The media descriptor of a fixed disk.
+const MEDIA_DESCRIPTOR: u8 = 0xF8;
+/// The largest number of clusters a FAT32 volume may have.
+const MAX_CLUSTERS: u64 = 0x0FFFFFF5;
+/// The largest cluster size, in bytes, that is widely supported.
+const MAX_CLUSTER_SIZE: u32 = 32 * 1024;
+/// The number of sectors written at once when zeroing a region.
+const ZERO_CHUNK_SECTORS: u64 = 64;
+
+/// Options for `format()`.
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct FormatOptions {
+    /// The size of a cluster in bytes. It must be a power of two that is at
+    /// least the device's sector size and at most 32 KiB. If `None`, the size
+    /// is chosen from the size of the volume as Windows does: 512 bytes up to
+    /// 260 MB, growing to 32 KiB for volumes over 32 GB.
+    pub cluster_size: Option<u32>,
+    /// The volume label of at most 11 ASCII characters. Lowercase letters are
+    /// stored in uppercase. If `None`, the label is `NO NAME`.
+    pub volume_label: Option<String>,
+    /// The volume ID, usually derived from the time of formatting.
+    pub volume_id: u32,
+    /// If `true`, an MBR describing a single FAT32 partition that starts at
+    /// sector 2048 and spans the rest of the device is written, and the volume
+    /// is placed in that partition. Otherwise the volume starts at sector 0.
+    pub partition: bool,
+}
+
+/// The layout of a volume being formatted, in sectors relative to the start
+/// of the volume.
+struct Layout {
+    sector_size: u64,
+    start: u64,
+    sectors: u64,
+    sectors_per_cluster: u64,
+    sectors_per_fat: u64,
+    cluster_count: u64,
+}
+
+impl Layout {
+    /// The first sector of the data region.
+    fn data_start(&self) -> u64 {
+        RESERVED_SECTORS + NUM_FATS * self.sectors_per_fat
+    }
+}
+
+/// Formats the first `sectors` sectors of `device` as an empty FAT32 volume
+/// with two FATs, an FSInfo structure, a backup boot sector, and an empty root
+/// directory, optionally inside of a partition described by a new MBR. The
+/// volume can then be mounted with `VFat::from()`.
+///
+/// Volumes with fewer than 65525 clusters are formatted as FAT32 as well. Such
+/// volumes mount with this crate and Linux, but not on every system.
+///
+/// # Errors
+///
+/// Returns an error of `InvalidInput` if the device's sector size is not a
+/// power of two between 512 and 4096 bytes, if an option is invalid, or if
+/// `sectors` is too small or too large to hold a FAT32 volume with the
+/// requested cluster size. Returns any error that occurs while writing to
+/// `device`.
+pub fn format<T: BlockDevice>(
+    mut device: T,
+    sectors: u64,
+    options: &FormatOptions
+) -> io::Result<()> {
+    let label = volume_label(options.volume_label.as_deref())?;
+    let layout = layout(device.sector_size(), sectors, options)?;
+
+    if options.partition {
+        device.write_sector(0, &mbr(&layout, options.volume_id))?;
+    }
+
+    let boot = boot_sector(&layout, options.volume_id, &label);
+    let fsinfo = fsinfo_sector(&layout);
+    zero_sectors(&mut device, layout.start, RESERVED_SECTORS)?;
+    for &base in &[0, BACKUP_BOOT_SECTOR] {
+        device.write_sector(layout.start + base, &boot)?;
+        device.write_sector(layout.start + base + FSINFO_SECTOR, &fsinfo)?;
+    }
+
+    let mut fat_head = vec![0u8; layout.sector_size as usize];
+    for (i, &entry) in [0x0FFFFF00 | MEDIA_DESCRIPTOR as u32, 0x0FFFFFFF, 0x0FFFFFFF]
+        .iter().enumerate() {
+        put_u32(&mut fat_head, i * 4, entry);
+    }
+
+    for fat in 0..NUM_FATS {
+        let fat_start = layout.start + RESERVED_SECTORS + fat * layout.sectors_per_fat;
+        zero_sectors(&mut device, fat_start, layout.sectors_per_fat)?;
+        device.write_sector(fat_start, &fat_head)?;
+    }
+
+    let root_start = layout.start + layout.data_start();
+    zero_sectors(&mut device, root_start, layout.sectors_per_cluster)?;
+    if options.volume_label.is_some() {
+        let mut root = vec![0u8; layout.sector_size as usize];
+        root[..11].copy_from_slice(&label);
+        root[11] = 0x08;
+        device.write_sector(root_start, &root)?;
+    }
+
+    Ok(())
+}
+
+/// Returns the on-disk volume label for `label`.
+fn volume_label(label: Option<&str>) -> io::Result<[u8; 11]> {
+    let label = label.unwrap_or("NO NAME");
+    let invalid = label.len() > 11 || label.bytes().any(|b| {
+        b < 0x20 || b >= 0x7F || b"\"*+,./:;<=>?[\\]|".contains(&b)
+    });
+
+    if invalid {
+        return Err(io::Error::new(io::ErrorKind::InvalidInput, "invalid volume label"));
+    }
+
+    let mut raw = [b' '; 11];
+    raw[..label.len()].copy_from_slice(label.to_ascii_uppercase().as_bytes());
+    Ok(raw)
+}
+
+/// Computes the layout of a volume in the first `sectors` sectors of a device
+/// with sectors of `sector_size` bytes.
+fn layout(sector_size: u64, sectors: u64, options: &FormatOptions) -> io::Result<Layout> {
+    let invalid = |msg| Err(io::Error::new(io::ErrorKind::InvalidInput, msg));
+    if !sector_size.is_power_of_two() || sector_size < 512 || sector_size > 4096 {
+        return invalid("unsupported sector size");
+    }
+
+    let start = match options.partition {
+        true => PARTITION_START,
+        false => 0,
+    };
+
+    if sectors <= start || sectors - start > ::std::u32::MAX as u64 {
+        return invalid("device size is out of range");
+    }
+
+    let volume_sectors = sectors - start;
+    let cluster_size = match options.cluster_size {
+        Some(size) => size as u64,
+        None => max(default_cluster_size(volume_sectors * sector_size), sector_size),
+    };
+
+    if !cluster_size.is_power_of_two() || cluster_size < sector_size
+        || cluster_size > MAX_CLUSTER_SIZE as u64
+    {
+        return invalid("invalid cluster size");
+    }
+
+    // Each FAT must hold an entry for every cluster plus the two reserved
+    // entries, but the FATs themselves take space away from the clusters.
+    let sectors_per_cluster = cluster_size / sector_size;
+    let entries_per_sector = sector_size / 4;
+    let mut sectors_per_fat = 1;
+    let cluster_count = loop {
+        let metadata = RESERVED_SECTORS + NUM_FATS * sectors_per_fat + sectors_per_cluster;
+        if volume_sectors < metadata {
+            return invalid("device is too small");
+        }
+
+        let clusters = (volume_sectors - RESERVED_SECTORS - NUM_FATS * sectors_per_fat)
+            / sectors_per_cluster;
+        let needed = (clusters + 2 + entries_per_sector - 1) / entries_per_sector;
+        if needed <= sectors_per_fat {
+            break clusters;
+        }
+
+        sectors_per_fat = needed;
+    };
+
+    if cluster_count > MAX_CLUSTERS {
+        return invalid("too many clusters; use a larger cluster size");
+    }
+
+    Ok(Layout {
+        sector_size: sector_size,
+        start: start,
+        sectors: volume_sectors,
+        sectors_per_cluster: sectors_per_cluster,
+        sectors_per_fat: sectors_per_fat,
+        cluster_count: cluster_count,
+    })
+}
+
+/// The cluster size, in bytes, Windows uses for a FAT32 volume of `bytes`
+/// bytes.
+fn default_cluster_size(bytes: u64) -> u64 {
+    const MB: u64 = 1000 * 1000;
+    const GB: u64 = 1024 * 1024 * 1024;
+    match bytes {
+        n if n <= 260 * MB => 512,
+        n if n <= 8 * GB => 4096,
+        n if n <= 16 * GB => 8192,
+        n if n <= 32 * GB => 16384,
+        _ => MAX_CLUSTER_SIZE as u64,
+    }
+}
+
+/// Builds an MBR with a single FAT32 (LBA) partition holding the volume.
+fn mbr(layout: &Layout, disk_id: u32) -> Vec<u8> {
+    let mut sector = vec![0u8; layout.sector_size as usize];
+    put_u32(&mut sector, 440, disk_id);
+
+    // CHS addresses are unused by LBA partitions; 1023/254/63 marks them so.
+    let entry = &mut sector[446..462];
+    entry[1..4].copy_from_slice(&[0xFE, 0xFF, 0xFF]);
+    entry[4] = 0x0C;
+    entry[5..8].copy_from_slice(&[0xFE, 0xFF, 0xFF]);
+    put_u32(entry, 8, layout.start as u32);
+    put_u32(entry, 12, layout.sectors as u32);
+
+    sector[510..512].copy_from_slice(&[0x55, 0xAA]);
+    sector
+}
+
+/// Builds the boot sector holding the FAT32 extended BIOS parameter block.
+fn boot_sector(layout: &Layout, volume_id: u32, label: &[u8; 11]) -> Vec<u8> {
+    let mut sector = vec![0u8; layout.sector_size as usize];
+    sector[..3].copy_from_slice(&[0xEB, 0x58, 0x90]);
+    sector[3..11].copy_from_slice(b"MSWIN4.1");
+    put_u16(&mut sector, 11, layout.sector_size as u16);
+    sector[13] = layout.sectors_per_cluster as u8;
+    put_u16(&mut sector, 14, RESERVED_SECTORS as u16);
+    sector[16] = NUM_FATS as u8;
+    sector[21] = MEDIA_DESCRIPTOR;
+    put_u16(&mut sector, 24, 63);
+    put_u16(&mut sector, 26, 255);
+    put_u32(&mut sector, 28, layout.start as u32);
+    put_u32(&mut sector, 32, layout.sectors as u32);
+
+    put_u32(&mut sector, 36, layout.sectors_per_fat as u32);
+    put_u32(&mut sector, 44, ROOT_CLUSTER);
+    put_u16(&mut sector, 48, FSINFO_SECTOR as u16);
+    put_u16(&mut sector, 50, BACKUP_BOOT_SECTOR as u16);
+    sector[64] = 0x80;
+    sector[66] = 0x29;
+    put_u32(&mut sector, 67, volume_id);
+    sector[71..82].copy_from_slice(label);
+    sector[82..90].copy_from_slice(b"FAT32   ");
+
+    sector[510..512].copy_from_slice(&[0x55, 0xAA]);
+    sector
+}
+
+/// Builds the FSInfo sector of a volume whose only allocated cluster holds
+/// the root directory.
+fn fsinfo_sector(layout: &Layout) -> Vec<u8> {
+    let fsinfo = FsInfo::new(Some(layout.cluster_count as u32 - 1), Some(ROOT_CLUSTER + 1));
+    let mut sector = vec![0u8; layout.sector_size as usize];
+    sector[..512].copy_from_slice(&fsinfo.to_bytes());
+    sector
+}
+
+/// Writes zeroes to the `count` sectors starting at `start`.
+fn zero_sectors<T: BlockDevice>(device: &mut T, start: u64, count: u64) -> io::Result<()> {
+    let sector_size = device.sector_size();
+    let zeroes = vec![0u8; (min(count, ZERO_CHUNK_SECTORS) * sector_size) as usize];
+    let mut written = 0;
+    while written < count {
+        let n = min(count - written, ZERO_CHUNK_SECTORS);
+        device.write_sectors(start + written, &zeroes[..(n * sector_size) as usize])?;
+        written += n;
+    }
+
+    Ok(())
+}
+
+fn put_u16(buf: &mut [u8], offset: usize, value: u16) {
+    buf[offset..offset + 2].copy_from_slice(&[value as u8, (value >> 8) as u8]);
+}
+
+fn put_u32(buf: &mut [u8], offset: usize, value: u32) {
+    put_u16(buf, offset, value as u16);
+    put_u16(buf, offset + 2, (value >> 16) as u16);
+}
@@ -0,0 +1,347 @@
+use std::{fmt, io};
+
+use mbr::{self, MasterBootRecord};
+use traits::BlockDevice;
+use vfat::VFat;
+use exfat::BootSector;
+
+/// The signature at the start of every GPT header.
+const SIGNATURE: &[u8; 8] = b"EFI PART";
+/// The size of the GPT header fields covered by the header CRC32.
+const MIN_HEADER_SIZE: usize = 92;
+/// The largest partition entry array we are willing to read, in bytes.
+const MAX_ENTRY_ARRAY_SIZE: usize = 1 << 20;
+
+/// A globally unique identifier as stored on disk: the first three fields are
+/// little-endian, the remaining eight bytes are stored as is.
+#[derive(Copy, Clone, PartialEq, Eq, Default)]
+pub struct Guid(pub [u8; 16]);
+
+impl Guid {
+    /// The unused partition type. Entries with this type are empty.
+    pub const UNUSED: Guid = Guid([0; 16]);
+
+    /// The Microsoft Basic Data partition type,
+    /// `EBD0A0A2-B9E5-4433-87C0-68B6B72699C7`.
+    pub const BASIC_DATA: Guid = Guid([0xA2, 0xA0, 0xD0, 0xEB, 0xE5, 0xB9, 0x33, 0x44,
+                                       0x87, 0xC0, 0x68, 0xB6, 0xB7, 0x26, 0x99, 0xC7]);
+
+    /// The EFI System partition type, `C12A7328-F81F-11D2-BA4B-00A0C93EC93B`.
+    pub const EFI_SYSTEM: Guid = Guid([0x28, 0x73, 0x2A, 0xC1, 0x1F, 0xF8, 0xD2, 0x11,
+                                       0xBA, 0x4B, 0x00, 0xA0, 0xC9, 0x3E, 0xC9, 0x3B]);
+}
+
+impl fmt::Display for Guid {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let b = &self.0;
+        write!(f, "{:02X}{:02X}{:02X}{:02X}-{:02X}{:02X}-{:02X}{:02X}-{:02X}{:02X}-",
+               b[3], b[2], b[1], b[0], b[5], b[4], b[7], b[6], b[8], b[9])?;
+        for byte in b[10..].iter() {
+            write!(f, "{:02X}", byte)?;
+        }
+
+        Ok(())
+    }
+}
+
+impl fmt::Debug for Guid {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Guid({})", self)
+    }
+}
+
+/// The fields of a GPT header.
+#[derive(Debug, Copy, Clone)]
+pub struct GptHeader {
+    pub revision: u32,
+    pub header_size: u32,
+    /// The LBA of this header.
+    pub my_lba: u64,
+    /// The LBA of the other copy of the header.
+    pub alternate_lba: u64,
+    pub first_usable_lba: u64,
+    pub last_usable_lba: u64,
+    pub disk_guid: Guid,
+    pub partition_entry_lba: u64,
+    pub num_partition_entries: u32,
+    pub partition_entry_size: u32,
+    pub partition_entry_array_crc32: u32,
+}
+
+/// A used entry in the GPT partition entry array.
+#[derive(Debug, Clone)]
+pub struct GptPartition {
+    pub type_guid: Guid,
+    pub unique_guid: Guid,
+    /// The first LBA of the partition.
+    pub first_lba: u64,
+    /// The last LBA of the partition, inclusive.
+    pub last_lba: u64,
+    pub attributes: u64,
+    pub name: String,
+}
+
+impl GptPartition {
+    /// The number of sectors in the partition.
+    pub fn sectors(&self) -> u64 {
+        (self.last_lba + 1).saturating_sub(self.first_lba)
+    }
+
+    /// Returns `true` if the partition type is Microsoft Basic Data or EFI
+    /// System, the types that may hold a FAT or exFAT volume.
+    pub fn is_data(&self) -> bool {
+        self.type_guid == Guid::BASIC_DATA || self.type_guid == Guid::EFI_SYSTEM
+    }
+}
+
+#[derive(Debug)]
+pub enum Error {
+    /// There was an error while reading the protective MBR.
+    Mbr(mbr::Error),
+    /// There was an I/O error while reading the GPT.
+    Io(io::Error),
+    /// The MBR has no protective (`0xEE`) partition.
+    NotProtective,
+    /// Neither the primary nor the backup header and entry array are intact.
+    BadChecksum,
+}
+
+impl From<mbr::Error> for Error {
+    fn from(error: mbr::Error) -> Error {
+        Error::Mbr(error)
+    }
+}
+
+impl From<io::Error> for Error {
+    fn from(error: io::Error) -> Error {
+        Error::Io(error)
+    }
+}
+
+/// The GUID partition table (GPT).
+#[derive(Debug)]
+pub struct GuidPartitionTable {
+    header: GptHeader,
+    partitions: Vec<GptPartition>,
+    from_backup: bool,
+}
+
+impl GuidPartitionTable {
+    /// Reads and returns the GUID partition table from `device`.
+    ///
+    /// The primary header at LBA 1 and its partition entry array are used if
+    /// their CRC32 checksums are valid. Otherwise the backup header, found
+    /// through the primary header or at the end of the protective partition,
+    /// is used instead.
+    ///
+    /// # Errors
+    ///
+    /// Returns `NotProtective` if the MBR has no protective partition and
+    /// `BadChecksum` if neither copy of the table is intact. Returns `Mbr(err)`
+    /// or `Io(err)` if reading the MBR or the table fails.
+    pub fn from<T: BlockDevice>(mut device: T) -> Result<GuidPartitionTable, Error> {
+        let mbr = MasterBootRecord::from(&mut device)?;
+        // A protective partition with no sectors is malformed.
+        let protective_end = mbr.partitions().iter()
+            .find(|p| p.is_gpt_protective())
+            .and_then(|p| {
+                (p.relative_sector as u64).checked_add(p.total_sectors as u64)?.checked_sub(1)
+            })
+            .ok_or(Error::NotProtective)?;
+
+        let primary = read_header(&mut device, 1)?;
+        if let Some(header) = primary {
+            if let Some(partitions) = read_entries(&mut device, &header)? {
+                return Ok(GuidPartitionTable {
+                    header: header,
+                    partitions: partitions,
+                    from_backup: false,
+                });
+            }
+        }
+
+        let backup_lba = primary.map(|h| h.alternate_lba).unwrap_or(protective_end);
+        if let Some(header) = read_header(&mut device, backup_lba)? {
+            if let Some(partitions) = read_entries(&mut device, &header)? {
+                return Ok(GuidPartitionTable {
+                    header: header,
+                    partitions: partitions,
+                    from_backup: true,
+                });
+            }
+        }
+
+        Err(Error::BadChecksum)
+    }
+
+    /// The header the table was read from.
+    pub fn header(&self) -> &GptHeader {
+        &self.header
+    }
+
+    /// The used partition entries, in the order of the entry array.
+    pub fn partitions(&self) -> &[GptPartition] {
+        &self.partitions
+    }
+
+    /// Returns `true` if the primary table was corrupt and the backup was
+    /// used.
+    pub fn is_from_backup(&self) -> bool {
+        self.from_backup
+    }
+
+    /// Returns the first Basic Data or EFI System partition holding a FAT
+    /// volume on `device`, if any.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if reading a partition's boot sector fails.
+    pub fn first_fat<T: BlockDevice>(&self, mut device: T) -> io::Result<Option<&GptPartition>> {
+        for partition in self.partitions.iter().filter(|p| p.is_data()) {
+            if VFat::is_boot_sector(&mut device, partition.first_lba) {
+                return Ok(Some(partition));
+            }
+        }
+
+        Ok(None)
+    }
+
+    /// Returns the first Basic Data or EFI System partition holding an exFAT
+    /// volume on `device`, if any.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if reading a partition's boot sector fails.
+    pub fn first_exfat<T: BlockDevice>(&self, mut device: T) -> io::Result<Option<&GptPartition>> {
+        let mut buf = [0u8; 512];
+        for partition in self.partitions.iter().filter(|p| p.is_data()) {
+            device.read_sector(partition.first_lba, &mut buf)?;
+            if BootSector::is_exfat(&buf) {
+                return Ok(Some(partition));
+            }
+        }
+
+        Ok(None)
+    }
+}
+
+/// Reads the GPT header at `lba`. Returns `None` if the signature, size, or
+/// CRC32 is invalid or if the header does not describe itself as being at
+/// `lba`.
+fn read_header<T: BlockDevice>(device: &mut T, lba: u64) -> io::Result<Option<GptHeader>> {
+    let mut sector = Vec::new();
+    device.read_all_sector(lba, &mut sector)?;
+    if sector.len() < MIN_HEADER_SIZE || &sector[..8] != SIGNATURE {
+        return Ok(None);
+    }
+
+    let header_size = read_u32(&sector, 12) as usize;
+    if header_size < MIN_HEADER_SIZE || header_size > sector.len() {
+        return Ok(None);
+    }
+
+    let expected_crc = read_u32(&sector, 16);
+    for byte in sector[16..20].iter_mut() {
+        *byte = 0;
+    }
+
+    if crc32(&sector[..header_size]) != expected_crc || read_u64(&sector, 24) != lba {
+        return Ok(None);
+    }
+
+    let mut disk_guid = [0u8; 16];
+    disk_guid.copy_from_slice(&sector[56..72]);
+
+    Ok(Some(GptHeader {
+        revision: read_u32(&sector, 8),
+        header_size: header_size as u32,
+        my_lba: lba,
+        alternate_lba: read_u64(&sector, 32),
+        first_usable_lba: read_u64(&sector, 40),
+        last_usable_lba: read_u64(&sector, 48),
+        disk_guid: Guid(disk_guid),
+        partition_entry_lba: read_u64(&sector, 72),
+        num_partition_entries: read_u32(&sector, 80),
+        partition_entry_size: read_u32(&sector, 84),
+        partition_entry_array_crc32: read_u32(&sector, 88),
+    }))
+}
+
+/// Reads the partition entry array described by `header`. Returns `None` if
+/// the array is implausibly large or its CRC32 does not match the header.
+fn read_entries<T: BlockDevice>(
+    device: &mut T,
+    header: &GptHeader
+) -> io::Result<Option<Vec<GptPartition>>> {
+    let entry_size = header.partition_entry_size as usize;
+    let array_size = header.num_partition_entries as usize * entry_size;
+    if entry_size < 128 || array_size > MAX_ENTRY_ARRAY_SIZE {
+        return Ok(None);
+    }
+
+    let mut data = Vec::with_capacity(array_size);
+    let mut lba = header.partition_entry_lba;
+    while data.len() < array_size {
+        if device.read_all_sector(lba, &mut data)? == 0 {
+            return Ok(None);
+        }
+
+        lba += 1;
+    }
+
+    data.truncate(array_size);
+    if crc32(&data) != header.partition_entry_array_crc32 {
+        return Ok(None);
+    }
+
+    let mut partitions = Vec::new();
+    for entry in data.chunks(entry_size) {
+        let mut type_guid = [0u8; 16];
+        let mut unique_guid = [0u8; 16];
+        type_guid.copy_from_slice(&entry[..16]);
+        unique_guid.copy_from_slice(&entry[16..32]);
+        if Guid(type_guid) == Guid::UNUSED {
+            continue;
+        }
+
+        let units: Vec<u16> = entry[56..128].chunks(2)
+            .map(|c| c[0] as u16 | (c[1] as u16) << 8)
+            .take_while(|&u| u != 0)
+            .collect();
+
+        partitions.push(GptPartition {
+            type_guid: Guid(type_guid),
+            unique_guid: Guid(unique_guid),
+            first_lba: read_u64(entry, 32),
+            last_lba: read_u64(entry, 40),
+            attributes: read_u64(entry, 48),
+            name: String::from_utf16_lossy(&units),
+        });
+    }
+
+    Ok(Some(partitions))
+}
+
+fn read_u32(data: &[u8], offset: usize) -> u32 {
+    data[offset..offset + 4].iter().rev().fold(0, |value, &b| value << 8 | b as u32)
+}
+
+fn read_u64(data: &[u8], offset: usize) -> u64 {
+    read_u32(data, offset) as u64 | (read_u32(data, offset + 4) as u64) << 32
+}
+
+/// Computes the CRC32 (IEEE 802.3) checksum of `data` as used by GPT.
+pub(crate) fn crc32(data: &[u8]) -> u32 {
+    let mut crc = !0u32;
+    for &byte in data {
+        crc ^= byte as u32;
+        for _ in 0..8 {
+            crc = match crc & 1 {
+                1 => (crc >> 1) ^ 0xEDB88320,
+                _ => crc >> 1,
+            };
+        }
+    }
+
+    !crc
+}
@@ -6,10 +6,22 @@ compile_error!("only little endian platforms supported");
 
 #[cfg(test)]
 mod tests;
+mod format;
 mod mbr;
+mod mount;
+mod partition;
 mod util;
 
+pub mod check;
+pub mod defrag;
+pub mod gpt;
 pub mod vfat;
+pub mod exfat;
 pub mod traits;
+pub mod tmpfs;
+pub mod walk;
 
+pub use format::*;
 pub use mbr::*;
+pub use mount::*;
+pub use partition::*;
@@ -1,23 +1,127 @@
-use std::{fmt, io};
+use std::{fmt, io, mem};
 
 use traits::BlockDevice;
+use exfat::BootSector;
 
 #[repr(C, packed)]
 #[derive(Copy, Clone)]
 pub struct CHS {
-    // FIXME: Fill me in.
+    head: u8,
+    sector_cylinder_hi: u8,
+    cylinder_lo: u8,
+}
+
+impl CHS {
+    /// The head of this CHS address.
+    pub fn head(&self) -> u8 {
+        self.head
+    }
+
+    /// The sector (bits 0-5) of this CHS address.
+    pub fn sector(&self) -> u8 {
+        self.sector_cylinder_hi & 0b0011_1111
+    }
+
+    /// The cylinder (10 bits) of this CHS address.
+    pub fn cylinder(&self) -> u16 {
+        (((self.sector_cylinder_hi & 0b1100_0000) as u16) << 2) | self.cylinder_lo as u16
+    }
+}
+
+impl fmt::Debug for CHS {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("CHS")
+            .field("head", &self.head())
+            .field("sector", &self.sector())
+            .field("cylinder", &self.cylinder())
+            .finish()
+    }
 }
 
 #[repr(C, packed)]
-#[derive(Debug, Clone)]
+#[derive(Debug, Copy, Clone)]
 pub struct PartitionEntry {
-    // FIXME: Fill me in.
+    /// Boot indicator: `0x80` if active/bootable, `0x00` otherwise.
+    pub boot_indicator: u8,
+    /// CHS address of the first sector in the partition.
+    pub starting_chs: CHS,
+    /// The partition type.
+    pub partition_type: u8,
+    /// CHS address of the last sector in the partition.
+    pub ending_chs: CHS,
+    /// The LBA of the first sector in the partition.
+    pub relative_sector: u32,
+    /// The number of sectors in the partition.
+    pub total_sectors: u32,
+}
+
+impl PartitionEntry {
+    /// Returns `true` if this partition is marked as bootable.
+    pub fn is_bootable(&self) -> bool {
+        self.boot_indicator == 0x80
+    }
+
+    /// Returns `true` if the partition type indicates a FAT32 partition
+    /// (`0xB` or `0xC`).
+    pub fn is_fat32(&self) -> bool {
+        self.partition_type == 0xB || self.partition_type == 0xC
+    }
+
+    /// Returns `true` if the partition type indicates a FAT12, FAT16, or FAT32
+    /// partition.
+    pub fn is_fat(&self) -> bool {
+        match self.partition_type {
+            0x01 | 0x04 | 0x06 | 0x0E => true,
+            _ => self.is_fat32(),
+        }
+    }
+
+    /// Returns `true` if this is an extended partition (type `0x05`, `0x0F`,
+    /// or `0x85`) whose space is divided into logical partitions.
+    pub fn is_extended(&self) -> bool {
+        match self.partition_type {
+            0x05 | 0x0F | 0x85 => true,
+            _ => false,
+        }
+    }
+
+    /// Returns `true` if this is the protective partition (type `0xEE`) of a
+    /// disk using a GUID partition table.
+    pub fn is_gpt_protective(&self) -> bool {
+        self.partition_type == 0xEE
+    }
+
+    /// Returns `true` if the partition type is `0x07`, which is shared by
+    /// exFAT, NTFS, and HPFS. Use `is_exfat()` to tell them apart.
+    pub fn may_be_exfat(&self) -> bool {
+        self.partition_type == 0x07
+    }
+
+    /// Returns `true` if this partition holds an exFAT volume, as determined by
+    /// its partition type and the file system name in its boot sector on
+    /// `device`.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if reading the boot sector from `device` fails.
+    pub fn is_exfat<T: BlockDevice>(&self, mut device: T) -> io::Result<bool> {
+        if !self.may_be_exfat() {
+            return Ok(false);
+        }
+
+        let mut buf = [0u8; 512];
+        device.read_sector(self.relative_sector as u64, &mut buf)?;
+        Ok(BootSector::is_exfat(&buf))
+    }
 }
 
 /// The master boot record (MBR).
 #[repr(C, packed)]
 pub struct MasterBootRecord {
-    // FIXME: Fill me in.
+    bootstrap: [u8; 436],
+    disk_id: [u8; 10],
+    partitions: [PartitionEntry; 4],
+    signature: [u8; 2],
 }
 
 #[derive(Debug)]
@@ -28,8 +132,14 @@ pub enum Error {
     UnknownBootIndicator(u8),
     /// The MBR magic signature was invalid.
     BadSignature,
+    /// The chain of extended boot records loops or is implausibly long.
+    BadEbrChain,
 }
 
+/// The most logical partitions followed in an extended partition before the
+/// EBR chain is considered to loop.
+const MAX_LOGICAL_PARTITIONS: usize = 128;
+
 impl MasterBootRecord {
     /// Reads and returns the master boot record (MBR) from `device`.
     ///
@@ -39,13 +149,174 @@ impl MasterBootRecord {
     /// Returns `UnknownBootIndicator(n)` if partition `n` contains an invalid
     /// boot indicator. Returns `Io(err)` if the I/O error `err` occured while
     /// reading the MBR.
-    pub fn from<T: BlockDevice>(mut device: T) -> Result<MasterBootRecord, Error> {
-        unimplemented!("MasterBootRecord::from()")
+    pub fn from<T: BlockDevice>(device: T) -> Result<MasterBootRecord, Error> {
+        MasterBootRecord::read(device, 0)
+    }
+
+    /// Reads a boot record, the MBR or an EBR, from sector `sector` of
+    /// `device`.
+    fn read<T: BlockDevice>(mut device: T, sector: u64) -> Result<MasterBootRecord, Error> {
+        let mut buf = [0u8; 512];
+        let read = device.read_sector(sector, &mut buf).map_err(Error::Io)?;
+        if read != buf.len() {
+            return Err(Error::Io(io::Error::new(io::ErrorKind::UnexpectedEof,
+                                                "MBR sector is too short")));
+        }
+
+        let mbr: MasterBootRecord = unsafe { mem::transmute(buf) };
+        if mbr.signature != [0x55, 0xAA] {
+            return Err(Error::BadSignature);
+        }
+
+        for (i, partition) in mbr.partitions.iter().enumerate() {
+            match partition.boot_indicator {
+                0x00 | 0x80 => continue,
+                _ => return Err(Error::UnknownBootIndicator(i as u8)),
+            }
+        }
+
+        Ok(mbr)
+    }
+
+    /// The four primary partition entries in this MBR.
+    pub fn partitions(&self) -> &[PartitionEntry; 4] {
+        &self.partitions
+    }
+
+    /// Returns `true` if this is a protective MBR and the disk's partitions are
+    /// described by a GUID partition table.
+    pub fn is_protective(&self) -> bool {
+        self.partitions.iter().any(|p| p.is_gpt_protective())
+    }
+
+    /// Returns the first partition entry with a FAT partition type, if any.
+    pub fn first_fat(&self) -> Option<&PartitionEntry> {
+        self.partitions.iter().find(|p| p.is_fat())
+    }
+
+    /// Returns an iterator over every partition on `device`: first the
+    /// non-empty primary entries in this MBR, including any extended
+    /// partition, then the logical partitions found by following the chain of
+    /// extended boot records (EBRs) in the first extended partition.
+    ///
+    /// The `relative_sector` of a logical partition is made absolute, so it can
+    /// be used like that of a primary partition.
+    pub fn all_partitions<T: BlockDevice>(&self, device: T) -> Partitions<T> {
+        let extended = self.partitions.iter()
+            .find(|p| p.is_extended())
+            .map(|p| p.relative_sector as u64);
+
+        Partitions {
+            device: device,
+            primary: self.partitions,
+            index: 0,
+            extended: extended,
+            next_ebr: extended,
+            visited: Vec::new(),
+        }
+    }
+
+    /// Returns the first partition, primary or logical, holding an exFAT
+    /// volume on `device`, if any.
+    ///
+    /// # Errors
+    ///
+    /// Returns `Io(err)` if the I/O error `err` occured while reading a
+    /// partition's boot sector and any error encountered while following the
+    /// EBR chain.
+    pub fn first_exfat<T: BlockDevice>(
+        &self,
+        mut device: T
+    ) -> Result<Option<PartitionEntry>, Error> {
+        let partitions: Vec<PartitionEntry> = self.all_partitions(&mut device)
+            .collect::<Result<_, _>>()?;
+
+        for partition in partitions {
+            if partition.is_exfat(&mut device).map_err(Error::Io)? {
+                return Ok(Some(partition));
+            }
+        }
+
+        Ok(None)
+    }
+}
+
+/// An iterator over the primary and logical partitions on a device. See
+/// `MasterBootRecord::all_partitions()`.
+pub struct Partitions<T: BlockDevice> {
+    device: T,
+    primary: [PartitionEntry; 4],
+    index: usize,
+    /// The first sector of the extended partition being walked.
+    extended: Option<u64>,
+    /// The sector of the next EBR to read.
+    next_ebr: Option<u64>,
+    /// The sectors of every EBR read so far.
+    visited: Vec<u64>,
+}
+
+impl<T: BlockDevice> Partitions<T> {
+    /// Reads the EBR at `sector` and returns its logical partition, if any,
+    /// queuing the next EBR in the chain.
+    fn read_ebr(&mut self, sector: u64) -> Result<Option<PartitionEntry>, Error> {
+        if self.visited.contains(&sector) || self.visited.len() >= MAX_LOGICAL_PARTITIONS {
+            return Err(Error::BadEbrChain);
+        }
+
+        self.visited.push(sector);
+        let ebr = MasterBootRecord::read(&mut self.device, sector)?;
+
+        // The link to the next EBR is relative to the extended partition.
+        let link = ebr.partitions[1];
+        if link.is_extended() && link.relative_sector != 0 {
+            self.next_ebr = self.extended.map(|start| start + link.relative_sector as u64);
+        }
+
+        // The logical partition is relative to its EBR.
+        let mut logical = ebr.partitions[0];
+        if logical.partition_type == 0 {
+            return Ok(None);
+        }
+
+        let start = sector + logical.relative_sector as u64;
+        if start > u32::max_value() as u64 {
+            return Err(Error::BadEbrChain);
+        }
+
+        logical.relative_sector = start as u32;
+        Ok(Some(logical))
+    }
+}
+
+impl<T: BlockDevice> Iterator for Partitions<T> {
+    type Item = Result<PartitionEntry, Error>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        while self.index < self.primary.len() {
+            let partition = self.primary[self.index];
+            self.index += 1;
+            if partition.partition_type != 0 {
+                return Some(Ok(partition));
+            }
+        }
+
+        while let Some(sector) = self.next_ebr.take() {
+            match self.read_ebr(sector) {
+                Ok(Some(logical)) => return Some(Ok(logical)),
+                Ok(None) => continue,
+                Err(e) => return Some(Err(e)),
+            }
+        }
+
+        None
     }
 }
 
 impl fmt::Debug for MasterBootRecord {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        unimplemented!("MasterBootRecord::fmt()")
+        f.debug_struct("MasterBootRecord")
+            .field("disk_id", &self.disk_id)
+            .field("partitions", &self.partitions)
+            .finish()
     }
 }
@@ -0,0 +1,118 @@
+use std::io;
+
+use mbr::{self, MasterBootRecord, PartitionEntry};
+use gpt::{self, GuidPartitionTable};
+use traits::BlockDevice;
+use partition::PartitionDevice;
+use vfat::{self, VFat, Shared};
+use exfat::{self, ExFat, BootSector};
+
+/// A mounted volume of one of the supported file systems.
+#[derive(Debug)]
+pub enum Volume {
+    Fat(Shared<VFat>),
+    ExFat(Shared<ExFat>),
+}
+
+#[derive(Debug)]
+pub enum MountError {
+    Mbr(mbr::Error),
+    Gpt(gpt::Error),
+    Io(io::Error),
+    Fat(vfat::Error),
+    ExFat(exfat::Error),
+    /// The device holds neither a FAT nor an exFAT volume.
+    NotFound,
+}
+
+impl From<mbr::Error> for MountError {
+    fn from(error: mbr::Error) -> MountError {
+        MountError::Mbr(error)
+    }
+}
+
+impl From<gpt::Error> for MountError {
+    fn from(error: gpt::Error) -> MountError {
+        MountError::Gpt(error)
+    }
+}
+
+impl From<io::Error> for MountError {
+    fn from(error: io::Error) -> MountError {
+        MountError::Io(error)
+    }
+}
+
+impl From<vfat::Error> for MountError {
+    fn from(error: vfat::Error) -> MountError {
+        MountError::Fat(error)
+    }
+}
+
+impl From<exfat::Error> for MountError {
+    fn from(error: exfat::Error) -> MountError {
+        MountError::ExFat(error)
+    }
+}
+
+/// Mounts the volume on `device` with the driver for its file system.
+///
+/// A device whose first sector is a FAT or exFAT boot sector, or a damaged
+/// FAT32 boot sector with an intact backup, is mounted as a partitionless
+/// volume. Otherwise, the first partition in the MBR, or in the
+/// GPT for a protective MBR, holding a FAT or exFAT volume is mounted. The
+/// driver is given only that partition, through a `PartitionDevice`.
+///
+/// # Errors
+///
+/// Returns `NotFound` if the device holds no supported volume. Errors from
+/// reading the MBR or from the selected driver are returned as is.
+pub fn mount<T: BlockDevice + 'static>(mut device: T) -> Result<Volume, MountError> {
+    let mut buf = [0u8; 512];
+    device.read_sector(0, &mut buf)?;
+    if BootSector::is_exfat(&buf) {
+        return Ok(Volume::ExFat(ExFat::from(device)?));
+    }
+
+    if VFat::is_boot_sector(&mut device, 0) {
+        return Ok(Volume::Fat(VFat::from(device)?));
+    }
+
+    let mbr = MasterBootRecord::from(&mut device)?;
+    if mbr.is_protective() {
+        let gpt = GuidPartitionTable::from(&mut device)?;
+        for partition in gpt.partitions().iter().filter(|p| p.is_data()) {
+            let (start, sectors) = (partition.first_lba, partition.sectors());
+            device.read_sector(start, &mut buf)?;
+            if BootSector::is_exfat(&buf) {
+                let device = PartitionDevice::new(device, start, sectors);
+                return Ok(Volume::ExFat(ExFat::from(device)?));
+            }
+
+            if VFat::is_boot_sector(&mut device, start) {
+                let device = PartitionDevice::new(device, start, sectors);
+                return Ok(Volume::Fat(VFat::from(device)?));
+            }
+        }
+
+        return Err(MountError::NotFound);
+    }
+
+    let partitions: Vec<PartitionEntry> = mbr.all_partitions(&mut device)
+        .collect::<Result<_, _>>()?;
+    for partition in partitions {
+        let (start, sectors) = (partition.relative_sector as u64,
+                                partition.total_sectors as u64);
+        if partition.is_fat() {
+            let device = PartitionDevice::new(device, start, sectors);
+            return Ok(Volume::Fat(VFat::from(device)?));
+        }
+
+        if partition.is_exfat(&mut device)? {
+            let device = PartitionDevice::new(device, start, sectors);
+            return Ok(Volume::ExFat(ExFat::from(device)?));
+        }
+    }
+
+    Err(MountError::NotFound)
+}
@@ -0,0 +1,127 @@
+use std::io;
+use std::cmp::min;
+
+use traits::BlockDevice;
+
+/// A `BlockDevice` exposing a single partition of another device as its own
+/// device whose sector 0 is the partition's first sector.
+///
+/// The partition's sectors may be larger than the device's: a logical sector
+/// spans `sector_size / device.sector_size()` consecutive physical sectors.
+/// Accesses past the end of the partition are rejected.
+#[derive(Debug)]
+pub struct PartitionDevice<T: BlockDevice> {
+    device: T,
+    /// The physical sector where the partition begins.
+    start: u64,
+    /// The number of logical sectors in the partition.
+    sectors: u64,
+    /// The size, in bytes, of a logical sector.
+    sector_size: u64,
+}
+
+impl<T: BlockDevice> PartitionDevice<T> {
+    /// Returns a device for the `sectors` sectors of `device` starting at
+    /// sector `start`, with the device's sector size.
+    pub fn new(device: T, start: u64, sectors: u64) -> PartitionDevice<T> {
+        let sector_size = device.sector_size();
+        PartitionDevice::with_sector_size(device, start, sectors, sector_size)
+    }
+
+    /// Returns a device for the `sectors` physical sectors of `device` starting
+    /// at physical sector `start`, with logical sectors of `sector_size`
+    /// bytes. A trailing part of the partition smaller than a logical sector
+    /// is inaccessible.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `sector_size` is not a non-zero multiple of the device's
+    /// sector size.
+    pub fn with_sector_size(
+        device: T,
+        start: u64,
+        sectors: u64,
+        sector_size: u64
+    ) -> PartitionDevice<T> {
+        let physical_size = device.sector_size();
+        assert!(sector_size >= physical_size && sector_size % physical_size == 0,
+                "logical sector size must be a multiple of the device's sector size");
+
+        PartitionDevice {
+            device: device,
+            start: start,
+            sectors: sectors / (sector_size / physical_size),
+            sector_size: sector_size,
+        }
+    }
+
+    /// The number of logical sectors in the partition.
+    pub fn sectors(&self) -> u64 {
+        self.sectors
+    }
+
+    /// Returns the underlying device.
+    pub fn into_inner(self) -> T {
+        self.device
+    }
+
+    /// Maps the `count` logical sectors starting at sector `n` to the first
+    /// physical sector holding them.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error of `InvalidInput` if any of the sectors is past the end
+    /// of the partition.
+    fn physical(&self, n: u64, count: u64) -> io::Result<u64> {
+        match n.checked_add(count) {
+            Some(end) if end <= self.sectors => {}
+            _ => return Err(io::Error::new(io::ErrorKind::InvalidInput,
+                                           "sector is past the end of the partition")),
+        }
+
+        Ok(self.start + n * (self.sector_size / self.device.sector_size()))
+    }
+}
+
+impl<T: BlockDevice> BlockDevice for PartitionDevice<T> {
+    fn sector_size(&self) -> u64 {
+        self.sector_size
+    }
+
+    fn read_sector(&mut self, n: u64, buf: &mut [u8]) -> io::Result<usize> {
+        let physical = self.physical(n, 1)?;
+        let sector_size = self.sector_size as usize;
+        if buf.len() >= sector_size {
+            return self.device.read_sectors(physical, &mut buf[..sector_size]);
+        }
+
+        let mut sector = vec![0u8; sector_size];
+        self.device.read_sectors(physical, &mut sector)?;
+        let amount = min(buf.len(), sector_size);
+        buf[..amount].copy_from_slice(&sector[..amount]);
+        Ok(amount)
+    }
+
+    fn write_sector(&mut self, n: u64, buf: &[u8]) -> io::Result<usize> {
+        let physical = self.physical(n, 1)?;
+        let sector_size = self.sector_size as usize;
+        if buf.len() < sector_size {
+            return Err(io::Error::new(io::ErrorKind::UnexpectedEof,
+                                      "buffer is smaller than a sector"));
+        }
+
+        self.device.write_sectors(physical, &buf[..sector_size])
+    }
+
+    fn read_sectors(&mut self, n: u64, buf: &mut [u8]) -> io::Result<usize> {
+        let count = buf.len() / self.sector_size as usize;
+        let physical = self.physical(n, count as u64)?;
+        self.device.read_sectors(physical, &mut buf[..count * self.sector_size as usize])
+    }
+
+    fn write_sectors(&mut self, n: u64, buf: &[u8]) -> io::Result<usize> {
+        let count = buf.len() / self.sector_size as usize;
+        let physical = self.physical(n, count as u64)?;
+        self.device.write_sectors(physical, &buf[..count * self.sector_size as usize])
+    }
+}
@@ -1,10 +1,12 @@
 extern crate rand;
 
 use std::io::prelude::*;
-use std::io::Cursor;
+use std::io::{self, Cursor};
 use std::path::Path;
 
-use vfat::{Shared, VFat, BiosParameterBlock};
+use vfat::{Shared, VFat, BiosParameterBlock, FsInfo, FatType};
+use exfat::{self, ExFat};
+use check;
 use mbr::{MasterBootRecord, CHS, PartitionEntry};
 use traits::*;
 
@@ -175,7 +177,8 @@ fn hash_dir<T: Dir>(
 ) -> Result<Vec<T::Entry>, ::std::fmt::Error> {
     let mut entries: Vec<_> = dir.entries()
         .expect("entries interator")
-        .collect();
+        .collect::<io::Result<_>>()
+        .expect("readable directory");
 
     entries.sort_by(|a, b| a.name().cmp(b.name()));
     for (i, entry) in entries.iter().enumerate() {
@@ -214,19 +217,23 @@ fn hash_dir_recursive<P: AsRef<Path>>(
 ) -> ::std::fmt::Result {
     use std::fmt::Write;
 
+    // Sorting the paths component-wise visits directories depth-first with
+    // siblings in name order.
     let path = path.as_ref();
-    let dir = vfat.open_dir(path).expect("directory");
-
-    write!(hash, "{}\n", path.display())?;
-    let entries = hash_dir(hash, dir)?;
-    if entries.iter().any(|e| e.is_dir()) {
-        hash.push_str("\n\n");
+    let mut dirs = vec![path.to_path_buf()];
+    for entry in ::walk::walk(&vfat, path).expect("directory") {
+        let entry = entry.expect("readable directory");
+        if entry.entry.is_dir() {
+            dirs.push(entry.path);
+        }
     }
+    dirs.sort();
 
-    for entry in entries {
-        if entry.is_dir() && entry.name() != "." && entry.name() != ".." {
-            let path = path.join(entry.name());
-            hash_dir_recursive(hash, vfat.clone(), path)?;
+    for path in dirs {
+        write!(hash, "{}\n", path.display())?;
+        let entries = hash_dir(hash, vfat.open_dir(&path).expect("directory"))?;
+        if entries.iter().any(|e| e.is_dir()) {
+            hash.push_str("\n\n");
         }
     }
 
@@ -293,7 +300,8 @@ fn hash_files_recursive<P: AsRef<Path>>(
         .expect("directory")
         .entries()
         .expect("entries interator")
-        .collect::<Vec<_>>();
+        .collect::<io::Result<Vec<_>>>()
+        .expect("readable directory");
 
     entries.sort_by(|a, b| a.name().cmp(b.name()));
     for entry in entries {
@@ -349,3 +357,2271 @@ fn shared_fs_is_sync_send_static() {
     fn f<T: Sync + Send + 'static>() {  }
     f::<Shared<VFat>>();
 }
+
+/// A block device backed by a shared in-memory image so that the image can be
+/// inspected and remounted after a `VFat` using it is dropped.
+#[derive(Clone)]
+struct SharedImage(::std::sync::Arc<::std::sync::Mutex<Cursor<Vec<u8>>>>);
+
+impl SharedImage {
+    fn new(data: Vec<u8>) -> SharedImage {
+        SharedImage(::std::sync::Arc::new(::std::sync::Mutex::new(Cursor::new(data))))
+    }
+}
+
+impl BlockDevice for SharedImage {
+    fn read_sector(&mut self, n: u64, buf: &mut [u8]) -> ::std::io::Result<usize> {
+        self.0.lock().unwrap().read_sector(n, buf)
+    }
+
+    fn write_sector(&mut self, n: u64, buf: &[u8]) -> ::std::io::Result<usize> {
+        self.0.lock().unwrap().write_sector(n, buf)
+    }
+}
+
+const MOCK_PARTITION_START: usize = 8;
+
+fn put_u16(data: &mut [u8], offset: usize, value: u16) {
+    data[offset..offset + 2].copy_from_slice(&[value as u8, (value >> 8) as u8]);
+}
+
+fn put_u32(data: &mut [u8], offset: usize, value: u32) {
+    put_u16(data, offset, value as u16);
+    put_u16(data, offset + 2, (value >> 16) as u16);
+}
+
+fn put_u64(data: &mut [u8], offset: usize, value: u64) {
+    put_u32(data, offset, value as u32);
+    put_u32(data, offset + 4, (value >> 32) as u32);
+}
+
+/// Builds a blank FAT image of type `fat_type` with 512-byte sectors and
+/// clusters containing a single empty file, `LOG.TXT`, in the root directory.
+/// If `partitioned` is `true`, the volume is placed in a partition described
+/// by an MBR. Otherwise the volume starts at sector 0, as on a floppy.
+fn mock_image(fat_type: FatType, partitioned: bool) -> Vec<u8> {
+    // (sectors, reserved sectors, sectors per FAT, root entries, MBR type)
+    let (sectors, reserved, sectors_per_fat, root_entries, partition_type) = match fat_type {
+        FatType::Fat12 => (2880, 1, 9, 224, 0x01),
+        FatType::Fat16 => (8192, 1, 32, 512, 0x06),
+        FatType::Fat32 => (4096, 32, 32, 0, 0x0C),
+    };
+
+    let start = if partitioned { MOCK_PARTITION_START } else { 0 };
+    let mut data = vec![0u8; (start + sectors) * 512];
+
+    if partitioned {
+        data[446 + 4] = partition_type;
+        put_u32(&mut data, 446 + 8, start as u32);
+        put_u32(&mut data, 446 + 12, sectors as u32);
+        data[510..512].copy_from_slice(&[0x55, 0xAA]);
+    }
+
+    {
+        let ebpb = &mut data[start * 512..][..512];
+        ebpb[..3].copy_from_slice(&[0xEB, 0x58, 0x90]);
+        put_u16(ebpb, 11, 512);
+        ebpb[13] = 1;
+        put_u16(ebpb, 14, reserved as u16);
+        ebpb[16] = 2;
+        put_u16(ebpb, 17, root_entries);
+        ebpb[21] = 0xF8;
+        put_u32(ebpb, 32, sectors as u32);
+        if fat_type == FatType::Fat32 {
+            put_u32(ebpb, 36, sectors_per_fat as u32);
+            put_u32(ebpb, 44, 2);
+            ebpb[66] = 0x29;
+        } else {
+            put_u16(ebpb, 22, sectors_per_fat as u16);
+            ebpb[38] = 0x29;
+        }
+        ebpb[510..512].copy_from_slice(&[0x55, 0xAA]);
+    }
+
+    // Both FATs: media entry, reserved entry, and the root directory's EOC.
+    let fat_head: &[u8] = match fat_type {
+        FatType::Fat12 => &[0xF8, 0xFF, 0xFF],
+        FatType::Fat16 => &[0xF8, 0xFF, 0xFF, 0xFF],
+        FatType::Fat32 => &[0xF8, 0xFF, 0xFF, 0x0F, 0xFF, 0xFF, 0xFF, 0x0F,
+                            0xFF, 0xFF, 0xFF, 0x0F],
+    };
+
+    for fat in 0..2 {
+        let fat_start = (start + reserved + fat * sectors_per_fat) * 512;
+        data[fat_start..fat_start + fat_head.len()].copy_from_slice(fat_head);
+    }
+
+    // Root directory (cluster 2 or the fixed region) with an empty `LOG.TXT`.
+    let root = (start + reserved + 2 * sectors_per_fat) * 512;
+    data[root..root + 11].copy_from_slice(b"LOG     TXT");
+    data[root + 11] = 0x20;
+
+    data
+}
+
+fn mock_fat32_image() -> Vec<u8> {
+    mock_image(FatType::Fat32, true)
+}
+
+#[test]
+fn test_write_grows_chain_and_size() {
+    let image = SharedImage::new(mock_fat32_image());
+    let contents: Vec<u8> = (0..5000u32).map(|i| (i % 251) as u8).collect();
+
+    {
+        let vfat = VFat::from(image.clone()).expect("mock image mounts");
+        let mut file = vfat.open_file("/log.txt").expect("file exists");
+        assert_eq!(file.size(), 0);
+
+        file.write_all(&contents[..1000]).expect("first write");
+        file.write_all(&contents[1000..]).expect("second write");
+        assert_eq!(file.size(), contents.len() as u64);
+        file.sync().expect("sync");
+    }
+
+    let vfat = VFat::from(image.clone()).expect("image remounts");
+    let mut file = vfat.open_file("/LOG.TXT").expect("file exists");
+    assert_eq!(file.size(), contents.len() as u64);
+
+    let mut read = Vec::new();
+    file.read_to_end(&mut read).expect("read back");
+    assert!(read == contents, "file contents differ after remount");
+}
+
+#[test]
+fn test_write_overwrites_in_place() {
+    let image = SharedImage::new(mock_fat32_image());
+    let vfat = VFat::from(image).expect("mock image mounts");
+
+    let mut file = vfat.open_file("/LOG.TXT").unwrap();
+    file.write_all(&[b'a'; 1500]).unwrap();
+    file.seek(::std::io::SeekFrom::Start(510)).unwrap();
+    file.write_all(b"hello").unwrap();
+    assert_eq!(file.size(), 1500);
+
+    file.seek(::std::io::SeekFrom::Start(508)).unwrap();
+    let mut buf = [0u8; 9];
+    file.read_exact(&mut buf).unwrap();
+    assert_eq!(&buf, b"aahelloaa");
+
+    expect_variant!(file.seek(::std::io::SeekFrom::Start(1501)),
+                    Err(ref e) if e.kind() == ::std::io::ErrorKind::InvalidInput);
+}
+
+#[test]
+fn test_write_reports_partial_write_when_full() {
+    let image = SharedImage::new(mock_fat32_image());
+    let vfat = VFat::from(image).expect("mock image mounts");
+
+    let mut file = vfat.open_file("/LOG.TXT").unwrap();
+    let contents = vec![b'x'; 2 * 1024 * 1024];
+    let written = file.write(&contents).expect("partial write");
+    assert!(written > 0 && written < contents.len());
+    assert_eq!(file.size(), written as u64);
+
+    expect_variant!(file.write(&contents), Err(_));
+    assert_eq!(file.size(), written as u64);
+
+    let mut buf = [0u8; 16];
+    assert_eq!(file.read(&mut buf).unwrap(), 0);
+
+    file.seek(::std::io::SeekFrom::Start(0)).unwrap();
+    let mut read = Vec::new();
+    file.read_to_end(&mut read).expect("read back");
+    assert!(read == &contents[..written], "partially written contents differ");
+}
+
+fn entry_names<T: Dir>(dir: T) -> Vec<String> {
+    let mut names: Vec<String> = dir.entries()
+        .expect("entries iterator")
+        .map(|e| e.expect("readable directory").name().to_string())
+        .collect();
+    names.sort();
+    names
+}
+
+#[test]
+fn test_create_file_and_dir() {
+    let image = SharedImage::new(mock_fat32_image());
+    {
+        let vfat = VFat::from(image.clone()).expect("mock image mounts");
+        let mut file = vfat.create_file("/Hello World.txt").expect("create file");
+        file.write_all(b"hello, world").unwrap();
+        file.sync().unwrap();
+
+        expect_variant!(vfat.create_file("/hello world.TXT"),
+                        Err(ref e) if e.kind() == ::std::io::ErrorKind::AlreadyExists);
+        expect_variant!(vfat.create_file("/missing/file"),
+                        Err(ref e) if e.kind() == ::std::io::ErrorKind::InvalidInput);
+        expect_variant!(vfat.create_dir("/a/b/c", false),
+                        Err(ref e) if e.kind() == ::std::io::ErrorKind::InvalidInput);
+
+        vfat.create_dir("/a/b/c", true).expect("create dirs with parents");
+        vfat.create_file("/a/b/c/deep file").expect("create nested file");
+    }
+
+    let vfat = VFat::from(image).expect("image remounts");
+    assert_eq!(entry_names(vfat.open_dir("/").unwrap()),
+               vec!["Hello World.txt", "LOG.TXT", "a"]);
+    assert_eq!(entry_names(vfat.open_dir("/a/b/c").unwrap()),
+               vec![".", "..", "deep file"]);
+
+    let mut contents = String::new();
+    vfat.open_file("/hello world.txt").unwrap().read_to_string(&mut contents).unwrap();
+    assert_eq!(contents, "hello, world");
+
+    let dot_dot = vfat.open_dir("/a/b/c/..").unwrap();
+    assert_eq!(entry_names(dot_dot), vec![".", "..", "c"]);
+}
+
+#[test]
+fn test_create_dir_in_full_fixed_root() {
+    let vfat = VFat::from(SharedImage::new(mock_image(FatType::Fat16, true))).unwrap();
+    for i in 0..511 {
+        vfat.create_file(format!("/F{}", i)).unwrap();
+    }
+
+    let free = vfat.borrow_mut().free_clusters().unwrap();
+    assert!(vfat.create_dir("/dir", false).is_err());
+    assert_eq!(vfat.borrow_mut().free_clusters().unwrap(), free);
+    let report = check::check(&vfat, check::Mode::ReadOnly).unwrap();
+    assert!(report.is_clean(), "{}", report);
+}
+
+#[test]
+fn test_create_many_entries_grows_dir() {
+    let vfat = VFat::from(SharedImage::new(mock_fat32_image())).unwrap();
+    vfat.create_dir("/many", false).unwrap();
+    for i in 0..100 {
+        vfat.create_file(format!("/many/a rather long file name {}", i)).unwrap();
+    }
+
+    let names = entry_names(vfat.open_dir("/many").unwrap());
+    assert_eq!(names.len(), 102);
+    vfat.open_file("/many/a rather long file name 99").expect("last file exists");
+}
+
+#[test]
+fn test_rename() {
+    let vfat = VFat::from(SharedImage::new(mock_fat32_image())).unwrap();
+    vfat.create_dir("/src/inner", true).unwrap();
+    vfat.create_dir("/dst", false).unwrap();
+    let mut file = vfat.create_file("/src/inner/file").unwrap();
+    file.write_all(b"data").unwrap();
+    file.sync().unwrap();
+
+    expect_variant!(vfat.rename("/src", "/dst"),
+                    Err(ref e) if e.kind() == ::std::io::ErrorKind::AlreadyExists);
+    expect_variant!(vfat.rename("/nope", "/other"),
+                    Err(ref e) if e.kind() == ::std::io::ErrorKind::NotFound);
+    expect_variant!(vfat.rename("/src", "/src/inner/src"),
+                    Err(ref e) if e.kind() == ::std::io::ErrorKind::InvalidInput);
+    expect_variant!(vfat.rename("/src", "/dst/../SRC/inner/src"),
+                    Err(ref e) if e.kind() == ::std::io::ErrorKind::InvalidInput);
+    vfat.rename("/src", "/src").expect("rename to itself");
+
+    vfat.create_dir("/Ärger/sub", true).unwrap();
+    expect_variant!(vfat.rename("/Ärger", "/ärger/SUB/x"),
+                    Err(ref e) if e.kind() == ::std::io::ErrorKind::InvalidInput);
+    vfat.rename("/Ärger/sub", "/sub").expect("move out of a directory");
+    vfat.remove("/Ärger", true).unwrap();
+    vfat.remove("/sub", true).unwrap();
+
+    vfat.rename("/src/inner", "/dst/moved").expect("cross-directory rename");
+    vfat.rename("/LOG.TXT", "/log.txt").expect("case-only rename");
+
+    assert_eq!(entry_names(vfat.open_dir("/src").unwrap()), vec![".", ".."]);
+    assert_eq!(entry_names(vfat.open_dir("/dst/moved").unwrap()),
+               vec![".", "..", "file"]);
+    assert_eq!(entry_names(vfat.open_dir("/dst/moved/..").unwrap()),
+               vec![".", "..", "moved"]);
+    assert!(entry_names(vfat.open_dir("/").unwrap()).contains(&"log.txt".to_string()));
+
+    let mut data = String::new();
+    vfat.open_file("/dst/moved/file").unwrap().read_to_string(&mut data).unwrap();
+    assert_eq!(data, "data");
+}
+
+#[test]
+fn test_remove() {
+    let vfat = VFat::from(SharedImage::new(mock_fat32_image())).unwrap();
+    vfat.create_dir("/tree/sub", true).unwrap();
+    vfat.create_file("/tree/sub/file").unwrap().write_all(&[1; 4096]).unwrap();
+
+    expect_variant!(vfat.remove("/tree", false),
+                    Err(ref e) if e.kind() == ::std::io::ErrorKind::Other);
+    expect_variant!(vfat.remove("/missing", true),
+                    Err(ref e) if e.kind() == ::std::io::ErrorKind::NotFound);
+    expect_variant!(vfat.remove("relative", true),
+                    Err(ref e) if e.kind() == ::std::io::ErrorKind::InvalidInput);
+
+    vfat.remove("/LOG.TXT", false).expect("remove file");
+    vfat.remove("/tree", true).expect("remove tree");
+    assert!(entry_names(vfat.open_dir("/").unwrap()).is_empty());
+    expect_variant!(vfat.open("/tree/sub/file"),
+                    Err(ref e) if e.kind() == ::std::io::ErrorKind::InvalidInput);
+}
+
+fn exercise_fat_type(fat_type: FatType, partitioned: bool) {
+    let image = SharedImage::new(mock_image(fat_type, partitioned));
+    let contents: Vec<u8> = (0..3000u32).map(|i| (i % 13) as u8).collect();
+    {
+        let vfat = VFat::from(image.clone()).expect("mock image mounts");
+        assert_eq!(vfat.borrow().fat_type(), fat_type);
+
+        vfat.create_dir("/logs/old", true).unwrap();
+        for i in 0..40 {
+            vfat.create_file(format!("/root file {}", i)).unwrap();
+        }
+
+        let mut file = vfat.create_file("/logs/old/system.log").unwrap();
+        file.write_all(&contents).unwrap();
+        file.sync().unwrap();
+
+        vfat.rename("/logs/old/system.log", "/system.log").unwrap();
+        vfat.remove("/logs", true).unwrap();
+    }
+
+    let vfat = VFat::from(image).expect("image remounts");
+    let names = entry_names(vfat.open_dir("/").unwrap());
+    assert_eq!(names.len(), 42);
+    assert!(names.contains(&"system.log".to_string()));
+
+    let mut read = Vec::new();
+    vfat.open_file("/system.log").unwrap().read_to_end(&mut read).unwrap();
+    assert!(read == contents, "file contents differ after remount");
+}
+
+#[test]
+fn test_fat12() {
+    exercise_fat_type(FatType::Fat12, true);
+    exercise_fat_type(FatType::Fat12, false);
+}
+
+#[test]
+fn test_fat16() {
+    exercise_fat_type(FatType::Fat16, true);
+}
+
+#[test]
+fn test_fat32_small_volume() {
+    exercise_fat_type(FatType::Fat32, true);
+}
+
+#[test]
+fn test_fixed_root_dir_full() {
+    let vfat = VFat::from(SharedImage::new(mock_image(FatType::Fat12, false))).unwrap();
+    let mut result = Ok(());
+    for i in 0..224 {
+        if let Err(e) = vfat.create_file(format!("/F{}", i)) {
+            result = Err(e);
+            break;
+        }
+    }
+
+    expect_variant!(result, Err(ref e) if e.kind() == ::std::io::ErrorKind::Other);
+}
+
+/// 05/17/2018 13:45:30 as a packed exFAT date and time.
+const MOCK_EXFAT_TIME: u32 = (38 << 25) | (5 << 21) | (17 << 16) | (13 << 11) | (45 << 5) | 15;
+
+/// Builds the raw file entry set for an entry named `name` whose data starts
+/// at `cluster`.
+fn exfat_entry_set(
+    name: &str,
+    attributes: u16,
+    cluster: u32,
+    size: u64,
+    valid_size: u64,
+    contiguous: bool
+) -> Vec<u8> {
+    let units: Vec<u16> = name.encode_utf16().collect();
+    let name_entries = (units.len() + 14) / 15;
+    let mut set = vec![0u8; 32 * (2 + name_entries)];
+
+    set[0] = 0x85;
+    set[1] = (1 + name_entries) as u8;
+    put_u16(&mut set, 4, attributes);
+    put_u32(&mut set, 8, MOCK_EXFAT_TIME);
+    put_u32(&mut set, 12, MOCK_EXFAT_TIME);
+    put_u32(&mut set, 16, MOCK_EXFAT_TIME);
+    set[20] = 150;
+    set[22] = 0x80 | 4;
+
+    set[32] = 0xC0;
+    set[33] = if contiguous { 0x03 } else { 0x01 };
+    set[35] = units.len() as u8;
+    put_u64(&mut set, 32 + 8, valid_size);
+    put_u32(&mut set, 32 + 20, cluster);
+    put_u64(&mut set, 32 + 24, size);
+
+    for (i, chunk) in units.chunks(15).enumerate() {
+        let entry = 64 + 32 * i;
+        set[entry] = 0xC1;
+        for (j, &unit) in chunk.iter().enumerate() {
+            put_u16(&mut set, entry + 2 + 2 * j, unit);
+        }
+    }
+
+    let checksum = exfat::dir::entry_set_checksum(&set);
+    put_u16(&mut set, 2, checksum);
+    set
+}
+
+/// Builds an exFAT image with 512-byte sectors and clusters. If `partitioned`
+/// is `true`, the volume is placed in a partition described by an MBR.
+///
+/// The root directory spans clusters 4 and 13 and holds `Hello.txt`
+/// (contiguous), `fragmented.bin` (FAT chain 7, 10, 8 with 1000 of 1400 bytes
+/// valid), and the directory `Sub Dir`, followed by a deleted entry set and
+/// one with a bad checksum. `Sub Dir` holds `Nested.TXT` and an empty file
+/// with a long name.
+fn mock_exfat_image(partitioned: bool) -> Vec<u8> {
+    const FAT_OFFSET: usize = 24;
+    const HEAP_OFFSET: usize = 32;
+    const CLUSTERS: usize = 256;
+
+    let start = if partitioned { MOCK_PARTITION_START } else { 0 };
+    let sectors = HEAP_OFFSET + CLUSTERS;
+    let mut data = vec![0u8; (start + sectors) * 512];
+
+    if partitioned {
+        data[446 + 4] = 0x07;
+        put_u32(&mut data, 446 + 8, start as u32);
+        put_u32(&mut data, 446 + 12, sectors as u32);
+        data[510..512].copy_from_slice(&[0x55, 0xAA]);
+    }
+
+    {
+        let boot = &mut data[start * 512..][..512];
+        boot[..11].copy_from_slice(b"\xEB\x76\x90EXFAT   ");
+        put_u64(boot, 64, start as u64);
+        put_u64(boot, 72, sectors as u64);
+        put_u32(boot, 80, FAT_OFFSET as u32);
+        put_u32(boot, 84, 8);
+        put_u32(boot, 88, HEAP_OFFSET as u32);
+        put_u32(boot, 92, CLUSTERS as u32);
+        put_u32(boot, 96, 4);
+        put_u32(boot, 100, 0x1234ABCD);
+        put_u16(boot, 104, 0x0100);
+        boot[108] = 9;
+        boot[110] = 1;
+        boot[111] = 0x80;
+        boot[510..512].copy_from_slice(&[0x55, 0xAA]);
+    }
+
+    let fat = (start + FAT_OFFSET) * 512;
+    let chain = [(0, 0xFFFFFFF8), (1, 0xFFFFFFFF), (2, 0xFFFFFFFF), (3, 0xFFFFFFFF),
+                 (4, 13), (13, 0xFFFFFFFF), (7, 10), (10, 8), (8, 0xFFFFFFFF)];
+    for &(cluster, next) in chain.iter() {
+        put_u32(&mut data, fat + cluster * 4, next);
+    }
+
+    let cluster = |n: usize| (start + HEAP_OFFSET + n - 2) * 512;
+
+    // Clusters 2 through 13 are in use.
+    put_u16(&mut data, cluster(2), 0x0FFF);
+
+    // A compressed up-case table mapping only ASCII letters.
+    let mut upcase = vec![0xFFFF, 0x61];
+    upcase.extend((0x61..0x7B).map(|c| c - 0x20));
+    upcase.extend(&[0xFFFF, 0xFF85]);
+    let upcase: Vec<u8> = upcase.iter().flat_map(|&u: &u16| vec![u as u8, (u >> 8) as u8]).collect();
+    data[cluster(3)..cluster(3) + upcase.len()].copy_from_slice(&upcase);
+
+    let mut root = vec![0u8; 32 * 3];
+    root[0] = 0x83;
+    root[1] = 8;
+    for (i, unit) in "Test Vol".encode_utf16().enumerate() {
+        put_u16(&mut root, 2 + 2 * i, unit);
+    }
+    root[32] = 0x81;
+    put_u32(&mut root, 32 + 20, 2);
+    put_u64(&mut root, 32 + 24, (CLUSTERS / 8) as u64);
+    root[64] = 0x82;
+    put_u32(&mut root, 64 + 4, exfat::upcase::table_checksum(&upcase));
+    put_u32(&mut root, 64 + 20, 3);
+    put_u64(&mut root, 64 + 24, upcase.len() as u64);
+
+    root.extend(exfat_entry_set("Hello.txt", 0x20, 5, 700, 700, true));
+    root.extend(exfat_entry_set("fragmented.bin", 0x20, 7, 1400, 1000, false));
+    root.extend(exfat_entry_set("Sub Dir", 0x10, 11, 512, 512, true));
+
+    let mut deleted = exfat_entry_set("gone.txt", 0x20, 0, 0, 0, false);
+    for entry in deleted.chunks_mut(32) {
+        entry[0] &= 0x7F;
+    }
+    root.extend(deleted);
+
+    let mut corrupt = exfat_entry_set("corrupt.txt", 0x20, 0, 0, 0, false);
+    corrupt[2] ^= 0xFF;
+    root.extend(corrupt);
+
+    data[cluster(4)..cluster(4) + 512].copy_from_slice(&root[..512]);
+    data[cluster(13)..cluster(13) + root.len() - 512].copy_from_slice(&root[512..]);
+
+    let mut sub = exfat_entry_set("Nested.TXT", 0x20, 12, 5, 5, true);
+    sub.extend(exfat_entry_set("A rather long file name.txt", 0x21, 0, 0, 0, false));
+    data[cluster(11)..cluster(11) + sub.len()].copy_from_slice(&sub);
+
+    let contents = mock_exfat_contents(1400);
+    data[cluster(5)..cluster(5) + 700].copy_from_slice(&contents[..700]);
+    for (part, &n) in contents[..1000].chunks(512).zip([7, 10, 8].iter()) {
+        data[cluster(n)..cluster(n) + part.len()].copy_from_slice(part);
+    }
+    data[cluster(12)..cluster(12) + 5].copy_from_slice(b"hello");
+
+    data
+}
+
+fn mock_exfat_contents(len: usize) -> Vec<u8> {
+    (0..len).map(|i| (i % 251) as u8).collect()
+}
+
+#[test]
+fn check_exfat_sizes() {
+    check_size!(exfat::BootSector, 512);
+    check_size!(exfat::dir::ExFatDirEntry, 32);
+}
+
+#[test]
+fn test_exfat_read() {
+    for &partitioned in [false, true].iter() {
+        let exfat = ExFat::from(Cursor::new(mock_exfat_image(partitioned)))
+            .expect("mock image mounts");
+        assert_eq!(exfat.borrow().volume_label(), "Test Vol");
+        assert_eq!(exfat.borrow().volume_serial(), 0x1234ABCD);
+
+        assert_eq!(entry_names(exfat.open_dir("/").unwrap()),
+                   vec!["Hello.txt", "Sub Dir", "fragmented.bin"]);
+        assert_eq!(entry_names(exfat.open_dir("/sub dir").unwrap()),
+                   vec!["A rather long file name.txt", "Nested.TXT"]);
+
+        let contents = mock_exfat_contents(1400);
+        let mut read = Vec::new();
+        exfat.open_file("/HELLO.TXT").unwrap().read_to_end(&mut read).unwrap();
+        assert!(read[..] == contents[..700], "contiguous file contents differ");
+
+        read.clear();
+        exfat.open_file("/fragmented.bin").unwrap().read_to_end(&mut read).unwrap();
+        assert!(read[..1000] == contents[..1000], "chained file contents differ");
+        assert!(read[1000..].iter().all(|&b| b == 0) && read.len() == 1400,
+                "bytes past the valid data length are not zero");
+
+        read.clear();
+        exfat.open_file("/Sub Dir/../sub dir/nested.txt").unwrap().read_to_end(&mut read).unwrap();
+        assert_eq!(read, b"hello");
+
+        let entry = exfat.open("/Sub Dir/A RATHER LONG FILE NAME.TXT").unwrap();
+        assert!(entry.metadata().read_only());
+        assert_eq!(entry.into_file().unwrap().size(), 0);
+
+        let entry = exfat.open("/Hello.txt").unwrap();
+        let metadata = entry.metadata();
+        assert_eq!((metadata.created().year(), metadata.created().month()), (2018, 5));
+        assert_eq!((metadata.created().hour(), metadata.created().second()), (13, 31));
+        assert_eq!(metadata.modified().second(), 30);
+        assert_eq!(metadata.created().utc_offset_minutes(), Some(60));
+
+        expect_variant!(exfat.open("/gone.txt"), Err(ref e) if e.kind() == io::ErrorKind::NotFound);
+        expect_variant!(exfat.open("/corrupt.txt"), Err(ref e) if e.kind() == io::ErrorKind::NotFound);
+
+        let mut exfat = exfat.borrow_mut();
+        assert_eq!(exfat.free_clusters().unwrap(), 244);
+        assert!(exfat.is_allocated(13).unwrap());
+        assert!(!exfat.is_allocated(14).unwrap());
+    }
+}
+
+#[test]
+fn test_exfat_is_read_only() {
+    let exfat = ExFat::from(Cursor::new(mock_exfat_image(false))).unwrap();
+    let permission_denied = |result: io::Result<()>| {
+        expect_variant!(result, Err(ref e) if e.kind() == io::ErrorKind::PermissionDenied);
+    };
+
+    permission_denied(exfat.create_file("/new.txt").map(|_| ()));
+    permission_denied(exfat.create_dir("/new", false).map(|_| ()));
+    permission_denied(exfat.rename("/Hello.txt", "/Bye.txt"));
+    permission_denied(exfat.remove("/Hello.txt", false));
+    permission_denied(exfat.open_file("/Hello.txt").unwrap().write_all(b"x"));
+}
+
+#[test]
+fn test_exfat_bad_upcase_checksum() {
+    let mut image = mock_exfat_image(false);
+    // Corrupt the first unit of the up-case table in cluster 3.
+    image[33 * 512] ^= 0x01;
+    expect_variant!(ExFat::from(Cursor::new(image)), Err(exfat::Error::BadChecksum));
+}
+
+#[test]
+fn test_mount_picks_driver() {
+    expect_variant!(::mount(Cursor::new(mock_exfat_image(false))), Ok(::Volume::ExFat(_)));
+    expect_variant!(::mount(Cursor::new(mock_exfat_image(true))), Ok(::Volume::ExFat(_)));
+    expect_variant!(::mount(Cursor::new(mock_image(FatType::Fat16, true))), Ok(::Volume::Fat(_)));
+    expect_variant!(::mount(Cursor::new(mock_image(FatType::Fat12, false))), Ok(::Volume::Fat(_)));
+
+    // A type 0x07 partition holding something other than exFAT, such as NTFS.
+    let mut image = mock_exfat_image(true);
+    image[MOCK_PARTITION_START * 512 + 3..][..8].copy_from_slice(b"NTFS    ");
+    expect_variant!(::mount(Cursor::new(image)), Err(::MountError::NotFound));
+}
+
+/// The first LBA of the FAT32 volume in `mock_gpt_image`.
+const MOCK_GPT_FAT_START: usize = 64;
+
+/// Builds a GPT disk with 512-byte sectors holding a Linux partition followed
+/// by a Basic Data partition with a FAT32 volume at `MOCK_GPT_FAT_START`. The
+/// table has 128 entries and a backup at the end of the disk.
+fn mock_gpt_image() -> Vec<u8> {
+    use gpt::{crc32, Guid};
+
+    const LINUX_DATA: [u8; 16] = [0xAF, 0x3D, 0xC6, 0x0F, 0x83, 0x84, 0x72, 0x47,
+                                  0x8E, 0x79, 0x3D, 0x69, 0xD8, 0x47, 0x7D, 0xE4];
+
+    let volume = mock_image(FatType::Fat32, false);
+    let volume_sectors = volume.len() / 512;
+    let sectors = MOCK_GPT_FAT_START + volume_sectors + 33;
+    let mut data = vec![0u8; sectors * 512];
+    data[MOCK_GPT_FAT_START * 512..][..volume.len()].copy_from_slice(&volume);
+
+    data[446 + 4] = 0xEE;
+    put_u32(&mut data, 446 + 8, 1);
+    put_u32(&mut data, 446 + 12, sectors as u32 - 1);
+    data[510..512].copy_from_slice(&[0x55, 0xAA]);
+
+    let mut entries = vec![0u8; 128 * 128];
+    let partitions = [(LINUX_DATA, 40, MOCK_GPT_FAT_START - 1, "linux"),
+                      (Guid::BASIC_DATA.0, MOCK_GPT_FAT_START,
+                       MOCK_GPT_FAT_START + volume_sectors - 1, "FAT volume")];
+    for (i, &(type_guid, first, last, name)) in partitions.iter().enumerate() {
+        let entry = &mut entries[i * 128..][..128];
+        entry[..16].copy_from_slice(&type_guid);
+        entry[16] = i as u8 + 1;
+        put_u64(entry, 32, first as u64);
+        put_u64(entry, 40, last as u64);
+        for (j, unit) in name.encode_utf16().enumerate() {
+            put_u16(entry, 56 + 2 * j, unit);
+        }
+    }
+
+    let entries_crc = crc32(&entries);
+    let backup_lba = sectors - 1;
+    for &(my_lba, alternate_lba, entries_lba) in [(1, backup_lba, 2),
+                                                  (backup_lba, 1, backup_lba - 32)].iter() {
+        data[entries_lba * 512..][..entries.len()].copy_from_slice(&entries);
+
+        let header = &mut data[my_lba * 512..][..512];
+        header[..8].copy_from_slice(b"EFI PART");
+        put_u32(header, 8, 0x00010000);
+        put_u32(header, 12, 92);
+        put_u64(header, 24, my_lba as u64);
+        put_u64(header, 32, alternate_lba as u64);
+        put_u64(header, 40, 34);
+        put_u64(header, 48, backup_lba as u64 - 33);
+        header[56] = 0x42;
+        put_u64(header, 72, entries_lba as u64);
+        put_u32(header, 80, 128);
+        put_u32(header, 84, 128);
+        put_u32(header, 88, entries_crc);
+        let header_crc = crc32(&header[..92]);
+        put_u32(header, 16, header_crc);
+    }
+
+    data
+}
+
+#[test]
+fn test_gpt() {
+    use gpt::{GuidPartitionTable, Guid};
+
+    let gpt = GuidPartitionTable::from(Cursor::new(mock_gpt_image())).expect("valid GPT");
+    assert!(!gpt.is_from_backup());
+    assert_eq!(gpt.partitions().len(), 2);
+
+    let linux = &gpt.partitions()[0];
+    assert_eq!(linux.name, "linux");
+    assert_eq!(linux.type_guid.to_string(), "0FC63DAF-8483-4772-8E79-3D69D8477DE4");
+    assert!(!linux.is_data());
+
+    let fat = &gpt.partitions()[1];
+    assert_eq!(fat.name, "FAT volume");
+    assert_eq!(fat.type_guid, Guid::BASIC_DATA);
+    assert_eq!(fat.type_guid.to_string(), "EBD0A0A2-B9E5-4433-87C0-68B6B72699C7");
+    assert_eq!((fat.first_lba, fat.sectors()), (MOCK_GPT_FAT_START as u64, 4096));
+}
+
+#[test]
+fn test_gpt_backup() {
+    use gpt::{self, GuidPartitionTable};
+
+    // A corrupt primary entry array falls back to the backup table.
+    let mut image = mock_gpt_image();
+    image[2 * 512 + 56] ^= 0xFF;
+    let gpt = GuidPartitionTable::from(Cursor::new(image.clone())).expect("backup GPT");
+    assert!(gpt.is_from_backup());
+    assert_eq!(gpt.partitions()[1].name, "FAT volume");
+
+    // So does a corrupt primary header, found from the protective partition.
+    let mut image = mock_gpt_image();
+    image[512] ^= 0xFF;
+    let gpt = GuidPartitionTable::from(Cursor::new(image.clone())).expect("backup GPT");
+    assert!(gpt.is_from_backup());
+
+    let backup = image.len() - 512;
+    image[backup + 16] ^= 0xFF;
+    expect_variant!(GuidPartitionTable::from(Cursor::new(image)), Err(gpt::Error::BadChecksum));
+
+    expect_variant!(GuidPartitionTable::from(Cursor::new(mock_fat32_image())),
+                    Err(gpt::Error::NotProtective));
+
+    // A protective partition with no sectors is malformed.
+    let mut image = mock_gpt_image();
+    put_u32(&mut image, 446 + 8, 0);
+    put_u32(&mut image, 446 + 12, 0);
+    expect_variant!(GuidPartitionTable::from(Cursor::new(image)),
+                    Err(gpt::Error::NotProtective));
+}
+
+#[test]
+fn test_vfat_on_gpt() {
+    let image = SharedImage::new(mock_gpt_image());
+    {
+        let vfat = VFat::from(image.clone()).expect("GPT image mounts");
+        let mut file = vfat.open_file("/LOG.TXT").unwrap();
+        file.write_all(b"on gpt").unwrap();
+        file.sync().unwrap();
+    }
+
+    let volume = image.0.lock().unwrap().get_ref()[MOCK_GPT_FAT_START * 512..].to_vec();
+    let vfat = VFat::from(Cursor::new(volume)).expect("volume mounts on its own");
+    let mut read = String::new();
+    vfat.open_file("/LOG.TXT").unwrap().read_to_string(&mut read).unwrap();
+    assert_eq!(read, "on gpt");
+
+    expect_variant!(::mount(Cursor::new(mock_gpt_image())), Ok(::Volume::Fat(_)));
+}
+
+/// The first sector of the FAT32 logical partition in `mock_ebr_image`.
+const MOCK_LOGICAL_FAT_START: usize = 56;
+
+/// Builds a disk whose MBR holds a Linux partition and an extended partition
+/// at sector 16. The extended partition chains two EBRs: the first, at sector
+/// 16, describes a Linux logical partition; the second, at sector 48,
+/// describes a FAT32 logical partition at `MOCK_LOGICAL_FAT_START`.
+fn mock_ebr_image() -> Vec<u8> {
+    fn put_entry(data: &mut [u8], record: usize, index: usize, kind: u8, start: u32, len: u32) {
+        let entry = record * 512 + 446 + index * 16;
+        data[entry + 4] = kind;
+        put_u32(data, entry + 8, start);
+        put_u32(data, entry + 12, len);
+        data[record * 512 + 510..][..2].copy_from_slice(&[0x55, 0xAA]);
+    }
+
+    let volume = mock_image(FatType::Fat32, false);
+    let sectors = MOCK_LOGICAL_FAT_START + volume.len() / 512;
+    let mut data = vec![0u8; sectors * 512];
+    data[MOCK_LOGICAL_FAT_START * 512..].copy_from_slice(&volume);
+
+    put_entry(&mut data, 0, 0, 0x83, 8, 8);
+    put_entry(&mut data, 0, 1, 0x0F, 16, sectors as u32 - 16);
+    put_entry(&mut data, 16, 0, 0x83, 8, 8);
+    put_entry(&mut data, 16, 1, 0x05, 32, sectors as u32 - 48);
+    put_entry(&mut data, 48, 0, 0x0C, 8, volume.len() as u32 / 512);
+    data
+}
+
+#[test]
+fn test_mbr_logical_partitions() {
+    let mut image = mock_ebr_image();
+    let mbr = MasterBootRecord::from(Cursor::new(&mut image[..])).unwrap();
+    let partitions: Vec<(u8, u32)> = mbr.all_partitions(Cursor::new(&mut image[..]))
+        .map(|p| p.map(|p| (p.partition_type, p.relative_sector)))
+        .collect::<Result<_, _>>()
+        .expect("EBR chain is valid");
+
+    assert_eq!(partitions, vec![(0x83, 8), (0x0F, 16), (0x83, 24),
+                                (0x0C, MOCK_LOGICAL_FAT_START as u32)]);
+
+    let vfat = VFat::from(Cursor::new(image.clone())).expect("logical FAT32 mounts");
+    assert_eq!(entry_names(vfat.open_dir("/").unwrap()), vec!["LOG.TXT"]);
+
+    // The volume `mount()` selects is the one it mounts, and nothing outside
+    // of that partition is written.
+    let shared = SharedImage::new(image.clone());
+    let vfat = match ::mount(shared.clone()) {
+        Ok(::Volume::Fat(vfat)) => vfat,
+        other => panic!("logical FAT32 not mounted: {:?}", other),
+    };
+    vfat.create_file("/new.txt").unwrap().write_all(b"logical").unwrap();
+    vfat.borrow_mut().flush().unwrap();
+    let written = shared.0.lock().unwrap().get_ref().clone();
+    assert!(written[..MOCK_LOGICAL_FAT_START * 512] == image[..MOCK_LOGICAL_FAT_START * 512]);
+    let volume = written[MOCK_LOGICAL_FAT_START * 512..].to_vec();
+    let vfat = VFat::from(Cursor::new(volume)).unwrap();
+    assert_eq!(entry_names(vfat.open_dir("/").unwrap()), vec!["LOG.TXT", "new.txt"]);
+}
+
+#[test]
+fn test_mbr_ebr_loop() {
+    // Point the second EBR's link back at itself and hide its FAT volume, so
+    // the chain is followed until the loop is detected.
+    let mut image = mock_ebr_image();
+    image[48 * 512 + 446 + 4] = 0x83;
+    image[48 * 512 + 446 + 16 + 4] = 0x05;
+    put_u32(&mut image, 48 * 512 + 446 + 16 + 8, 32);
+
+    let mbr = MasterBootRecord::from(Cursor::new(&mut image[..])).unwrap();
+    let last = mbr.all_partitions(Cursor::new(&mut image[..])).last().unwrap();
+    expect_variant!(last, Err(::mbr::Error::BadEbrChain));
+    expect_variant!(VFat::from(Cursor::new(image)), Err(::vfat::Error::Mbr(::mbr::Error::BadEbrChain)));
+}
+
+#[test]
+fn test_cache_lru_eviction() {
+    use vfat::{CachedDevice, CacheStats};
+
+    let image = SharedImage::new(vec![0u8; 16 * 512]);
+    let mut cache = CachedDevice::with_capacity(image.clone(), 2);
+
+    cache.get_mut(0).unwrap()[0] = 0xAB;
+    cache.get(1).unwrap();
+    cache.get(0).unwrap();
+
+    // Sector 1 is the least recently used and clean; it is dropped silently.
+    cache.get(2).unwrap();
+    assert_eq!(image.0.lock().unwrap().get_ref()[0], 0);
+
+    // Sector 0 is now the least recently used and is written back.
+    cache.get(3).unwrap();
+    assert_eq!(image.0.lock().unwrap().get_ref()[0], 0xAB);
+
+    let stats = CacheStats { hits: 1, misses: 4, evictions: 2, writebacks: 1, prefetched: 0 };
+    assert_eq!(cache.stats(), stats);
+    assert_eq!(cache.stats().to_string(),
+               "hits: 1, misses: 4 (20.0% hit rate), evictions: 2, write-backs: 1, prefetched: 0");
+
+    cache.get_mut(3).unwrap()[1] = 0xCD;
+    cache.set_capacity(1).unwrap();
+    cache.flush().unwrap();
+    assert_eq!(image.0.lock().unwrap().get_ref()[3 * 512 + 1], 0xCD);
+    assert_eq!(cache.stats().evictions, 3);
+}
+
+#[test]
+fn test_vfat_small_cache() {
+    let image = SharedImage::new(mock_fat32_image());
+    let contents: Vec<u8> = (0..20000u32).map(|i| (i % 241) as u8).collect();
+    {
+        let vfat = VFat::from(image.clone()).expect("mock image mounts");
+        vfat.borrow_mut().set_cache_capacity(4).unwrap();
+
+        let mut file = vfat.open_file("/LOG.TXT").unwrap();
+        file.write_all(&contents).unwrap();
+        file.sync().unwrap();
+
+        let stats = vfat.borrow().cache_stats();
+        assert!(stats.evictions > 0 && stats.writebacks > 0, "{}", stats);
+    }
+
+    let vfat = VFat::from(image).expect("image remounts");
+    let mut read = Vec::new();
+    vfat.open_file("/LOG.TXT").unwrap().read_to_end(&mut read).unwrap();
+    assert!(read == contents, "file contents differ after remount");
+}
+
+#[test]
+fn test_block_device_multi_sector() {
+    let mut cursor = Cursor::new(vec![0u8; 8 * 512]);
+    let data: Vec<u8> = (0..3 * 512 + 100).map(|i| (i % 199) as u8).collect();
+    assert_eq!(cursor.write_sectors(2, &data).unwrap(), 3 * 512);
+
+    // A trailing partial sector is neither written nor read.
+    let mut buf = vec![0xFFu8; 3 * 512 + 100];
+    assert_eq!(cursor.read_sectors(2, &mut buf).unwrap(), 3 * 512);
+    assert!(buf[..3 * 512] == data[..3 * 512]);
+    assert!(cursor.get_ref()[5 * 512..].iter().all(|&b| b == 0));
+
+    // The default implementations transfer one sector at a time.
+    let mut image = SharedImage::new(cursor.into_inner());
+    let mut buf = vec![0u8; 4 * 512];
+    assert_eq!(image.read_sectors(1, &mut buf).unwrap(), 4 * 512);
+    assert!(buf[512..] == data[..3 * 512]);
+    assert_eq!(image.write_sectors(6, &buf[..2 * 512]).unwrap(), 2 * 512);
+    assert!(image.0.lock().unwrap().get_ref()[6 * 512..] == buf[..2 * 512]);
+}
+
+/// A device that counts the read transfers made to the underlying image.
+struct CountingDevice(Cursor<Vec<u8>>, ::std::sync::Arc<::std::sync::atomic::AtomicUsize>);
+
+impl BlockDevice for CountingDevice {
+    fn read_sector(&mut self, n: u64, buf: &mut [u8]) -> io::Result<usize> {
+        self.1.fetch_add(1, ::std::sync::atomic::Ordering::SeqCst);
+        self.0.read_sector(n, buf)
+    }
+
+    fn write_sector(&mut self, n: u64, buf: &[u8]) -> io::Result<usize> {
+        self.0.write_sector(n, buf)
+    }
+
+    fn read_sectors(&mut self, n: u64, buf: &mut [u8]) -> io::Result<usize> {
+        self.1.fetch_add(1, ::std::sync::atomic::Ordering::SeqCst);
+        self.0.read_sectors(n, buf)
+    }
+}
+
+#[test]
+fn test_cache_read_ahead() {
+    use std::sync::Arc;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use vfat::CachedDevice;
+
+    let data: Vec<u8> = (0..40 * 512).map(|i| (i / 512) as u8).collect();
+    let read_all = |read_ahead: usize| {
+        let transfers = Arc::new(AtomicUsize::new(0));
+        let device = CountingDevice(Cursor::new(data.clone()), transfers.clone());
+        let mut cache = CachedDevice::with_capacity(device, 64);
+        cache.set_read_ahead(read_ahead);
+
+        for sector in 0..40 {
+            assert_eq!(cache.get(sector).unwrap()[0], sector as u8);
+        }
+
+        (transfers.load(Ordering::SeqCst), cache.stats())
+    };
+
+    // Sector 0 is read alone, then sectors are read in runs of nine. Near the
+    // end of the device a run would extend past it and falls back to a single
+    // sector.
+    let (transfers, stats) = read_all(8);
+    assert_eq!(stats.misses + stats.hits, 40);
+    assert_eq!(stats.prefetched, 32);
+    assert!(transfers < 20, "{} transfers with read-ahead", transfers);
+
+    let (transfers, stats) = read_all(0);
+    assert_eq!((transfers, stats.misses, stats.prefetched), (40, 40, 0));
+}
+
+/// Mounts a FAT32 mock volume holding `/a.txt` (3 clusters), `/dir/b.txt` (2
+/// clusters), and `/A long file name.txt` whose FAT copies are in sync.
+fn mock_check_volume() -> Shared<VFat> {
+    let vfat = VFat::from(Cursor::new(mock_fat32_image())).expect("mock image mounts");
+    vfat.create_dir("/dir", false).unwrap();
+    for &(path, contents) in &[("/a.txt", &[1; 1500][..]), ("/dir/b.txt", &[2; 1000][..]),
+                               ("/A long file name.txt", &b"long"[..])] {
+        let mut file = vfat.create_file(path).unwrap();
+        file.write_all(contents).unwrap();
+        file.sync().unwrap();
+    }
+
+    let report = check::check(&vfat, check::Mode::Repair).expect("check");
+    assert_eq!(report.unrepaired(), 0, "{}", report);
+    assert!(check::check(&vfat, check::Mode::ReadOnly).unwrap().is_clean());
+    vfat
+}
+
+/// The clusters of the chain of the file at `path`.
+fn file_chain(vfat: &Shared<VFat>, path: &str) -> Vec<::vfat::Cluster> {
+    let mut chain = Vec::new();
+    let mut cluster = vfat.open_file(path).unwrap().first_cluster;
+    while let Some(current) = cluster {
+        chain.push(current);
+        cluster = vfat.borrow_mut().next_cluster(current).unwrap();
+    }
+
+    chain
+}
+
+/// The problems in `report`.
+fn chain_problems(report: &check::Report) -> Vec<check::Problem> {
+    report.issues.iter().map(|issue| issue.problem.clone()).collect()
+}
+
+#[test]
+fn test_check_fat_mismatch() {
+    let vfat = VFat::from(Cursor::new(mock_fat32_image())).expect("mock image mounts");
+    let report = check::check(&vfat, check::Mode::ReadOnly).unwrap();
+    assert!(report.is_clean(), "{}", report);
+    assert_eq!((report.files, report.dirs), (1, 1));
+
+    // FAT writes are mirrored, so only a copy changed directly differs.
+    let mut file = vfat.open_file("/LOG.TXT").unwrap();
+    file.write_all(&[0; 600]).unwrap();
+    file.sync().unwrap();
+    assert!(check::compare_fats(&vfat).unwrap().is_empty());
+
+    vfat.borrow_mut().fat_sector_mut(1, 0).unwrap()[20] = 0xAB;
+    assert_eq!(check::compare_fats(&vfat).unwrap(),
+               vec![check::Problem::FatMismatch { fat: 1, sectors: 1 }]);
+    let report = check::check(&vfat, check::Mode::ReadOnly).unwrap();
+    assert_eq!(report.issues, vec![check::Issue {
+        problem: check::Problem::FatMismatch { fat: 1, sectors: 1 },
+        repaired: false,
+    }]);
+
+    let report = check::check(&vfat, check::Mode::Repair).unwrap();
+    assert_eq!(report.unrepaired(), 0);
+    assert!(check::check(&vfat, check::Mode::ReadOnly).unwrap().is_clean());
+}
+
+#[test]
+fn test_check_chain_faults() {
+    use check::Problem::*;
+
+    let vfat = mock_check_volume();
+    let a = file_chain(&vfat, "/a.txt");
+    let b = file_chain(&vfat, "/dir/b.txt");
+    assert_eq!((a.len(), b.len()), (3, 2));
+
+    {
+        let mut vfat = vfat.borrow_mut();
+        vfat.set_fat_entry(b[1], b[0].number()).unwrap();
+        vfat.set_fat_entry(a[2], b[0].number()).unwrap();
+        vfat.set_fat_entry(::vfat::Cluster::from(200), 201).unwrap();
+        vfat.set_fat_entry(::vfat::Cluster::from(201), 0x0FFFFFFF).unwrap();
+    }
+
+    // `/dir` is checked before `/a.txt` as it was created first.
+    let expected = vec![
+        Cycle { path: "/dir/b.txt".to_string(), cluster: b[0].number() },
+        CrossLinked { path: "/a.txt".to_string(), cluster: b[0].number() },
+        LostChain { start: 200, clusters: 2 },
+    ];
+
+    let report = check::check(&vfat, check::Mode::ReadOnly).unwrap();
+    assert_eq!(chain_problems(&report), expected);
+    assert_eq!(report.unrepaired(), report.issues.len());
+
+    let report = check::check(&vfat, check::Mode::Repair).unwrap();
+    assert_eq!(chain_problems(&report), expected);
+    assert_eq!(report.unrepaired(), 0, "{}", report);
+
+    let report = check::check(&vfat, check::Mode::ReadOnly).unwrap();
+    assert!(report.is_clean(), "{}", report);
+    assert_eq!(file_chain(&vfat, "/a.txt"), a);
+    assert_eq!(file_chain(&vfat, "/dir/b.txt"), b);
+    let lost = vfat.borrow_mut().fat_entry(::vfat::Cluster::from(200)).unwrap();
+    assert_eq!(lost.status(), ::vfat::Status::Free);
+}
+
+#[test]
+fn test_check_sizes_and_lfn_checksums() {
+    use check::Problem::*;
+
+    let vfat = mock_check_volume();
+    let a = vfat.open_file("/a.txt").unwrap().location;
+    let b = vfat.open_file("/dir/b.txt").unwrap().location;
+    let long = vfat.open_file("/A long file name.txt").unwrap().location;
+    let short_name = {
+        let mut vfat = vfat.borrow_mut();
+        for &(location, size) in &[(a, 5000), (b, 10)] {
+            let mut entry = vfat.read_dir_entry(location).unwrap();
+            entry.set_size(size);
+            vfat.write_dir_entry(location, &entry).unwrap();
+        }
+
+        // Break the checksum of the LFN entry directly before the entry.
+        let entry = vfat.read_dir_entry(long).unwrap();
+        let checksum = ::vfat::dir::short_name_checksum(&entry.raw_name());
+        vfat.write_raw_dir_entry(long.dir, long.offset - 32 + 13, &[checksum ^ 0xFF]).unwrap();
+        entry.short_name(&::vfat::CP437)
+    };
+
+    let expected = vec![
+        SizeMismatch { path: "/dir/b.txt".to_string(), size: 10, clusters: 2 },
+        SizeMismatch { path: "/a.txt".to_string(), size: 5000, clusters: 3 },
+        BadLfnChecksum { path: format!("/{}", short_name), entries: 2 },
+    ];
+
+    let report = check::check(&vfat, check::Mode::Repair).unwrap();
+    assert_eq!(chain_problems(&report), expected);
+    assert_eq!(report.unrepaired(), 0, "{}", report);
+
+    let report = check::check(&vfat, check::Mode::ReadOnly).unwrap();
+    assert!(report.is_clean(), "{}", report);
+    assert_eq!(vfat.open_file("/a.txt").unwrap().size(), 1536);
+    assert_eq!(file_chain(&vfat, "/dir/b.txt").len(), 1);
+    vfat.open_file(format!("/{}", short_name)).expect("short name remains");
+    expect_variant!(vfat.open("/A long file name.txt"),
+                    Err(ref e) if e.kind() == io::ErrorKind::NotFound);
+}
+
+#[test]
+fn test_format() {
+    for &partition in &[false, true] {
+        let options = ::FormatOptions {
+            volume_label: Some("scratch".to_string()),
+            volume_id: 0xCAFEF00D,
+            partition: partition,
+            ..::FormatOptions::default()
+        };
+
+        let mut data = vec![0xAAu8; 8192 * 512];
+        ::format(Cursor::new(&mut data[..]), 8192, &options).expect("format");
+
+        let start = if partition { 2048 } else { 0 };
+        let ebpb = BiosParameterBlock::from(Cursor::new(&mut data[..]), start).unwrap();
+        assert_eq!(ebpb.fat_type(), FatType::Fat32);
+        assert_eq!((ebpb.sectors_per_cluster, ebpb.num_fats), (1, 2));
+        assert_eq!(ebpb.volume_id(), 0xCAFEF00D);
+        assert_eq!(&ebpb.volume_label(), b"SCRATCH    ");
+        let (boot, backup) = (start as usize * 512, (start as usize + 6) * 512);
+        assert!(data[boot..boot + 512] == data[backup..backup + 512]);
+        assert_eq!(&data[boot + 512..boot + 516], b"RRaA");
+
+        let image = SharedImage::new(data);
+        {
+            let vfat = VFat::from(image.clone()).expect("formatted image mounts");
+            assert!(check::check(&vfat, check::Mode::ReadOnly).unwrap().is_clean());
+            assert!(entry_names(vfat.open_dir("/").unwrap()).is_empty());
+
+            let mut file = vfat.create_file("/hello.txt").unwrap();
+            file.write_all(&[7; 3000]).unwrap();
+            file.sync().unwrap();
+        }
+
+        let vfat = VFat::from(image).expect("image remounts");
+        assert_eq!(entry_names(vfat.open_dir("/").unwrap()), vec!["hello.txt"]);
+        assert_eq!(vfat.open_file("/hello.txt").unwrap().size(), 3000);
+    }
+}
+
+#[test]
+fn test_format_options() {
+    let mut data = vec![0u8; 8192 * 512];
+    let options = ::FormatOptions { cluster_size: Some(4096), ..::FormatOptions::default() };
+    ::format(Cursor::new(&mut data[..]), 8192, &options).expect("format");
+    let ebpb = BiosParameterBlock::from(Cursor::new(&mut data[..]), 0).unwrap();
+    assert_eq!(ebpb.sectors_per_cluster, 8);
+    assert_eq!(&ebpb.volume_label(), b"NO NAME    ");
+
+    let invalid = [
+        (8192, ::FormatOptions { cluster_size: Some(1000), ..::FormatOptions::default() }),
+        (8192, ::FormatOptions { cluster_size: Some(65536), ..::FormatOptions::default() }),
+        (8192, ::FormatOptions { cluster_size: Some(0), ..::FormatOptions::default() }),
+        (8192, ::FormatOptions { cluster_size: Some(256), ..::FormatOptions::default() }),
+        (8192, ::FormatOptions {
+            volume_label: Some("much too long".to_string()),
+            ..::FormatOptions::default()
+        }),
+        (8192, ::FormatOptions {
+            volume_label: Some("a/b".to_string()),
+            ..::FormatOptions::default()
+        }),
+        (32, ::FormatOptions::default()),
+        (2048, ::FormatOptions { partition: true, ..::FormatOptions::default() }),
+    ];
+
+    for &(sectors, ref options) in invalid.iter() {
+        expect_variant!(::format(Cursor::new(&mut data[..]), sectors, options),
+                        Err(ref e) if e.kind() == io::ErrorKind::InvalidInput);
+    }
+}
+
+#[test]
+fn test_fsinfo() {
+    let mut data = vec![0u8; 8192 * 512];
+    ::format(Cursor::new(&mut data[..]), 8192, &::FormatOptions::default()).unwrap();
+    let image = SharedImage::new(data);
+
+    let total = {
+        let vfat = VFat::from(image.clone()).unwrap();
+        let total = vfat.borrow().total_clusters();
+        assert_eq!(vfat.borrow_mut().free_clusters().unwrap(), total - 1);
+
+        // 3000 bytes take 6 of the 512-byte clusters, from cluster 3 onwards.
+        let mut file = vfat.create_file("/data.bin").unwrap();
+        file.write_all(&[1; 3000]).unwrap();
+        file.sync().unwrap();
+        assert_eq!(vfat.borrow_mut().free_clusters().unwrap(), total - 7);
+        total
+    };
+
+    let fsinfo = FsInfo::from(image.clone(), 1).unwrap();
+    assert_eq!((fsinfo.free_count(), fsinfo.next_free()), (Some(total - 7), Some(9)));
+
+    // Unknown values are recomputed, and an allocation starts at the hint.
+    let mut fsinfo = FsInfo::new(None, Some(100));
+    image.clone().write_sector(1, &fsinfo.to_bytes()).unwrap();
+    {
+        let vfat = VFat::from(image.clone()).unwrap();
+        assert_eq!(vfat.borrow_mut().free_clusters().unwrap(), total - 7);
+
+        let mut file = vfat.create_file("/more.bin").unwrap();
+        file.write_all(&[2; 10]).unwrap();
+        file.sync().unwrap();
+        assert_eq!(vfat.open_file("/more.bin").unwrap().first_cluster,
+                   Some(::vfat::Cluster::from(100)));
+    }
+
+    fsinfo = FsInfo::from(image.clone(), 1).unwrap();
+    assert_eq!((fsinfo.free_count(), fsinfo.next_free()), (Some(total - 8), Some(101)));
+
+    // A structure with a bad signature is ignored.
+    let mut raw = fsinfo.to_bytes();
+    raw[0] = 0;
+    image.clone().write_sector(1, &raw).unwrap();
+    expect_variant!(FsInfo::from(image.clone(), 1), Err(::vfat::Error::BadSignature));
+    let vfat = VFat::from(image.clone()).unwrap();
+    assert_eq!(vfat.borrow_mut().free_clusters().unwrap(), total - 8);
+
+    // A count computed on a read-only mount is not written back.
+    image.clone().write_sector(1, &FsInfo::new(None, Some(100)).to_bytes()).unwrap();
+    let before = image.0.lock().unwrap().get_ref().clone();
+    let options = ::vfat::MountOptions { read_only: true, ..Default::default() };
+    let vfat = VFat::with_options(image.clone(), options).unwrap();
+    assert_eq!(vfat.borrow_mut().free_clusters().unwrap(), total - 8);
+    vfat.borrow_mut().unmount().unwrap();
+    assert!(*image.0.lock().unwrap().get_ref() == before);
+}
+
+#[test]
+fn test_free_clusters_without_fsinfo() {
+    let vfat = VFat::from(Cursor::new(mock_image(FatType::Fat16, false))).unwrap();
+    let total = vfat.borrow().total_clusters();
+    assert_eq!(vfat.borrow_mut().free_clusters().unwrap(), total);
+
+    let mut file = vfat.open_file("/LOG.TXT").unwrap();
+    file.write_all(&[0; 1000]).unwrap();
+    assert_eq!(vfat.borrow_mut().free_clusters().unwrap(), total - 2);
+}
+
+/// The raw 32-byte directory entries of the entry for `path`: its LFN entries
+/// in on-disk order followed by its regular entry.
+fn raw_entries_of(vfat: &Shared<VFat>, path: &str) -> Vec<[u8; 32]> {
+    let location = match vfat.open(path).unwrap() {
+        ::vfat::Entry::File(file) => file.location,
+        ::vfat::Entry::Dir(dir) => dir.location.unwrap(),
+    };
+
+    let mut vfat = vfat.borrow_mut();
+    (0..location.lfn_entries as u64 + 1).rev().map(|i| {
+        let mut at = location;
+        at.offset -= i * 32;
+        vfat.read_dir_entry(at).unwrap().to_bytes()
+    }).collect()
+}
+
+#[test]
+fn test_short_names() {
+    let vfat = VFat::from(Cursor::new(mock_fat32_image())).unwrap();
+
+    // (name, short name, NT case flags, LFN entries)
+    let cases: &[(&str, &[u8; 11], u8, usize)] = &[
+        ("readme.txt", b"README  TXT", 0x18, 0),
+        ("CONFIG.SYS", b"CONFIG  SYS", 0x00, 0),
+        ("notes.TXT", b"NOTES   TXT", 0x08, 0),
+        ("Makefile", b"MAKEFILE   ", 0x00, 1),
+        ("Hello.Txt", b"HELLO   TXT", 0x00, 1),
+        ("index.html", b"INDEX~1 HTM", 0x00, 1),
+        ("a.b.c", b"AB~1    C  ", 0x00, 1),
+        (".profile", b"PROFIL~1   ", 0x00, 1),
+        ("my file+name.text", b"MYFILE~1TEX", 0x00, 2),
+    ];
+
+    for &(name, short, flags, lfn_entries) in cases {
+        vfat.create_file(format!("/{}", name)).unwrap();
+        let raw = raw_entries_of(&vfat, &format!("/{}", name));
+        let regular = raw.last().unwrap();
+        assert_eq!((&regular[..11], regular[12], raw.len() - 1),
+                   (&short[..], flags, lfn_entries), "{}", name);
+    }
+
+    let mut names: Vec<&str> = cases.iter().map(|case| case.0).collect();
+    names.push("LOG.TXT");
+    names.sort();
+    assert_eq!(entry_names(vfat.open_dir("/").unwrap()), names);
+}
+
+#[test]
+fn test_short_name_tails() {
+    let vfat = VFat::from(Cursor::new(mock_fat32_image())).unwrap();
+    let mut aliases = Vec::new();
+    for i in 0..7 {
+        let path = format!("/Long File Name {}.txt", i);
+        vfat.create_file(&path).unwrap();
+        let raw = raw_entries_of(&vfat, &path);
+        aliases.push(raw.last().unwrap()[..11].to_vec());
+
+        // Sequence numbers count down to 1, the first marked as the last
+        // part of the name, and every entry carries the alias's checksum.
+        let checksum = ::vfat::dir::short_name_checksum(&{
+            let mut name = [0u8; 11];
+            name.copy_from_slice(&raw.last().unwrap()[..11]);
+            name
+        });
+        assert_eq!(raw.len(), 3);
+        assert_eq!((raw[0][0], raw[1][0]), (0x42, 0x01));
+        assert!(raw[..2].iter().all(|lfn| lfn[11] == 0x0F && lfn[13] == checksum));
+    }
+
+    for (i, alias) in aliases[..4].iter().enumerate() {
+        assert_eq!(&alias[..], format!("LONGFI~{}TXT", i + 1).as_bytes());
+    }
+
+    // Once the numeric tails run out, a hash of the long name is used.
+    for alias in &aliases[4..] {
+        assert_eq!(&alias[..2], b"LO");
+        assert!(alias[2..6].iter().all(|b| (*b as char).is_digit(16)), "{:?}", alias);
+        assert_eq!(&alias[6..], b"~1TXT");
+    }
+
+    assert!(aliases[4] != aliases[5] && aliases[5] != aliases[6]);
+    vfat.open_file("/long file name 6.TXT").expect("long name is found");
+}
+
+#[test]
+fn test_lazy_dir_entries() {
+    let vfat = VFat::from(Cursor::new(mock_fat32_image())).unwrap();
+    let dir = vfat.create_dir("/many", false).unwrap();
+
+    // Long names take several entries each, so some sets of LFN entries
+    // straddle a cluster boundary.
+    let mut expected = vec![".".to_string(), "..".to_string()];
+    for i in 0..120 {
+        let name = format!("a rather long file name {:03}.txt", i);
+        vfat.create_file(format!("/many/{}", name)).unwrap();
+        expected.push(name);
+    }
+
+    expected.sort();
+    assert_eq!(entry_names(vfat.open_dir("/many").unwrap()), expected);
+    vfat.open_file("/many/A Rather Long File Name 119.TXT").expect("last entry is found");
+
+    // A broken chain surfaces as an error rather than a panic.
+    let first = dir.first_cluster;
+    let second = vfat.borrow_mut().next_cluster(first).unwrap().expect("chain spans clusters");
+    vfat.borrow_mut().set_fat_entry(second, 0).unwrap();
+
+    let mut entries = vfat.open_dir("/many").unwrap().entries().unwrap();
+    let mut error = None;
+    loop {
+        match entries.try_next() {
+            Ok(Some(_)) => continue,
+            Ok(None) => break,
+            Err(e) => { error = Some(e); break; }
+        }
+    }
+
+    assert_eq!(error.expect("broken chain is reported").kind(), io::ErrorKind::InvalidData);
+    assert!(entries.try_next().unwrap().is_none());
+
+    // Iterating through `traits::Dir` ends with the error, as does a walk.
+    let entries: Vec<_> = vfat.open_dir("/many").unwrap().entries().unwrap().collect();
+    assert!(entries.len() < expected.len());
+    let error = entries.last().unwrap().as_ref().expect_err("broken chain is reported");
+    assert_eq!(error.kind(), io::ErrorKind::InvalidData);
+    assert!(::walk::walk(&vfat, "/").unwrap().any(|entry| entry.is_err()));
+
+    let missing = vfat.open_file("/many/a rather long file name 119.txt").unwrap_err();
+    assert_eq!(missing.kind(), io::ErrorKind::InvalidData);
+
+    // So does an entry referring to a cluster outside of the volume.
+    let location = vfat.open_file("/many/a rather long file name 000.txt").unwrap().location;
+    let mut raw = vfat.borrow_mut().read_dir_entry(location).unwrap().to_bytes();
+    raw[20..22].copy_from_slice(&[0xFF, 0x0F]);
+    vfat.borrow_mut().write_raw_dir_entry(location.dir, location.offset, &raw).unwrap();
+    let error = vfat.open_file("/many/a rather long file name 000.txt").unwrap_err();
+    assert_eq!(error.kind(), io::ErrorKind::InvalidData);
+}
+
+/// The `vfat::Error` carried by the chain fault `error`.
+fn chain_fault(error: io::Error) -> ::vfat::Error {
+    assert_eq!(error.kind(), io::ErrorKind::InvalidData);
+    *error.into_inner().expect("error has a cause")
+        .downcast::<::vfat::Error>().expect("cause is a vfat::Error")
+}
+
+#[test]
+fn test_chain_faults() {
+    let vfat = mock_check_volume();
+    let chain = file_chain(&vfat, "/a.txt");
+    let (first, last) = (chain[0], *chain.last().unwrap());
+    let total = vfat.borrow().total_clusters();
+    let read_all = |vfat: &Shared<VFat>| {
+        let mut data = Vec::new();
+        vfat.open_file("/a.txt").unwrap().read_to_end(&mut data)
+    };
+
+    let faults: Vec<(::vfat::Cluster, u32)> = vec![
+        (last, first.number()),
+        (first, total + 5),
+        (first, 0x0FFFFFF7),
+        (first, 0x0FFFFFF0),
+        (first, 0),
+    ];
+
+    for (i, &(cluster, value)) in faults.iter().enumerate() {
+        vfat.borrow_mut().set_fat_entry(cluster, value).unwrap();
+        let fault = match i {
+            // Reading the file stops at its size, so follow the whole chain.
+            0 => vfat.borrow_mut().read_chain(first, &mut Vec::new()).unwrap_err(),
+            _ => read_all(&vfat).unwrap_err(),
+        };
+
+        match (i, chain_fault(fault)) {
+            (0, ::vfat::Error::ChainCycle(n)) => assert!(chain.iter().any(|c| c.number() == n)),
+            (1, ::vfat::Error::ClusterOutOfRange(n)) => assert_eq!(n, total + 5),
+            (2, ::vfat::Error::BadCluster(n)) => assert_eq!(n, first.number()),
+            (3, ::vfat::Error::ReservedCluster(n)) => assert_eq!(n, first.number()),
+            (4, ::vfat::Error::ReservedCluster(n)) => assert_eq!(n, first.number()),
+            (i, error) => panic!("fault {}: unexpected error {:?}", i, error),
+        }
+
+        let restore = match i {
+            0 => 0x0FFFFFFF,
+            _ => chain[1].number(),
+        };
+        vfat.borrow_mut().set_fat_entry(cluster, restore).unwrap();
+        read_all(&vfat).expect("restored chain reads");
+    }
+
+    // A directory entry pointing past the data region is reported too.
+    let location = vfat.open_file("/dir/b.txt").unwrap().location;
+    let mut raw = vfat.borrow_mut().read_dir_entry(location).unwrap().to_bytes();
+    raw[26..28].copy_from_slice(&[0xFF, 0xFF]);
+    raw[20..22].copy_from_slice(&[0xFF, 0x00]);
+    vfat.borrow_mut().write_raw_dir_entry(location.dir, location.offset, &raw).unwrap();
+    let dir_cluster = vfat.open_dir("/dir").unwrap().first_cluster.number();
+    match chain_fault(vfat.open_file("/dir/b.txt").unwrap_err()) {
+        ::vfat::Error::CorruptEntry { cluster, .. } => assert_eq!(cluster, dir_cluster),
+        error => panic!("unexpected error {:?}", error),
+    }
+}
+
+#[test]
+fn test_vfat_errors() {
+    let start = MOCK_PARTITION_START as u64;
+    let mount_with = |offset: usize, bytes: &[u8]| {
+        let mut image = mock_fat32_image();
+        let at = MOCK_PARTITION_START * 512 + offset;
+        image[at..at + bytes.len()].copy_from_slice(bytes);
+        VFat::from(Cursor::new(image))
+    };
+
+    expect_variant!(mount_with(11, &[0x00, 0x03]),
+                    Err(::vfat::Error::BadBytesPerSector { sector, value: 768 }) if sector == start);
+    expect_variant!(mount_with(13, &[3]),
+                    Err(::vfat::Error::BadSectorsPerCluster { sector, value: 3 }) if sector == start);
+    expect_variant!(mount_with(16, &[0]),
+                    Err(::vfat::Error::BadFatCount { sector, value: 0 }) if sector == start);
+    expect_variant!(mount_with(42, &[0x01, 0x00]),
+                    Err(::vfat::Error::UnsupportedFatType { fat_type: FatType::Fat32, .. }));
+
+    let error = mount_with(13, &[0]).unwrap_err();
+    assert_eq!(error.to_string(), format!("BPB in sector {} has invalid sectors per cluster 0", start));
+
+    // Conversion into `io::Error` keeps the original error.
+    let error: io::Error = mount_with(16, &[0]).unwrap_err().into();
+    assert_eq!(error.kind(), io::ErrorKind::InvalidData);
+    expect_variant!(chain_fault(error), ::vfat::Error::BadFatCount { value: 0, .. });
+
+    let error: io::Error = ::vfat::Error::NotFound.into();
+    assert_eq!(error.kind(), io::ErrorKind::NotFound);
+    let io_error = io::Error::new(io::ErrorKind::UnexpectedEof, "short read");
+    let error: io::Error = ::vfat::Error::from(io_error).into();
+    assert_eq!((error.kind(), error.to_string()), (io::ErrorKind::UnexpectedEof, "short read".into()));
+}
+
+#[test]
+fn test_code_page_and_case_folding() {
+    let vfat = VFat::from(Cursor::new(mock_fat32_image())).unwrap();
+
+    // Short names are encoded in CP437; `É` is 0x90.
+    vfat.create_file("/café.txt").unwrap();
+    let raw = raw_entries_of(&vfat, "/café.txt");
+    assert_eq!((&raw.last().unwrap()[..11], raw.len()), (&b"CAF\x90    TXT"[..], 2));
+
+    // Names compare equal under Unicode simple case folding.
+    for &(name, other) in &[("café.txt", "CAFÉ.TXT"), ("σοφός.txt", "ΣΟΦΌΣ.TXT"),
+                            ("5µm.txt", "5ΜM.txt"), ("Ærø.txt", "ærØ.TXT")] {
+        if name != "café.txt" {
+            vfat.create_file(format!("/{}", name)).unwrap();
+        }
+
+        let file = vfat.open_file(format!("/{}", other)).expect(other);
+        assert_eq!(file.name, name);
+    }
+
+    assert!(vfat.create_file("/CAFÉ.TXT").is_err());
+    assert!(vfat.open_file("/STRASSE.txt").is_err());
+
+    // Short names are decoded through the code page, which can be replaced.
+    vfat.create_file("/X.TXT").unwrap();
+    let location = vfat.open_file("/X.TXT").unwrap().location;
+    let mut raw = vfat.borrow_mut().read_dir_entry(location).unwrap().to_bytes();
+    raw[..2].copy_from_slice(&[0x80, b'A']);
+    vfat.borrow_mut().write_raw_dir_entry(location.dir, location.offset, &raw).unwrap();
+    vfat.open_file("/çA.txt").expect("decoded with CP437");
+
+    let mut high = ['?'; 128];
+    high[0] = 'Ж';
+    vfat.borrow_mut().set_code_page(::vfat::CodePage::new(high));
+    vfat.open_file("/жa.txt").expect("decoded with the custom code page");
+    vfat.borrow_mut().set_code_page(::vfat::CP437);
+
+    // An unpaired surrogate in a long name is replaced rather than panicking.
+    vfat.create_file("/Long Name Here.txt").unwrap();
+    let location = vfat.open_file("/Long Name Here.txt").unwrap().location;
+    let mut first = raw_entries_of(&vfat, "/Long Name Here.txt")[1];
+    first[1..3].copy_from_slice(&[0x00, 0xD8]);
+    vfat.borrow_mut().write_raw_dir_entry(location.dir, location.offset - 32, &first).unwrap();
+    let names = entry_names(vfat.open_dir("/").unwrap());
+    assert!(names.contains(&"\u{FFFD}ong Name Here.txt".to_string()), "{:?}", names);
+}
+
+#[test]
+fn test_mount_read_only() {
+    let image = SharedImage::new(mock_fat32_image());
+    {
+        let vfat = VFat::from(image.clone()).unwrap();
+        let mut file = vfat.open_file("/log.txt").unwrap();
+        file.write_all(b"contents").unwrap();
+        file.sync().unwrap();
+    }
+
+    let before = image.0.lock().unwrap().get_ref().clone();
+    let options = ::vfat::MountOptions { read_only: true, check_dirty_flag: true, ..Default::default() };
+    let vfat = VFat::with_options(image.clone(), options).unwrap();
+
+    let denied = |result: io::Result<()>| {
+        assert_eq!(result.unwrap_err().kind(), io::ErrorKind::PermissionDenied);
+    };
+
+    denied(vfat.create_file("/new.txt").map(|_| ()));
+    denied(vfat.create_dir("/new", false).map(|_| ()));
+    denied(vfat.rename("/log.txt", "/moved.txt"));
+    denied(vfat.remove("/log.txt", false));
+    denied(vfat.open_file("/log.txt").unwrap().write_all(b"more"));
+
+    let mut contents = String::new();
+    vfat.open_file("/log.txt").unwrap().read_to_string(&mut contents).unwrap();
+    assert_eq!(contents, "contents");
+
+    vfat.borrow_mut().unmount().unwrap();
+    assert!(*image.0.lock().unwrap().get_ref() == before);
+}
+
+#[test]
+fn test_mount_dirty_flag() {
+    let image = SharedImage::new(mock_fat32_image());
+    let fat_1 = |image: &SharedImage| {
+        let offset = (MOCK_PARTITION_START + 32) * 512 + 4;
+        let data = image.0.lock().unwrap();
+        let bytes = &data.get_ref()[offset..offset + 4];
+        bytes[0] as u32 | (bytes[1] as u32) << 8 | (bytes[2] as u32) << 16 | (bytes[3] as u32) << 24
+    };
+
+    let options = ::vfat::MountOptions { check_dirty_flag: true, ..Default::default() };
+    let vfat = VFat::with_options(image.clone(), options).unwrap();
+    assert!(!vfat.borrow().was_dirty());
+    assert_eq!(fat_1(&image), 0x07FFFFFF);
+
+    // Mounting again before unmounting sees the volume in use.
+    assert!(VFat::with_options(image.clone(), options).unwrap().borrow().was_dirty());
+
+    vfat.create_file("/new.txt").unwrap();
+    vfat.borrow_mut().unmount().unwrap();
+    assert_eq!(fat_1(&image), 0x0FFFFFFF);
+    assert!(!VFat::with_options(image.clone(), options).unwrap().borrow().was_dirty());
+
+    // Without the option, the flag is neither read nor written.
+    let vfat = VFat::from(image.clone()).unwrap();
+    vfat.borrow_mut().unmount().unwrap();
+    assert_eq!(fat_1(&image), 0x07FFFFFF);
+}
+
+#[test]
+fn test_mount_access_date() {
+    fn clock() -> ::vfat::Timestamp {
+        ::vfat::Timestamp::new(2026, 10, 18, 0, 0, 0).unwrap()
+    }
+
+    let image = SharedImage::new(mock_fat32_image());
+    {
+        let vfat = VFat::from(image.clone()).unwrap();
+        let mut file = vfat.open_file("/log.txt").unwrap();
+        file.write_all(b"contents").unwrap();
+        file.sync().unwrap();
+        file.seek(io::SeekFrom::Start(0)).unwrap();
+        file.read_to_end(&mut Vec::new()).unwrap();
+        assert_eq!(vfat.open_file("/log.txt").unwrap().metadata.accessed.date, Default::default());
+    }
+
+    let options = ::vfat::MountOptions { update_access_date: true, clock: Some(clock),
+                                         ..Default::default() };
+    let vfat = VFat::with_options(image.clone(), options).unwrap();
+    let mut file = vfat.open_file("/log.txt").unwrap();
+    file.read_to_end(&mut Vec::new()).unwrap();
+    file.sync().unwrap();
+    assert_eq!(file.metadata.accessed.date, clock().date);
+
+    let file = VFat::from(image.clone()).unwrap().open_file("/log.txt").unwrap();
+    assert_eq!(file.metadata.accessed.year(), 2026);
+    assert_eq!(file.metadata.accessed.date, clock().date);
+}
+
+#[test]
+fn test_partition_device() {
+    use PartitionDevice;
+
+    let mut data = vec![0u8; 64 * 512];
+    for (i, sector) in data.chunks_mut(512).enumerate() {
+        for byte in sector.iter_mut() {
+            *byte = i as u8;
+        }
+    }
+
+    let mut disk = Cursor::new(data);
+    {
+        let mut partition = PartitionDevice::new(&mut disk, 8, 16);
+        assert_eq!((partition.sector_size(), partition.sectors()), (512, 16));
+
+        let mut buf = [0u8; 512];
+        assert_eq!(partition.read_sector(0, &mut buf).unwrap(), 512);
+        assert!(buf.iter().all(|&b| b == 8));
+
+        let mut short = [0u8; 100];
+        assert_eq!(partition.read_sector(15, &mut short).unwrap(), 100);
+        assert!(short.iter().all(|&b| b == 23));
+
+        let mut two = [0u8; 1024];
+        assert_eq!(partition.read_sectors(14, &mut two).unwrap(), 1024);
+        assert_eq!((two[0], two[1023]), (22, 23));
+
+        for result in vec![partition.read_sector(16, &mut buf),
+                           partition.read_sectors(15, &mut two),
+                           partition.write_sector(16, &[0xAA; 512])] {
+            assert_eq!(result.unwrap_err().kind(), io::ErrorKind::InvalidInput);
+        }
+
+        assert_eq!(partition.write_sector(0, &[0xAA; 100]).unwrap_err().kind(),
+                   io::ErrorKind::UnexpectedEof);
+        partition.write_sector(1, &[0xAA; 512]).unwrap();
+    }
+
+    {
+        // 2048-byte logical sectors span four physical sectors; the trailing
+        // three physical sectors don't make up a whole logical sector.
+        let mut partition = PartitionDevice::with_sector_size(&mut disk, 8, 19, 2048);
+        assert_eq!((partition.sector_size(), partition.sectors()), (2048, 4));
+
+        let mut buf = vec![0u8; 2048];
+        partition.read_sector(1, &mut buf).unwrap();
+        assert_eq!((buf[0], buf[511], buf[512], buf[2047]), (12, 12, 13, 15));
+        assert!(partition.read_sector(4, &mut buf).is_err());
+
+        partition.write_sectors(3, &[0x55; 2048]).unwrap();
+    }
+
+    let data = disk.into_inner();
+    assert!(data[9 * 512..10 * 512].iter().all(|&b| b == 0xAA));
+    assert!(data[20 * 512..24 * 512].iter().all(|&b| b == 0x55));
+    assert!(data[24 * 512..].iter().all(|&b| b != 0x55));
+
+    // A volume in a partition mounts through the partition alone.
+    let image = mock_fat32_image();
+    let sectors = (image.len() / 512 - MOCK_PARTITION_START) as u64;
+    let partition = PartitionDevice::new(Cursor::new(image), MOCK_PARTITION_START as u64, sectors);
+    let vfat = VFat::from(partition).expect("partition mounts");
+    assert_eq!(entry_names(vfat.open_dir("/").unwrap()), vec!["LOG.TXT"]);
+
+    // A volume with sectors larger than the device's mounts from the device.
+    let mut data = vec![0u8; 8192 * 512];
+    {
+        let partition = PartitionDevice::with_sector_size(Cursor::new(&mut data[..]), 0, 8192, 2048);
+        ::format(partition, 2048, &::FormatOptions::default()).expect("format");
+    }
+
+    let image = SharedImage::new(data);
+    {
+        let vfat = VFat::from(image.clone()).expect("2048-byte sectors mount");
+        let mut file = vfat.create_file("/big sectors").unwrap();
+        file.write_all(&[7; 5000]).unwrap();
+        file.sync().unwrap();
+    }
+
+    let vfat = VFat::from(image).expect("volume remounts");
+    let mut read = Vec::new();
+    vfat.open_file("/big sectors").unwrap().read_to_end(&mut read).unwrap();
+    assert!(read == vec![7; 5000]);
+}
+
+#[test]
+fn test_tmpfs() {
+    use tmpfs::TmpFs;
+
+    let fs = TmpFs::new();
+    assert_eq!(entry_names(fs.open_dir("/").unwrap()), Vec::<String>::new());
+
+    let mut file = fs.create_file("/hello.txt").unwrap();
+    file.write_all(b"hello, world").unwrap();
+    assert_eq!(file.size(), 12);
+    file.seek(io::SeekFrom::Start(7)).unwrap();
+    file.write_all(b"tmpfs!").unwrap();
+    assert!(file.seek(io::SeekFrom::Start(14)).is_err());
+
+    let mut contents = String::new();
+    fs.open_file("/hello.txt").unwrap().read_to_string(&mut contents).unwrap();
+    assert_eq!(contents, "hello, tmpfs!");
+
+    fs.create_dir("/a/b/c", true).unwrap();
+    assert_eq!(fs.create_dir("/x/y", false).unwrap_err().kind(), io::ErrorKind::InvalidInput);
+    assert_eq!(fs.create_dir("/a/b", false).unwrap_err().kind(), io::ErrorKind::AlreadyExists);
+    assert_eq!(fs.create_file("/hello.txt").unwrap_err().kind(), io::ErrorKind::AlreadyExists);
+    assert_eq!(fs.create_file("/hello.txt/x").unwrap_err().kind(), io::ErrorKind::InvalidInput);
+    assert_eq!(fs.open("/HELLO.TXT").unwrap_err().kind(), io::ErrorKind::NotFound);
+    assert_eq!(fs.open("hello.txt").unwrap_err().kind(), io::ErrorKind::InvalidInput);
+    assert_eq!(entry_names(fs.open_dir("/").unwrap()), vec!["a", "hello.txt"]);
+
+    fs.rename("/hello.txt", "/a/b/greeting.txt").unwrap();
+    fs.rename("/a/b/greeting.txt", "/a/b/greeting.txt").unwrap();
+    assert_eq!(fs.open("/hello.txt").unwrap_err().kind(), io::ErrorKind::NotFound);
+    assert_eq!(fs.open_file("/a/b/greeting.txt").unwrap().size(), 13);
+    assert_eq!(fs.rename("/a", "/a/b/c/a").unwrap_err().kind(), io::ErrorKind::InvalidInput);
+    assert_eq!(fs.rename("/a/b/c", "/a/b/greeting.txt").unwrap_err().kind(),
+               io::ErrorKind::AlreadyExists);
+    assert_eq!(fs.rename("/", "/root").unwrap_err().kind(), io::ErrorKind::InvalidInput);
+    fs.rename("/a/b/c", "/c").unwrap();
+    assert_eq!(entry_names(fs.open_dir("/a/b").unwrap()), vec!["greeting.txt"]);
+
+    // An open file outlives its removal.
+    let mut file = fs.open_file("/a/b/greeting.txt").unwrap();
+    assert_eq!(fs.remove("/a", false).unwrap_err().kind(), io::ErrorKind::Other);
+    fs.remove("/a", true).unwrap();
+    assert_eq!(fs.open("/a/b/greeting.txt").unwrap_err().kind(), io::ErrorKind::InvalidInput);
+    assert_eq!(file.read_to_end(&mut Vec::new()).unwrap(), 13);
+    assert_eq!(entry_names(fs.open_dir("/").unwrap()), vec!["c"]);
+}
+
+#[test]
+fn test_walk() {
+    use tmpfs::TmpFs;
+    use walk::{walk, Order, WalkEntry};
+
+    fn paths<D: Dir>(walk: ::walk::Walk<D>) -> Vec<(String, usize)>
+        where D::Entry: Entry<Dir = D>
+    {
+        walk.map(|entry: io::Result<WalkEntry<D::Entry>>| {
+            let entry = entry.expect("walk entry");
+            (entry.path.display().to_string(), entry.depth)
+        }).collect()
+    }
+
+    let fs = TmpFs::new();
+    fs.create_dir("/a/b", true).unwrap();
+    fs.create_file("/a/b/one").unwrap();
+    fs.create_file("/a/two").unwrap();
+    fs.create_dir("/c", false).unwrap();
+    fs.create_file("/c/.three").unwrap();
+    ::traits::Entry::into_file(fs.open("/c/.three").unwrap()).unwrap()
+        .node.borrow_mut().metadata.hidden = true;
+
+    let pre = paths(walk(&fs, "/").unwrap());
+    assert_eq!(pre, vec![("/a".to_string(), 1), ("/a/b".to_string(), 2),
+                         ("/a/b/one".to_string(), 3), ("/a/two".to_string(), 2),
+                         ("/c".to_string(), 1), ("/c/.three".to_string(), 2)]);
+
+    let post = paths(walk(&fs, "/").unwrap().order(Order::Post));
+    assert_eq!(post.iter().map(|p| &*p.0).collect::<Vec<_>>(),
+               vec!["/a/b/one", "/a/b", "/a/two", "/a", "/c/.three", "/c"]);
+
+    let shallow = paths(walk(&fs, "/a").unwrap().max_depth(1));
+    assert_eq!(shallow, vec![("/a/b".to_string(), 1), ("/a/two".to_string(), 1)]);
+    assert!(walk(&fs, "/").unwrap().max_depth(0).next().is_none());
+
+    let visible = paths(walk(&fs, "/").unwrap().skip_hidden(true).order(Order::Post));
+    assert_eq!(visible.last().unwrap(), &("/c".to_string(), 1));
+    assert_eq!(visible.len(), 5);
+
+    let filtered = paths(walk(&fs, "/").unwrap().filter(|e| e.entry.name() != "b"));
+    assert_eq!(filtered.iter().map(|p| &*p.0).collect::<Vec<_>>(),
+               vec!["/a", "/a/two", "/c", "/c/.three"]);
+
+    assert_eq!(walk(&fs, "/a/two").unwrap_err().kind(), io::ErrorKind::Other);
+    assert_eq!(walk(&fs, "/missing").unwrap_err().kind(), io::ErrorKind::NotFound);
+
+    // A post-order walk reaches each directory after its contents are gone.
+    for entry in walk(&fs, "/a").unwrap().order(Order::Post) {
+        let entry = entry.unwrap();
+        if entry.entry.is_dir() {
+            assert_eq!(entry_names(fs.open_dir(&entry.path).unwrap()), Vec::<String>::new());
+        }
+        fs.remove(&entry.path, entry.entry.is_dir()).unwrap();
+    }
+    assert_eq!(entry_names(fs.open_dir("/a").unwrap()), Vec::<String>::new());
+
+    // The walk works the same over a FAT file system, skipping `.` and `..`.
+    let vfat = VFat::from(SharedImage::new(mock_fat32_image())).unwrap();
+    vfat.create_dir("/x/y", true).unwrap();
+    vfat.create_file("/x/y/z.txt").unwrap();
+    let found: Vec<_> = walk(&vfat, "/x").unwrap()
+        .map(|e| e.unwrap().path.display().to_string())
+        .collect();
+    assert_eq!(found, vec!["/x/y", "/x/y/z.txt"]);
+}
+
+#[test]
+fn test_timestamp_conversion() {
+    use vfat::{Timestamp as FatTimestamp, Date, Time};
+
+    let epoch = FatTimestamp::new(1980, 1, 1, 0, 0, 0).unwrap();
+    assert_eq!((epoch.date, epoch.time), (Date::from_raw(0x21), Time::default()));
+    assert_eq!(epoch.to_unix(), Some(315_532_800));
+    assert_eq!(FatTimestamp::from_unix(315_532_800), Some(epoch));
+    assert_eq!(FatTimestamp::from_unix(315_532_799), None);
+
+    // 2024-02-29 13:45:07, a leap day and an odd second. Write and access
+    // times can't hold the odd second, so it is rounded down; only creation
+    // times keep it, in the 10 ms increment.
+    let leap = FatTimestamp::from_unix(1_709_214_307).unwrap();
+    assert_eq!((leap.year(), leap.month(), leap.day()), (2024, 2, 29));
+    assert_eq!((leap.hour(), leap.minute(), leap.second()), (13, 45, 6));
+    assert_eq!(leap.increment_10ms, 0);
+    assert_eq!(leap.time, Time::new(13, 45, 6).unwrap());
+    assert_eq!(leap.to_unix(), Some(1_709_214_306));
+    assert_eq!(FatTimestamp::new(2024, 2, 29, 13, 45, 7), Some(leap));
+    let created = leap.with_increment_10ms(100).unwrap();
+    assert_eq!((created.second(), created.to_unix()), (7, Some(1_709_214_307)));
+    assert_eq!(created.to_string(), "02/29/2024 13:45:07");
+    assert_eq!(leap.with_increment_10ms(200), None);
+
+    // A timestamp survives being stored in the write time fields.
+    let mut raw = [0u8; 32];
+    raw[22..24].copy_from_slice(&leap.time.raw().to_le_bytes());
+    raw[24..26].copy_from_slice(&leap.date.raw().to_le_bytes());
+    let modified = ::vfat::dir::VFatRegularDirEntry::from_bytes(&raw).metadata().modified;
+    assert_eq!(modified, leap);
+
+    let last = FatTimestamp::new(2107, 12, 31, 23, 59, 58).unwrap();
+    assert_eq!(FatTimestamp::from_unix(last.to_unix().unwrap()), Some(last));
+    assert_eq!(FatTimestamp::from_unix(last.to_unix().unwrap() + 1), Some(last));
+    assert_eq!(FatTimestamp::from_unix(last.to_unix().unwrap() + 2), None);
+    for seconds in (315_532_800..last.to_unix().unwrap()).step_by(7_654_321) {
+        assert_eq!(FatTimestamp::from_unix(seconds).unwrap().to_unix(),
+                   Some(seconds - seconds % 2));
+    }
+
+    assert_eq!(FatTimestamp::new(1979, 12, 31, 0, 0, 0), None);
+    assert_eq!(FatTimestamp::new(2023, 2, 29, 0, 0, 0), None);
+    assert_eq!(FatTimestamp::new(2000, 2, 29, 0, 0, 0).map(|t| t.day()), Some(29));
+    assert_eq!(FatTimestamp::new(2100, 2, 29, 0, 0, 0), None);
+    assert_eq!(FatTimestamp::new(2020, 4, 31, 0, 0, 0), None);
+    assert_eq!(FatTimestamp::new(2020, 13, 1, 0, 0, 0), None);
+    assert_eq!(FatTimestamp::new(2020, 1, 1, 24, 0, 0), None);
+    assert_eq!(FatTimestamp::new(2020, 1, 1, 0, 60, 0), None);
+    assert_eq!(FatTimestamp::new(2020, 1, 1, 0, 0, 60), None);
+
+    // Fields read from disk may be out of range.
+    let bad_month = FatTimestamp { date: Date::from_raw((40 << 9) | (13 << 5) | 1), ..epoch };
+    let bad_hour = FatTimestamp { time: Time::from_raw(24 << 11), ..epoch };
+    let bad_increment = FatTimestamp { increment_10ms: 200, ..epoch };
+    assert_eq!((bad_month.to_unix(), bad_hour.to_unix(), bad_increment.to_unix()),
+               (None, None, None));
+
+    // The creation time's increment is read from the directory entry.
+    let mut raw = [0u8; 32];
+    raw[13] = 150;
+    raw[14..16].copy_from_slice(&Time::new(12, 0, 0).unwrap().raw().to_le_bytes());
+    let created = ::vfat::dir::VFatRegularDirEntry::from_bytes(&raw).metadata().created;
+    assert_eq!((created.hour(), created.second(), created.increment_10ms), (12, 1, 150));
+}
+
+#[test]
+fn test_set_len_and_append() {
+    let image = SharedImage::new(mock_fat32_image());
+    let vfat = VFat::from(image.clone()).unwrap();
+    let cluster_size = vfat.borrow().bytes_per_cluster() as u64;
+    let free = vfat.borrow_mut().free_clusters().unwrap();
+
+    let mut file = vfat.open_file("/log.txt").unwrap();
+    file.write_all(&vec![0xAA; 4 * cluster_size as usize]).unwrap();
+    file.sync().unwrap();
+    assert_eq!(file_chain(&vfat, "/log.txt").len(), 4);
+
+    // Shrinking frees the tail of the chain and persists the size at once.
+    file.set_len(cluster_size + 10).unwrap();
+    assert_eq!(file.seek(io::SeekFrom::Current(0)).unwrap(), cluster_size + 10);
+    assert_eq!(file_chain(&vfat, "/log.txt").len(), 2);
+    assert_eq!(vfat.borrow_mut().free_clusters().unwrap(), free - 2);
+    assert_eq!(vfat.open_file("/log.txt").unwrap().size(), cluster_size + 10);
+
+    // Growing zeroes both the stale end of the last cluster and new clusters.
+    file.set_len(3 * cluster_size + 5).unwrap();
+    file.sync().unwrap();
+    assert_eq!(file_chain(&vfat, "/log.txt").len(), 4);
+    let mut contents = Vec::new();
+    vfat.open_file("/log.txt").unwrap().read_to_end(&mut contents).unwrap();
+    assert_eq!(contents.len() as u64, 3 * cluster_size + 5);
+    assert!(contents[..cluster_size as usize + 10].iter().all(|&b| b == 0xAA));
+    assert!(contents[cluster_size as usize + 10..].iter().all(|&b| b == 0));
+
+    file.set_len(0).unwrap();
+    assert_eq!(file.first_cluster, None);
+    assert_eq!(vfat.borrow_mut().free_clusters().unwrap(), free);
+    assert_eq!(vfat.open_file("/log.txt").unwrap().first_cluster, None);
+    assert_eq!(file.set_len(1 << 32).unwrap_err().kind(), io::ErrorKind::InvalidInput);
+
+    // Appends go to the end of the file wherever the position is.
+    file.write_all(b"first").unwrap();
+    file.sync().unwrap();
+    let mut file = ::vfat::File::open_append(&vfat, "/log.txt").unwrap();
+    file.seek(io::SeekFrom::Start(1)).unwrap();
+    file.write_all(b", second").unwrap();
+    file.seek(io::SeekFrom::Start(0)).unwrap();
+    let mut contents = String::new();
+    file.read_to_string(&mut contents).unwrap();
+    assert_eq!(contents, "first, second");
+    file.sync().unwrap();
+
+    let report = check::check(&vfat, check::Mode::ReadOnly).unwrap();
+    assert!(chain_problems(&report).is_empty(), "{}", report);
+}
+
+#[test]
+fn test_set_len_crash_safety() {
+    let image = SharedImage::new(mock_fat32_image());
+    let (cluster_size, contents) = {
+        let vfat = VFat::from(image.clone()).unwrap();
+        let cluster_size = vfat.borrow().bytes_per_cluster();
+        let contents: Vec<u8> = (0..4 * cluster_size).map(|i| (i % 249) as u8).collect();
+        let mut file = vfat.open_file("/log.txt").unwrap();
+        file.write_all(&contents).unwrap();
+        file.sync().unwrap();
+        (cluster_size as u64, contents)
+    };
+    let data = image.0.lock().unwrap().get_ref().clone();
+
+    // Losing power after any number of writes leaves the file readable and at
+    // worst clusters that `check` frees.
+    for &size in &[cluster_size + 10, 0] {
+        for writes in 0.. {
+            let image = SharedImage::new(data.clone());
+            let device = FailingDevice { image: image.clone(), writes_left: writes };
+            let result = VFat::from(device).unwrap().open_file("/log.txt").unwrap().set_len(size);
+
+            let vfat = VFat::from(image).unwrap();
+            let read = read_file(&vfat, "/log.txt");
+            assert!(read == &contents[..read.len()], "file damaged after {} writes", writes);
+            let report = check::check(&vfat, check::Mode::ReadOnly).unwrap();
+            for issue in &report.issues {
+                match issue.problem {
+                    check::Problem::LostChain { .. } | check::Problem::SizeMismatch { .. }
+                        | check::Problem::FatMismatch { .. } => {}
+                    ref problem => panic!("after {} writes: {}", writes, problem),
+                }
+            }
+
+            if result.is_ok() {
+                assert_eq!(read.len() as u64, size);
+                assert!(report.is_clean(), "{}", report);
+                break;
+            }
+        }
+    }
+}
+
+#[test]
+fn test_preallocate() {
+    let vfat = VFat::from(SharedImage::new(mock_fat32_image())).unwrap();
+    let cluster_size = vfat.borrow().bytes_per_cluster() as u64;
+    let free = vfat.borrow_mut().free_clusters().unwrap();
+
+    let mut file = vfat.create_file("/data.bin").unwrap();
+    file.preallocate(4 * cluster_size - 1).unwrap();
+    assert_eq!(file.size(), 0);
+    assert_eq!(vfat.borrow_mut().free_clusters().unwrap(), free - 4);
+
+    // Writes into the reserved clusters allocate nothing more.
+    let contents: Vec<u8> = (0..4 * cluster_size).map(|i| i as u8).collect();
+    file.write_all(&contents).unwrap();
+    file.sync().unwrap();
+    assert_eq!(vfat.borrow_mut().free_clusters().unwrap(), free - 4);
+    let chain = file_chain(&vfat, "/data.bin");
+    assert!(chain.windows(2).all(|w| w[1].number() == w[0].number() + 1), "{:?}", chain);
+
+    // Reads inside a known run don't consult the FAT, so they survive the
+    // FAT entry of the chain's second cluster being clobbered.
+    vfat.borrow_mut().set_fat_entry(chain[1], 0x0FFFFFF7).unwrap();
+    file.seek(io::SeekFrom::Start(0)).unwrap();
+    let mut read = Vec::new();
+    file.read_to_end(&mut read).unwrap();
+    assert!(read == contents);
+    vfat.borrow_mut().set_fat_entry(chain[1], chain[2].number()).unwrap();
+
+    // Reserving no more than the chain holds does nothing.
+    file.preallocate(2 * cluster_size).unwrap();
+    assert_eq!(vfat.borrow_mut().free_clusters().unwrap(), free - 4);
+
+    // Leave free only runs of two, one, and two clusters.
+    let runs = vfat.borrow_mut().free_runs().unwrap();
+    let free: Vec<u32> = runs.iter()
+        .flat_map(|&(start, len)| start.number()..start.number() + len)
+        .collect();
+    let kept = [free[0], free[0] + 1, free[0] + 3, free[0] + 5, free[0] + 6];
+    assert_eq!(free[6], free[0] + 6, "free space is one run");
+    for &number in free.iter().filter(|number| !kept.contains(number)) {
+        vfat.borrow_mut().set_fat_entry(::vfat::Cluster::from(number), 0x0FFFFFFF).unwrap();
+    }
+
+    let mut file = vfat.create_file("/fragmented.bin").unwrap();
+    assert_eq!(file.preallocate(6 * cluster_size).unwrap_err().kind(), io::ErrorKind::Other);
+    assert_eq!(vfat.borrow_mut().free_clusters().unwrap(), 5);
+
+    // With no run of three free clusters, the largest runs are used first.
+    file.preallocate(3 * cluster_size).unwrap();
+    file.sync().unwrap();
+    let chain = file_chain(&vfat, "/fragmented.bin");
+    assert_eq!(chain.iter().map(|c| c.number()).collect::<Vec<_>>(),
+               vec![kept[0], kept[1], kept[3]]);
+
+    // A single run large enough is preferred, the smallest such run first.
+    let mut file = vfat.create_file("/single.bin").unwrap();
+    file.preallocate(1).unwrap();
+    file.sync().unwrap();
+    assert_eq!(file_chain(&vfat, "/single.bin"), vec![::vfat::Cluster::from(kept[2])]);
+
+    // The reserved chain of an empty file is referenced from its entry even
+    // without `sync()`, but a repairing check frees it as a size mismatch.
+    let vfat = VFat::from(SharedImage::new(mock_fat32_image())).unwrap();
+    let free = vfat.borrow_mut().free_clusters().unwrap();
+    vfat.create_file("/data.bin").unwrap().preallocate(2 * cluster_size).unwrap();
+    assert_eq!(file_chain(&vfat, "/data.bin").len(), 2);
+    let report = check::check(&vfat, check::Mode::Repair).unwrap();
+    assert_eq!(chain_problems(&report), vec![check::Problem::SizeMismatch {
+        path: "/data.bin".to_string(),
+        size: 0,
+        clusters: 2,
+    }]);
+    assert_eq!(vfat.borrow_mut().free_clusters().unwrap(), free);
+    assert!(file_chain(&vfat, "/data.bin").is_empty());
+}
+
+/// A device that fails every write after the first `writes_left`, as if power
+/// were lost.
+struct FailingDevice {
+    image: SharedImage,
+    writes_left: usize,
+}
+
+impl BlockDevice for FailingDevice {
+    fn read_sector(&mut self, n: u64, buf: &mut [u8]) -> io::Result<usize> {
+        self.image.read_sector(n, buf)
+    }
+
+    fn write_sector(&mut self, n: u64, buf: &[u8]) -> io::Result<usize> {
+        match self.writes_left {
+            0 => Err(io::Error::new(io::ErrorKind::Other, "power lost")),
+            _ => {
+                self.writes_left -= 1;
+                self.image.write_sector(n, buf)
+            }
+        }
+    }
+}
+
+/// A mock image holding `/a.bin` and `/b.bin`, three clusters each, written
+/// in turns so that their chains interleave. Returns the image and the files'
+/// contents.
+fn fragmented_image() -> (Vec<u8>, Vec<u8>, Vec<u8>) {
+    let image = SharedImage::new(mock_fat32_image());
+    let vfat = VFat::from(image.clone()).unwrap();
+    let cluster_size = vfat.borrow().bytes_per_cluster();
+    let a: Vec<u8> = (0..3 * cluster_size).map(|i| (i % 253) as u8).collect();
+    let b: Vec<u8> = (0..3 * cluster_size).map(|i| (i % 241) as u8).collect();
+
+    let (mut file_a, mut file_b) = (vfat.create_file("/a.bin").unwrap(),
+                                    vfat.create_file("/b.bin").unwrap());
+    for (chunk_a, chunk_b) in a.chunks(cluster_size).zip(b.chunks(cluster_size)) {
+        file_a.write_all(chunk_a).unwrap();
+        file_b.write_all(chunk_b).unwrap();
+    }
+    file_a.sync().unwrap();
+    file_b.sync().unwrap();
+    check::check(&vfat, check::Mode::Repair).unwrap();
+
+    let data = image.0.lock().unwrap().get_ref().clone();
+    (data, a, b)
+}
+
+fn read_file(vfat: &Shared<VFat>, path: &str) -> Vec<u8> {
+    let mut contents = Vec::new();
+    vfat.open_file(path).unwrap().read_to_end(&mut contents).unwrap();
+    contents
+}
+
+#[test]
+fn test_fragmentation_report_and_defragment() {
+    use defrag;
+
+    let (data, a, b) = fragmented_image();
+    let vfat = VFat::from(SharedImage::new(data.clone())).unwrap();
+    let report = defrag::report(&vfat).unwrap();
+    let fragmented: Vec<_> = report.fragmented().map(|f| (&*f.path, f.clusters, f.fragments))
+        .collect();
+    assert_eq!(fragmented, vec![("/a.bin", 3, 3), ("/b.bin", 3, 3)]);
+    let free = vfat.borrow_mut().free_clusters().unwrap();
+    assert_eq!(report.free.iter().map(|run| run.1).sum::<u32>(), free);
+    assert!(report.largest_free_run() >= 3);
+
+    let result = defrag::defragment(&vfat).unwrap();
+    assert_eq!(result.moved, vec!["/a.bin", "/b.bin"]);
+    assert!(result.skipped.is_empty());
+    let report = defrag::report(&vfat).unwrap();
+    assert_eq!(report.fragmented().count(), 0, "{}", report);
+    assert_eq!(vfat.borrow_mut().free_clusters().unwrap(), free);
+    assert!(read_file(&vfat, "/a.bin") == a && read_file(&vfat, "/b.bin") == b);
+    let report = check::check(&vfat, check::Mode::ReadOnly).unwrap();
+    assert!(report.is_clean(), "{}", report);
+    assert_eq!(defrag::defragment(&vfat).unwrap(), defrag::Defragmented::default());
+
+    // Fragmented directories are reported as skipped rather than moved.
+    let cluster_size = vfat.borrow().bytes_per_cluster();
+    vfat.create_dir("/dir", false).unwrap();
+    vfat.create_file("/c.bin").unwrap().write_all(&vec![7; cluster_size]).unwrap();
+    for i in 0..cluster_size / 32 {
+        vfat.create_file(format!("/dir/{}", i)).unwrap();
+    }
+    let result = defrag::defragment(&vfat).unwrap();
+    assert!(result.moved.is_empty());
+    assert_eq!(result.skipped, vec![("/dir".to_string(), defrag::Skip::Directory)]);
+
+    // So are files that no single free run can hold.
+    let (data, _, _) = fragmented_image();
+    let vfat = VFat::from(SharedImage::new(data.clone())).unwrap();
+    let largest = defrag::report(&vfat).unwrap().largest_free_run();
+    let mut fill = vfat.create_file("/fill").unwrap();
+    fill.preallocate((largest as u64 - 2) * cluster_size as u64).unwrap();
+    fill.sync().unwrap();
+    let report = defrag::report(&vfat).unwrap();
+    assert!(report.largest_free_run() < 3, "{}", report);
+    let result = defrag::defragment(&vfat).unwrap();
+    assert!(result.moved.is_empty());
+    assert_eq!(result.skipped, vec![("/a.bin".to_string(), defrag::Skip::NoFreeRun),
+                                    ("/b.bin".to_string(), defrag::Skip::NoFreeRun)]);
+
+    // However many writes reach the disk before power is lost, both files
+    // stay intact, and at worst clusters are leaked or the FATs differ.
+    for writes in 0.. {
+        let image = SharedImage::new(data.clone());
+        let device = FailingDevice { image: image.clone(), writes_left: writes };
+        let result = defrag::defragment(&VFat::from(device).unwrap());
+
+        let vfat = VFat::from(image).unwrap();
+        assert!(read_file(&vfat, "/a.bin") == a && read_file(&vfat, "/b.bin") == b,
+                "files damaged after {} writes", writes);
+        let report = check::check(&vfat, check::Mode::ReadOnly).unwrap();
+        for issue in &report.issues {
+            match issue.problem {
+                check::Problem::LostChain { .. } | check::Problem::FatMismatch { .. } => {}
+                ref problem => panic!("after {} writes: {}", writes, problem),
+            }
+        }
+
+        if result.is_ok() {
+            assert!(report.is_clean(), "{}", report);
+            break;
+        }
+    }
+}
+
+#[test]
+fn test_fat_mirroring() {
+    let fat_start = |fat: usize| (MOCK_PARTITION_START + 32 + fat * 32) * 512;
+    let fat_copy = |image: &SharedImage, fat: usize| {
+        image.0.lock().unwrap().get_ref()[fat_start(fat)..fat_start(fat + 1)].to_vec()
+    };
+
+    // Every write reaches both FATs.
+    let image = SharedImage::new(mock_fat32_image());
+    {
+        let vfat = VFat::from(image.clone()).unwrap();
+        assert_eq!(vfat.borrow().active_fat(), None);
+        vfat.open_file("/log.txt").unwrap().write_all(&[1; 2000]).unwrap();
+        vfat.borrow_mut().flush().unwrap();
+    }
+    assert!(fat_copy(&image, 0) == fat_copy(&image, 1));
+    assert!(fat_copy(&image, 0) != fat_copy(&SharedImage::new(mock_fat32_image()), 0));
+
+    // With mirroring disabled in the extended flags, only the active FAT is
+    // read and written.
+    let mut data = mock_fat32_image();
+    put_u16(&mut data, MOCK_PARTITION_START * 512 + 40, 0x81);
+    let fat_0 = data[fat_start(0)..fat_start(1)].to_vec();
+    let fat_1_head = data[fat_start(1)..fat_start(1) + 12].to_vec();
+    for byte in &mut data[fat_start(0)..fat_start(1)] {
+        *byte = 0xEE;
+    }
+
+    let image = SharedImage::new(data);
+    {
+        let vfat = VFat::from(image.clone()).unwrap();
+        assert_eq!(vfat.borrow().active_fat(), Some(1));
+        let mut file = vfat.create_file("/new.txt").unwrap();
+        file.write_all(&[2; 1500]).unwrap();
+        file.sync().unwrap();
+        assert!(check::compare_fats(&vfat).unwrap().is_empty());
+        let report = check::check(&vfat, check::Mode::Repair).unwrap();
+        assert!(report.is_clean(), "{}", report);
+    }
+    assert!(fat_copy(&image, 0).iter().all(|&b| b == 0xEE));
+    assert!(fat_copy(&image, 1) != fat_0 && fat_copy(&image, 1)[..12] == fat_1_head[..]);
+
+    let mut data = mock_fat32_image();
+    put_u16(&mut data, MOCK_PARTITION_START * 512 + 40, 0x82);
+    expect_variant!(VFat::from(Cursor::new(data)),
+                    Err(::vfat::Error::BadActiveFat { value: 2, .. }));
+}
+
+#[test]
+fn test_backup_boot_sector() {
+    let boot = MOCK_PARTITION_START * 512;
+    let backup = (MOCK_PARTITION_START + 6) * 512;
+
+    // Without a backup, a bad signature can't be recovered from.
+    let mut data = mock_fat32_image();
+    data[boot + 510] = 0;
+    expect_variant!(VFat::from(Cursor::new(data.clone())), Err(::vfat::Error::BadSignature));
+
+    let mut bpb = mock_fat32_image()[boot..boot + 512].to_vec();
+    put_u16(&mut bpb, 50, 6);
+    data[backup..backup + 512].copy_from_slice(&bpb);
+    let vfat = VFat::from(Cursor::new(data.clone())).unwrap();
+    assert!(vfat.borrow().used_backup_boot_sector());
+    assert_eq!(vfat.open_file("/log.txt").unwrap().size(), 0);
+
+    let vfat = VFat::from(Cursor::new(mock_fat32_image())).unwrap();
+    assert!(!vfat.borrow().used_backup_boot_sector());
+
+    // A bad backup is no better than none, and a bad primary BPB with a good
+    // signature is not replaced.
+    data[backup + 13] = 3;
+    expect_variant!(VFat::from(Cursor::new(data.clone())), Err(::vfat::Error::BadSignature));
+    let mut data = mock_fat32_image();
+    data[boot + 13] = 3;
+    data[backup..backup + 512].copy_from_slice(&bpb);
+    expect_variant!(VFat::from(Cursor::new(data)),
+                    Err(::vfat::Error::BadSectorsPerCluster { value: 3, .. }));
+
+    // Without a partition table, a damaged sector 0 is not mistaken for an
+    // MBR when the backup boot sector is intact.
+    let mut data = mock_image(FatType::Fat32, false);
+    let bpb = data[..512].to_vec();
+    data[6 * 512..7 * 512].copy_from_slice(&bpb);
+    put_u16(&mut data, 50, 6);
+    put_u16(&mut data, 6 * 512 + 50, 6);
+    data[510] = 0;
+    let vfat = VFat::from(Cursor::new(data.clone())).unwrap();
+    assert!(vfat.borrow().used_backup_boot_sector());
+    assert_eq!(vfat.open_file("/log.txt").unwrap().size(), 0);
+    match ::mount(Cursor::new(data)) {
+        Ok(::Volume::Fat(vfat)) => assert!(vfat.borrow().used_backup_boot_sector()),
+        other => panic!("partitionless FAT32 not mounted: {:?}", other),
+    }
+
+    // FAT16 volumes have no backup boot sector.
+    let mut data = mock_image(FatType::Fat16, true);
+    data[boot + 510] = 0;
+    expect_variant!(VFat::from(Cursor::new(data)), Err(::vfat::Error::BadSignature));
+}
@@ -0,0 +1,33 @@
+use std::{io, vec};
+
+use traits;
+use vfat::Shared;
+use tmpfs::{Node, Contents, Entry, Metadata, Timestamp};
+
+#[derive(Debug)]
+pub struct Dir {
+    pub(crate) node: Shared<Node>,
+    pub(crate) name: String,
+    /// The directory's metadata when it was opened.
+    pub(crate) metadata: Metadata,
+    pub(crate) clock: Option<fn() -> Timestamp>,
+}
+
+impl traits::Dir for Dir {
+    type Entry = Entry;
+    type Iter = vec::IntoIter<io::Result<Entry>>;
+
+    /// Returns an iterator over the entries in this directory, in the order
+    /// they were added, as of the time of the call.
+    fn entries(&self) -> io::Result<Self::Iter> {
+        let children = match self.node.borrow().contents {
+            Contents::Dir(ref entries) => entries.clone(),
+            Contents::File(_) => unreachable!("tmpfs: directory refers to a file"),
+        };
+
+        let entries: Vec<_> = children.into_iter()
+            .map(|(name, node)| Ok(Entry::new(name, node, self.clock)))
+            .collect();
+        Ok(entries.into_iter())
+    }
+}
@@ -0,0 +1,72 @@
+use traits;
+use vfat::Shared;
+use tmpfs::{Node, File, Dir, Metadata, Timestamp};
+
+#[derive(Debug)]
+pub enum Entry {
+    File(File),
+    Dir(Dir)
+}
+
+impl Entry {
+    /// Returns the entry named `name` for `node`.
+    pub(crate) fn new(name: String, node: Shared<Node>, clock: Option<fn() -> Timestamp>) -> Entry {
+        let (metadata, is_dir) = {
+            let node = node.borrow();
+            (node.metadata, node.is_dir())
+        };
+
+        match is_dir {
+            true => Entry::Dir(Dir { node: node, name: name, metadata: metadata, clock: clock }),
+            false => Entry::File(File::new(node, name, metadata, clock)),
+        }
+    }
+}
+
+impl traits::Entry for Entry {
+    type File = File;
+    type Dir = Dir;
+    type Metadata = Metadata;
+
+    fn name(&self) -> &str {
+        match *self {
+            Entry::File(ref file) => &file.name,
+            Entry::Dir(ref dir) => &dir.name,
+        }
+    }
+
+    fn metadata(&self) -> &Metadata {
+        match *self {
+            Entry::File(ref file) => &file.metadata,
+            Entry::Dir(ref dir) => &dir.metadata,
+        }
+    }
+
+    fn as_file(&self) -> Option<&File> {
+        match *self {
+            Entry::File(ref file) => Some(file),
+            Entry::Dir(_) => None,
+        }
+    }
+
+    fn as_dir(&self) -> Option<&Dir> {
+        match *self {
+            Entry::File(_) => None,
+            Entry::Dir(ref dir) => Some(dir),
+        }
+    }
+
+    fn into_file(self) -> Option<File> {
+        match self {
+            Entry::File(file) => Some(file),
+            Entry::Dir(_) => None,
+        }
+    }
+
+    fn into_dir(self) -> Option<Dir> {
+        match self {
+            Entry::File(_) => None,
+            Entry::Dir(dir) => Some(dir),
+        }
+    }
+}
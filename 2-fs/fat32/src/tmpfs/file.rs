@@ -0,0 +1,137 @@
+use std::cmp::min;
+use std::io::{self, SeekFrom};
+
+use traits;
+use vfat::Shared;
+use tmpfs::{Node, Contents, Metadata, Timestamp};
+
+#[derive(Debug)]
+pub struct File {
+    pub(crate) node: Shared<Node>,
+    pub(crate) name: String,
+    /// The file's metadata when it was opened.
+    pub(crate) metadata: Metadata,
+    pub(crate) clock: Option<fn() -> Timestamp>,
+    pos: u64,
+}
+
+impl File {
+    pub(crate) fn new(
+        node: Shared<Node>,
+        name: String,
+        metadata: Metadata,
+        clock: Option<fn() -> Timestamp>
+    ) -> File {
+        File {
+            node: node,
+            name: name,
+            metadata: metadata,
+            clock: clock,
+            pos: 0,
+        }
+    }
+}
+
+/// Calls `f` with the contents of the file `node`.
+fn with_data<R, F: FnOnce(&mut Vec<u8>, &mut Metadata) -> R>(node: &Shared<Node>, f: F) -> R {
+    let mut node = node.borrow_mut();
+    let node = &mut *node;
+    match node.contents {
+        Contents::File(ref mut data) => f(data, &mut node.metadata),
+        Contents::Dir(_) => unreachable!("tmpfs: file refers to a directory"),
+    }
+}
+
+impl traits::File for File {
+    /// Does nothing: the contents are always current.
+    fn sync(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+
+    fn size(&self) -> u64 {
+        with_data(&self.node, |data, _| data.len() as u64)
+    }
+
+    fn set_len(&mut self, size: u64) -> io::Result<()> {
+        let clock = self.clock;
+        with_data(&self.node, |data, metadata| {
+            data.resize(size as usize, 0);
+            if let Some(clock) = clock {
+                metadata.modified = clock();
+            }
+        });
+
+        self.pos = min(self.pos, size);
+        Ok(())
+    }
+}
+
+impl io::Read for File {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let pos = self.pos;
+        let clock = self.clock;
+        let read = with_data(&self.node, |data, metadata| {
+            let start = min(pos, data.len() as u64) as usize;
+            let amount = min(buf.len(), data.len() - start);
+            buf[..amount].copy_from_slice(&data[start..start + amount]);
+            if let Some(clock) = clock {
+                metadata.accessed = clock();
+            }
+
+            amount
+        });
+
+        self.pos += read as u64;
+        Ok(read)
+    }
+}
+
+impl io::Write for File {
+    /// Writes `buf` at the current position, growing the file as needed.
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let pos = self.pos as usize;
+        let clock = self.clock;
+        with_data(&self.node, |data, metadata| {
+            if data.len() < pos + buf.len() {
+                data.resize(pos + buf.len(), 0);
+            }
+
+            data[pos..pos + buf.len()].copy_from_slice(buf);
+            if let Some(clock) = clock {
+                metadata.modified = clock();
+            }
+        });
+
+        self.pos += buf.len() as u64;
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+impl io::Seek for File {
+    /// Seek to offset `pos` in the file.
+    ///
+    /// # Errors
+    ///
+    /// Seeking before the start of a file or beyond the end of the file results
+    /// in an `InvalidInput` error.
+    fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+        let size = traits::File::size(self);
+        let new_pos = match pos {
+            SeekFrom::Start(offset) => offset as i64,
+            SeekFrom::End(offset) => size as i64 + offset,
+            SeekFrom::Current(offset) => self.pos as i64 + offset,
+        };
+
+        if new_pos < 0 || new_pos as u64 > size {
+            return Err(io::Error::new(io::ErrorKind::InvalidInput,
+                                      "seek outside of file bounds"));
+        }
+
+        self.pos = new_pos as u64;
+        Ok(self.pos)
+    }
+}
@@ -0,0 +1,89 @@
+use std::fmt;
+
+use traits;
+
+/// A calendar date and time.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct Timestamp {
+    pub year: usize,
+    pub month: u8,
+    pub day: u8,
+    pub hour: u8,
+    pub minute: u8,
+    pub second: u8,
+}
+
+impl Default for Timestamp {
+    /// The FAT epoch, 1980-01-01 00:00:00, which is used when the file system
+    /// has no clock.
+    fn default() -> Timestamp {
+        Timestamp { year: 1980, month: 1, day: 1, hour: 0, minute: 0, second: 0 }
+    }
+}
+
+impl traits::Timestamp for Timestamp {
+    fn year(&self) -> usize {
+        self.year
+    }
+
+    fn month(&self) -> u8 {
+        self.month
+    }
+
+    fn day(&self) -> u8 {
+        self.day
+    }
+
+    fn hour(&self) -> u8 {
+        self.hour
+    }
+
+    fn minute(&self) -> u8 {
+        self.minute
+    }
+
+    fn second(&self) -> u8 {
+        self.second
+    }
+}
+
+impl fmt::Display for Timestamp {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{:02}/{:02}/{} {:02}:{:02}:{:02}",
+               self.month, self.day, self.year, self.hour, self.minute, self.second)
+    }
+}
+
+/// Metadata for a file or directory.
+#[derive(Default, Copy, Clone, Debug, PartialEq, Eq)]
+pub struct Metadata {
+    pub read_only: bool,
+    pub hidden: bool,
+    pub created: Timestamp,
+    pub accessed: Timestamp,
+    pub modified: Timestamp,
+}
+
+impl traits::Metadata for Metadata {
+    type Timestamp = Timestamp;
+
+    fn read_only(&self) -> bool {
+        self.read_only
+    }
+
+    fn hidden(&self) -> bool {
+        self.hidden
+    }
+
+    fn created(&self) -> Timestamp {
+        self.created
+    }
+
+    fn accessed(&self) -> Timestamp {
+        self.accessed
+    }
+
+    fn modified(&self) -> Timestamp {
+        self.modified
+    }
+}
@@ -0,0 +1,14 @@
+pub(crate) mod dir;
+pub(crate) mod entry;
+pub(crate) mod file;
+pub(crate) mod metadata;
+pub(crate) mod node;
+pub(crate) mod tmpfs;
+
+pub use self::dir::Dir;
+pub use self::entry::Entry;
+pub use self::file::File;
+pub use self::metadata::{Metadata, Timestamp};
+pub use self::tmpfs::TmpFs;
+
+pub(crate) use self::node::{Node, Contents};
@@ -0,0 +1,72 @@
+use vfat::Shared;
+use tmpfs::{Metadata, Timestamp};
+
+/// A file or directory held in memory.
+#[derive(Debug)]
+pub(crate) struct Node {
+    pub(crate) metadata: Metadata,
+    pub(crate) contents: Contents,
+}
+
+#[derive(Debug)]
+pub(crate) enum Contents {
+    /// The bytes of a file.
+    File(Vec<u8>),
+    /// The entries of a directory in the order they were added.
+    Dir(Vec<(String, Shared<Node>)>),
+}
+
+impl Node {
+    /// Returns a new node with contents `contents`, created at `now`.
+    pub(crate) fn new(contents: Contents, now: Timestamp) -> Shared<Node> {
+        Shared::new(Node {
+            metadata: Metadata {
+                created: now,
+                accessed: now,
+                modified: now,
+                ..Metadata::default()
+            },
+            contents: contents,
+        })
+    }
+
+    pub(crate) fn is_dir(&self) -> bool {
+        match self.contents {
+            Contents::Dir(_) => true,
+            Contents::File(_) => false,
+        }
+    }
+
+    /// The entry named `name` if this is a directory that has one.
+    pub(crate) fn child(&self, name: &str) -> Option<Shared<Node>> {
+        match self.contents {
+            Contents::Dir(ref entries) => {
+                entries.iter().find(|entry| entry.0 == name).map(|entry| entry.1.clone())
+            }
+            Contents::File(_) => None,
+        }
+    }
+
+    /// Adds the entry `node` named `name` to this directory.
+    ///
+    /// # Panics
+    ///
+    /// Panics if this is not a directory.
+    pub(crate) fn add_child(&mut self, name: String, node: Shared<Node>) {
+        match self.contents {
+            Contents::Dir(ref mut entries) => entries.push((name, node)),
+            Contents::File(_) => panic!("tmpfs: entry added to a file"),
+        }
+    }
+
+    /// Removes the entry named `name` from this directory and returns it.
+    pub(crate) fn remove_child(&mut self, name: &str) -> Option<Shared<Node>> {
+        match self.contents {
+            Contents::Dir(ref mut entries) => {
+                let index = entries.iter().position(|entry| entry.0 == name)?;
+                Some(entries.remove(index).1)
+            }
+            Contents::File(_) => None,
+        }
+    }
+}
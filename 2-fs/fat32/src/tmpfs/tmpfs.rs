@@ -0,0 +1,231 @@
+use std::io;
+use std::path::{Path, Component};
+
+use traits::FileSystem;
+use vfat::Shared;
+use tmpfs::{Node, Contents, File, Dir, Entry, Timestamp};
+
+/// A file system held entirely in memory.
+///
+/// Names are case-sensitive and may contain any character but `/`. Files and
+/// directories stay alive while a `File` or `Dir` refers to them, even after
+/// they are removed.
+#[derive(Debug)]
+pub struct TmpFs {
+    root: Shared<Node>,
+    /// Returns the current date and time for timestamps.
+    clock: Option<fn() -> Timestamp>,
+}
+
+impl TmpFs {
+    /// Returns an empty file system whose timestamps are all the FAT epoch.
+    pub fn new() -> TmpFs {
+        TmpFs::with_clock(None)
+    }
+
+    /// Returns an empty file system whose timestamps are taken from `clock`.
+    pub fn with_clock(clock: Option<fn() -> Timestamp>) -> TmpFs {
+        let now = clock.map_or(Timestamp::default(), |clock| clock());
+        TmpFs {
+            root: Node::new(Contents::Dir(Vec::new()), now),
+            clock: clock,
+        }
+    }
+
+    /// The current date and time.
+    fn now(&self) -> Timestamp {
+        self.clock.map_or(Timestamp::default(), |clock| clock())
+    }
+
+    /// Returns the node at the path with components `names`.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error of `InvalidInput` if any component but the last isn't
+    /// an existing directory, and of `NotFound` if there is no entry at the
+    /// path.
+    fn walk(&self, names: &[String]) -> io::Result<Shared<Node>> {
+        let mut node = self.root.clone();
+        for (i, name) in names.iter().enumerate() {
+            let child = node.borrow().child(name);
+            node = match child {
+                Some(child) => child,
+                None if i + 1 < names.len() => {
+                    return Err(io::Error::new(io::ErrorKind::InvalidInput,
+                                              "parent directory not found"));
+                }
+                None => return Err(io::Error::new(io::ErrorKind::NotFound, "entry not found")),
+            };
+        }
+
+        Ok(node)
+    }
+
+    /// Returns the directory holding the path with components `names` and the
+    /// path's final component.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error of `InvalidInput` if the path has no final component
+    /// or if its parent is not an existing directory.
+    fn parent(&self, names: &[String]) -> io::Result<(Shared<Node>, String)> {
+        let invalid = |msg| io::Error::new(io::ErrorKind::InvalidInput, msg);
+        let (name, parents) = names.split_last().ok_or_else(|| invalid("path has no file name"))?;
+        let parent = match self.walk(parents) {
+            Ok(parent) => parent,
+            Err(ref e) if e.kind() == io::ErrorKind::NotFound => {
+                return Err(invalid("parent directory not found"));
+            }
+            Err(e) => return Err(e),
+        };
+
+        if !parent.borrow().is_dir() {
+            return Err(invalid("parent is not a directory"));
+        }
+
+        Ok((parent, name.clone()))
+    }
+
+    /// Adds a new node with contents `contents` at the path with components
+    /// `names` and returns it.
+    fn create(&self, names: &[String], contents: Contents) -> io::Result<Entry> {
+        let (parent, name) = self.parent(names)?;
+        let mut parent = parent.borrow_mut();
+        if parent.child(&name).is_some() {
+            return Err(io::Error::new(io::ErrorKind::AlreadyExists, "entry already exists"));
+        }
+
+        let node = Node::new(contents, self.now());
+        parent.add_child(name.clone(), node.clone());
+        parent.metadata.modified = self.now();
+        Ok(Entry::new(name, node, self.clock))
+    }
+}
+
+impl Default for TmpFs {
+    fn default() -> TmpFs {
+        TmpFs::new()
+    }
+}
+
+/// The names of the components of the absolute path `path`, with `.` and
+/// `..` resolved.
+///
+/// # Errors
+///
+/// Returns an error of `InvalidInput` if `path` is not absolute or is not
+/// valid UTF-8.
+fn components(path: &Path) -> io::Result<Vec<String>> {
+    let invalid = |msg| io::Error::new(io::ErrorKind::InvalidInput, msg);
+    if !path.is_absolute() {
+        return Err(invalid("path is not absolute"));
+    }
+
+    let mut names = Vec::new();
+    for component in path.components() {
+        match component {
+            Component::Normal(name) => {
+                names.push(name.to_str().ok_or_else(|| invalid("path is not valid UTF-8"))?
+                           .to_string());
+            }
+            Component::ParentDir => { names.pop(); }
+            _ => continue,
+        }
+    }
+
+    Ok(names)
+}
+
+impl<'a> FileSystem for &'a TmpFs {
+    type File = File;
+    type Dir = Dir;
+    type Entry = Entry;
+
+    fn open<P: AsRef<Path>>(self, path: P) -> io::Result<Self::Entry> {
+        let names = components(path.as_ref())?;
+        let node = self.walk(&names)?;
+        let name = names.last().cloned().unwrap_or_else(|| String::from("/"));
+        Ok(Entry::new(name, node, self.clock))
+    }
+
+    fn create_file<P: AsRef<Path>>(self, path: P) -> io::Result<Self::File> {
+        let names = components(path.as_ref())?;
+        let entry = self.create(&names, Contents::File(Vec::new()))?;
+        Ok(::traits::Entry::into_file(entry).expect("new file"))
+    }
+
+    fn create_dir<P>(self, path: P, parents: bool) -> io::Result<Self::Dir>
+        where P: AsRef<Path>
+    {
+        let names = components(path.as_ref())?;
+        if parents {
+            for end in 1..names.len() {
+                match self.walk(&names[..end]) {
+                    Ok(ref node) if node.borrow().is_dir() => continue,
+                    Ok(_) => return Err(io::Error::new(io::ErrorKind::InvalidInput,
+                                                       "not a directory")),
+                    Err(ref e) if e.kind() == io::ErrorKind::NotFound => {
+                        self.create(&names[..end], Contents::Dir(Vec::new()))?;
+                    }
+                    Err(e) => return Err(e),
+                }
+            }
+        }
+
+        let entry = self.create(&names, Contents::Dir(Vec::new()))?;
+        Ok(::traits::Entry::into_dir(entry).expect("new directory"))
+    }
+
+    fn rename<P, Q>(self, from: P, to: Q) -> io::Result<()>
+        where P: AsRef<Path>, Q: AsRef<Path>
+    {
+        let (from, to) = (components(from.as_ref())?, components(to.as_ref())?);
+        let node = self.walk(&from)?;
+        let (old_parent, old_name) = self.parent(&from).map_err(|_| {
+            io::Error::new(io::ErrorKind::InvalidInput, "cannot rename the root directory")
+        })?;
+
+        let (new_parent, new_name) = self.parent(&to)?;
+        if from == to {
+            return Ok(());
+        }
+
+        if new_parent.borrow().child(&new_name).is_some() {
+            return Err(io::Error::new(io::ErrorKind::AlreadyExists, "entry already exists"));
+        }
+
+        if node.borrow().is_dir() && to.starts_with(&from) {
+            return Err(io::Error::new(io::ErrorKind::InvalidInput,
+                                      "cannot move a directory into itself"));
+        }
+
+        let now = self.now();
+        {
+            let mut old_parent = old_parent.borrow_mut();
+            old_parent.remove_child(&old_name);
+            old_parent.metadata.modified = now;
+        }
+
+        let mut new_parent = new_parent.borrow_mut();
+        new_parent.add_child(new_name, node);
+        new_parent.metadata.modified = now;
+        Ok(())
+    }
+
+    fn remove<P: AsRef<Path>>(self, path: P, children: bool) -> io::Result<()> {
+        let names = components(path.as_ref())?;
+        let node = self.walk(&names)?;
+        let (parent, name) = self.parent(&names).map_err(|_| {
+            io::Error::new(io::ErrorKind::InvalidInput, "cannot remove the root directory")
+        })?;
+
+        if node.borrow().is_dir() && !children {
+            return Err(io::Error::new(io::ErrorKind::Other, "entry is a directory"));
+        }
+
+        let mut parent = parent.borrow_mut();
+        parent.remove_child(&name);
+        parent.metadata.modified = self.now();
+        Ok(())
+    }
+}
@@ -52,9 +52,62 @@ pub trait BlockDevice: Send {
     /// error of `UnexpectedEof` if the length of `buf` is less than
     /// `self.sector_size()`.
     fn write_sector(&mut self, n: u64, buf: &[u8]) -> io::Result<usize>;
+
+    /// Reads consecutive sectors starting at sector `n` into `buf`.
+    ///
+    /// As many whole sectors as fit in `buf` are read. The number of bytes read
+    /// is returned. The default implementation calls `read_sector()` once per
+    /// sector; devices that can transfer several sectors at once should
+    /// override it.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if seeking or reading from `self` fails.
+    fn read_sectors(&mut self, n: u64, buf: &mut [u8]) -> io::Result<usize> {
+        let sector_size = self.sector_size() as usize;
+        let mut read = 0;
+        for (i, chunk) in buf.chunks_mut(sector_size).enumerate() {
+            if chunk.len() < sector_size {
+                break;
+            }
+
+            read += self.read_sector(n + i as u64, chunk)?;
+        }
+
+        Ok(read)
+    }
+
+    /// Overwrites consecutive sectors starting at sector `n` with the contents
+    /// of `buf`.
+    ///
+    /// As many whole sectors as `buf` contains are written. The number of bytes
+    /// written is returned. The default implementation calls `write_sector()`
+    /// once per sector; devices that can transfer several sectors at once
+    /// should override it.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if seeking or writing to `self` fails.
+    fn write_sectors(&mut self, n: u64, buf: &[u8]) -> io::Result<usize> {
+        let sector_size = self.sector_size() as usize;
+        let mut written = 0;
+        for (i, chunk) in buf.chunks(sector_size).enumerate() {
+            if chunk.len() < sector_size {
+                break;
+            }
+
+            written += self.write_sector(n + i as u64, chunk)?;
+        }
+
+        Ok(written)
+    }
 }
 
 impl<'a, T: BlockDevice> BlockDevice for &'a mut T {
+    fn sector_size(&self) -> u64 {
+        (**self).sector_size()
+    }
+
     fn read_sector(&mut self, n: u64, buf: &mut [u8]) -> io::Result<usize> {
         (*self).read_sector(n, buf)
     }
@@ -62,6 +115,14 @@ impl<'a, T: BlockDevice> BlockDevice for &'a mut T {
     fn write_sector(&mut self, n: u64, buf: &[u8]) -> io::Result<usize> {
         (*self).write_sector(n, buf)
     }
+
+    fn read_sectors(&mut self, n: u64, buf: &mut [u8]) -> io::Result<usize> {
+        (*self).read_sectors(n, buf)
+    }
+
+    fn write_sectors(&mut self, n: u64, buf: &[u8]) -> io::Result<usize> {
+        (*self).write_sectors(n, buf)
+    }
 }
 
 macro impl_for_read_write_seek($(<$($gen:tt),*>)* $T:path) {
@@ -83,6 +144,22 @@ macro impl_for_read_write_seek($(<$($gen:tt),*>)* $T:path) {
             self.write_all(&buf[..to_write])?;
             Ok(to_write)
         }
+
+        fn read_sectors(&mut self, n: u64, buf: &mut [u8]) -> io::Result<usize> {
+            let sector_size = self.sector_size();
+            let to_read = buf.len() - buf.len() % sector_size as usize;
+            self.seek(io::SeekFrom::Start(n * sector_size))?;
+            self.read_exact(&mut buf[..to_read])?;
+            Ok(to_read)
+        }
+
+        fn write_sectors(&mut self, n: u64, buf: &[u8]) -> io::Result<usize> {
+            let sector_size = self.sector_size();
+            let to_write = buf.len() - buf.len() % sector_size as usize;
+            self.seek(io::SeekFrom::Start(n * sector_size))?;
+            self.write_all(&buf[..to_write])?;
+            Ok(to_write)
+        }
     }
 }
 
@@ -10,6 +10,11 @@ pub trait File: io::Read + io::Write + io::Seek + Sized {
 
     /// Returns the size of the file in bytes.
     fn size(&self) -> u64;
+
+    /// Truncates or extends the file to `size` bytes. Bytes added to the end
+    /// of the file read as zeroes. If the file's position is past the new end,
+    /// it is moved to the end.
+    fn set_len(&mut self, size: u64) -> io::Result<()>;
 }
 
 /// Trait implemented by directories in a file system.
@@ -17,8 +22,9 @@ pub trait Dir: Sized {
     /// The type of entry stored in this directory.
     type Entry: Entry;
 
-    /// An type that is an iterator over the entries in this directory.
-    type Iter: Iterator<Item = Self::Entry>;
+    /// An type that is an iterator over the entries in this directory. An
+    /// error reading the directory is yielded in place of an entry.
+    type Iter: Iterator<Item = io::Result<Self::Entry>>;
 
     /// Returns an interator over the entries in this directory.
     fn entries(&self) -> io::Result<Self::Iter>;
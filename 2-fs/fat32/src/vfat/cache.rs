@@ -1,73 +1,225 @@
 use std::{io, fmt};
+use std::cmp::{min, max};
 use std::collections::HashMap;
 
 use traits::BlockDevice;
 
+/// The number of sectors a `CachedDevice` holds by default.
+pub const DEFAULT_CACHE_CAPACITY: usize = 256;
+
+/// The number of sectors a `CachedDevice` reads ahead by default.
+pub const DEFAULT_READ_AHEAD: usize = 8;
+
 #[derive(Debug)]
 struct CacheEntry {
     data: Vec<u8>,
-    dirty: bool
+    dirty: bool,
+    /// The value of the cache's access clock when the sector was last used.
+    last_used: u64
+}
+
+/// Counters describing the effectiveness of a `CachedDevice`.
+#[derive(Debug, Default, Copy, Clone, PartialEq, Eq)]
+pub struct CacheStats {
+    /// Accesses to a sector that was already cached.
+    pub hits: u64,
+    /// Accesses that required reading the sector from the device.
+    pub misses: u64,
+    /// Sectors dropped from the cache to make room for others.
+    pub evictions: u64,
+    /// Dirty sectors written back to the device, on eviction or flush.
+    pub writebacks: u64,
+    /// Sectors read ahead of a sequential access.
+    pub prefetched: u64,
 }
 
-pub struct Partition {
-    /// The physical sector where the partition begins.
-    pub start: u64,
-    /// The size, in bytes, of a logical sector in the partition.
-    pub sector_size: u64
+impl fmt::Display for CacheStats {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let accesses = self.hits + self.misses;
+        let hit_rate = match accesses {
+            0 => 0.0,
+            n => self.hits as f64 * 100.0 / n as f64,
+        };
+
+        write!(f, "hits: {}, misses: {} ({:.1}% hit rate), evictions: {}, write-backs: {}, \
+                   prefetched: {}",
+               self.hits, self.misses, hit_rate, self.evictions, self.writebacks,
+               self.prefetched)
+    }
 }
 
 pub struct CachedDevice {
     device: Box<BlockDevice>,
     cache: HashMap<u64, CacheEntry>,
-    partition: Partition
+    /// The maximum number of sectors held in `cache`.
+    capacity: usize,
+    /// Incremented on every access; orders sectors by recency of use.
+    clock: u64,
+    /// The number of sectors read ahead on a sequential miss.
+    read_ahead: usize,
+    /// The sector following the last one read from the device. A miss on this
+    /// sector is considered sequential.
+    next_sequential: Option<u64>,
+    stats: CacheStats
 }
 
 impl CachedDevice {
     /// Creates a new `CachedDevice` that transparently caches sectors from
-    /// `device` and maps physical sectors to logical sectors inside of
-    /// `partition`. All reads and writes from `CacheDevice` are performed on
+    /// `device`. All reads and writes from `CacheDevice` are performed on
     /// in-memory caches.
     ///
-    /// The `partition` parameter determines the size of a logical sector and
-    /// where logical sectors begin. An access to a sector `n` _before_
-    /// `partition.start` is made to physical sector `n`. Cached sectors before
-    /// `partition.start` are the size of a physical sector. An access to a
-    /// sector `n` at or after `partition.start` is made to the _logical_ sector
-    /// `n - partition.start`. Cached sectors at or after `partition.start` are
-    /// the size of a logical sector, `partition.sector_size`.
-    ///
-    /// `partition.sector_size` must be an integer multiple of
-    /// `device.sector_size()`.
+    /// Sectors are numbered and sized as on `device`. To access a single
+    /// partition, or to use logical sectors larger than the device's, wrap
+    /// `device` in a `PartitionDevice` first.
+    pub fn new<T>(device: T) -> CachedDevice
+        where T: BlockDevice + 'static
+    {
+        CachedDevice::with_capacity(device, DEFAULT_CACHE_CAPACITY)
+    }
+
+    /// Creates a new `CachedDevice` like `new()` that holds at most `capacity`
+    /// sectors. When the cache is full, the least recently used sector is
+    /// evicted, and written back first if it is dirty.
     ///
     /// # Panics
     ///
-    /// Panics if the partition's sector size is < the device's sector size.
-    pub fn new<T>(device: T, partition: Partition) -> CachedDevice
+    /// Panics if `capacity` is zero.
+    pub fn with_capacity<T>(device: T, capacity: usize) -> CachedDevice
         where T: BlockDevice + 'static
     {
-        assert!(partition.sector_size >= device.sector_size());
+        assert!(capacity > 0, "cache capacity must be non-zero");
 
         CachedDevice {
             device: Box::new(device),
             cache: HashMap::new(),
-            partition: partition
+            capacity: capacity,
+            clock: 0,
+            read_ahead: DEFAULT_READ_AHEAD,
+            next_sequential: None,
+            stats: CacheStats::default()
         }
     }
 
-    /// Maps a user's request for a sector `virt` to the physical sector and
-    /// number of physical sectors required to access `virt`.
-    fn virtual_to_physical(&self, virt: u64) -> (u64, u64) {
-        if self.device.sector_size() == self.partition.sector_size {
-            (virt, 1)
-        } else if virt < self.partition.start {
-            (virt, 1)
+    /// Sets the maximum number of sectors held in the cache, evicting the
+    /// least recently used sectors if more than `capacity` are cached.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if writing back an evicted dirty sector fails. The
+    /// capacity is updated regardless.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `capacity` is zero.
+    pub fn set_capacity(&mut self, capacity: usize) -> io::Result<()> {
+        assert!(capacity > 0, "cache capacity must be non-zero");
+        self.capacity = capacity;
+        while self.cache.len() > self.capacity {
+            self.evict()?;
+        }
+
+        Ok(())
+    }
+
+    /// Sets the number of sectors read ahead when a sector following the last
+    /// one read from the device is missed, as happens when reading consecutive
+    /// clusters. At most half of the cache's capacity is read at once. A depth
+    /// of zero disables read-ahead.
+    pub fn set_read_ahead(&mut self, sectors: usize) {
+        self.read_ahead = sectors;
+    }
+
+    /// The cache's hit, miss, eviction, and write-back counters.
+    pub fn stats(&self) -> CacheStats {
+        self.stats
+    }
+
+    /// Reads sector `sector` into the cache if it isn't already cached and
+    /// returns the cache entry for it. If the cache is full, the least
+    /// recently used sectors are evicted first.
+    fn entry(&mut self, sector: u64) -> io::Result<&mut CacheEntry> {
+        self.clock += 1;
+        if self.cache.contains_key(&sector) {
+            self.stats.hits += 1;
         } else {
-            let factor = self.partition.sector_size / self.device.sector_size();
-            let logical_offset = virt - self.partition.start;
-            let physical_offset = logical_offset * factor;
-            let physical_sector = self.partition.start + physical_offset;
-            (physical_sector, factor)
+            self.stats.misses += 1;
+            let wanted = match self.next_sequential == Some(sector) {
+                true => 1 + self.read_ahead,
+                false => 1,
+            };
+
+            let limit = min(wanted, max(1, self.capacity / 2));
+            let count = (1..limit as u64)
+                .take_while(|&i| !self.cache.contains_key(&(sector + i)))
+                .count() + 1;
+            self.fetch(sector, count)?;
+        }
+
+        let entry = self.cache.get_mut(&sector).expect("sector is cached");
+        entry.last_used = self.clock;
+        Ok(entry)
+    }
+
+    /// Reads the `count` uncached sectors starting at `sector` from the device
+    /// with a single transfer and caches them. If reading more than one sector
+    /// fails, as it may at the end of the device, only `sector` is read.
+    fn fetch(&mut self, sector: u64, count: usize) -> io::Result<()> {
+        while self.cache.len() + count > self.capacity {
+            self.evict()?;
         }
+
+        let sector_size = self.device.sector_size() as usize;
+        let mut data = vec![0u8; sector_size * count];
+        let read = match self.device.read_sectors(sector, &mut data) {
+            Ok(read) => read,
+            Err(_) if count > 1 => return self.fetch(sector, 1),
+            Err(e) => return Err(e),
+        };
+
+        if read < sector_size {
+            return Err(io::Error::new(io::ErrorKind::UnexpectedEof,
+                                      "device returned a partial sector"));
+        }
+
+        let fetched = read / sector_size;
+        for (i, chunk) in data[..fetched * sector_size].chunks(sector_size).enumerate() {
+            let entry = CacheEntry { data: chunk.to_vec(), dirty: false, last_used: self.clock };
+            self.cache.insert(sector + i as u64, entry);
+        }
+
+        self.stats.prefetched += fetched as u64 - 1;
+        self.next_sequential = Some(sector + fetched as u64);
+        Ok(())
+    }
+
+    /// Evicts the least recently used sector, writing it back first if it is
+    /// dirty. If the write fails, the sector stays cached.
+    fn evict(&mut self) -> io::Result<()> {
+        let victim = self.cache.iter()
+            .min_by_key(|&(_, entry)| entry.last_used)
+            .map(|(&sector, _)| sector);
+
+        if let Some(sector) = victim {
+            self.write_back(sector)?;
+            self.cache.remove(&sector);
+            self.stats.evictions += 1;
+        }
+
+        Ok(())
+    }
+
+    /// Writes the cached sector `sector` to the device if it is dirty.
+    fn write_back(&mut self, sector: u64) -> io::Result<()> {
+        let entry = match self.cache.get_mut(&sector) {
+            Some(entry) if entry.dirty => entry,
+            _ => return Ok(()),
+        };
+
+        self.device.write_sectors(sector, &entry.data)?;
+
+        entry.dirty = false;
+        self.stats.writebacks += 1;
+        Ok(())
     }
 
     /// Returns a mutable reference to the cached sector `sector`. If the sector
@@ -81,7 +233,9 @@ impl CachedDevice {
     ///
     /// Returns an error if there is an error reading the sector from the disk.
     pub fn get_mut(&mut self, sector: u64) -> io::Result<&mut [u8]> {
-        unimplemented!("CachedDevice::get_mut()")
+        let entry = self.entry(sector)?;
+        entry.dirty = true;
+        Ok(&mut entry.data)
     }
 
     /// Returns a reference to the cached sector `sector`. If the sector is not
@@ -91,18 +245,62 @@ impl CachedDevice {
     ///
     /// Returns an error if there is an error reading the sector from the disk.
     pub fn get(&mut self, sector: u64) -> io::Result<&[u8]> {
-        unimplemented!("CachedDevice::get()")
+        Ok(&self.entry(sector)?.data)
+    }
+
+    /// Writes every dirty cached sector back to the underlying device.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if there is an error writing a sector to the disk. The
+    /// sectors that were not written remain dirty.
+    pub fn flush(&mut self) -> io::Result<()> {
+        let mut dirty: Vec<u64> = self.cache.iter()
+            .filter(|&(_, entry)| entry.dirty)
+            .map(|(&sector, _)| sector)
+            .collect();
+        dirty.sort();
+
+        for sector in dirty {
+            self.write_back(sector)?;
+        }
+
+        Ok(())
     }
 }
 
-// FIXME: Implement `BlockDevice` for `CacheDevice`. The `read_sector` and
-// `write_sector` methods should only read/write from/to cached sectors.
+impl BlockDevice for CachedDevice {
+    fn sector_size(&self) -> u64 {
+        self.device.sector_size()
+    }
+
+    fn read_sector(&mut self, n: u64, buf: &mut [u8]) -> io::Result<usize> {
+        let sector = self.get(n)?;
+        let amount = min(sector.len(), buf.len());
+        buf[..amount].copy_from_slice(&sector[..amount]);
+        Ok(amount)
+    }
+
+    fn write_sector(&mut self, n: u64, buf: &[u8]) -> io::Result<usize> {
+        let sector = self.get_mut(n)?;
+        if buf.len() < sector.len() {
+            return Err(io::Error::new(io::ErrorKind::UnexpectedEof,
+                                      "buffer is smaller than a sector"));
+        }
+
+        let amount = sector.len();
+        sector.copy_from_slice(&buf[..amount]);
+        Ok(amount)
+    }
+}
 
 impl fmt::Debug for CachedDevice {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         f.debug_struct("CachedDevice")
             .field("device", &"<block device>")
-            .field("cache", &self.cache)
+            .field("cached", &self.cache.len())
+            .field("capacity", &self.capacity)
+            .field("stats", &self.stats)
             .finish()
     }
 }
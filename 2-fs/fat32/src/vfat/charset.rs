@@ -0,0 +1,105 @@
+use std::fmt;
+
+/// An OEM code page, in which the bytes of short names are stored. Bytes below
+/// `0x80` are ASCII; the table maps the bytes `0x80` through `0xFF` to
+/// Unicode.
+#[derive(Copy, Clone)]
+pub struct CodePage {
+    high: [char; 128],
+}
+
+/// Code page 437, the original IBM PC character set and the default code page
+/// of short names.
+pub static CP437: CodePage = CodePage {
+    high: [
+        'Ç', 'ü', 'é', 'â', 'ä', 'à', 'å', 'ç', 'ê', 'ë', 'è', 'ï', 'î', 'ì', 'Ä', 'Å',
+        'É', 'æ', 'Æ', 'ô', 'ö', 'ò', 'û', 'ù', 'ÿ', 'Ö', 'Ü', '¢', '£', '¥', '₧', 'ƒ',
+        'á', 'í', 'ó', 'ú', 'ñ', 'Ñ', 'ª', 'º', '¿', '⌐', '¬', '½', '¼', '¡', '«', '»',
+        '░', '▒', '▓', '│', '┤', '╡', '╢', '╖', '╕', '╣', '║', '╗', '╝', '╜', '╛', '┐',
+        '└', '┴', '┬', '├', '─', '┼', '╞', '╟', '╚', '╔', '╩', '╦', '╠', '═', '╬', '╧',
+        '╨', '╤', '╥', '╙', '╘', '╒', '╓', '╫', '╪', '┘', '┌', '█', '▄', '▌', '▐', '▀',
+        'α', 'ß', 'Γ', 'π', 'Σ', 'σ', 'µ', 'τ', 'Φ', 'Θ', 'Ω', 'δ', '∞', 'φ', 'ε', '∩',
+        '≡', '±', '≥', '≤', '⌠', '⌡', '÷', '≈', '°', '∙', '·', '√', 'ⁿ', '²', '■', '\u{A0}',
+    ],
+};
+
+impl CodePage {
+    /// Returns a code page mapping the byte `0x80 + i` to `high[i]`.
+    pub fn new(high: [char; 128]) -> CodePage {
+        CodePage { high: high }
+    }
+
+    /// The character for the byte `byte`.
+    pub fn decode(&self, byte: u8) -> char {
+        match byte {
+            0x00..=0x7F => byte as char,
+            _ => self.high[byte as usize - 0x80],
+        }
+    }
+
+    /// The characters for the bytes `bytes`.
+    pub fn decode_bytes(&self, bytes: &[u8]) -> String {
+        bytes.iter().map(|&byte| self.decode(byte)).collect()
+    }
+
+    /// The byte for the character `c`, or `None` if the code page has no such
+    /// character.
+    pub fn encode(&self, c: char) -> Option<u8> {
+        match c.is_ascii() {
+            true => Some(c as u8),
+            false => self.high.iter().position(|&h| h == c).map(|i| (i + 0x80) as u8),
+        }
+    }
+}
+
+impl fmt::Debug for CodePage {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("CodePage")
+            .field("high", &self.high.iter().collect::<String>())
+            .finish()
+    }
+}
+
+/// Returns the Unicode simple case folding of `c`: the single character that
+/// `c` and every character differing from it only in case map to.
+///
+/// The simple lowercase mapping is the folding for nearly every character.
+/// The exceptions are characters whose lowercase form differs from the folded
+/// form, such as final sigma, and characters with no single character
+/// lowercase form, which fold to themselves.
+pub fn fold_case(c: char) -> char {
+    match c {
+        '\u{B5}' => '\u{3BC}',
+        '\u{17F}' => 's',
+        '\u{345}' | '\u{1FBE}' => '\u{3B9}',
+        '\u{3C2}' => '\u{3C3}',
+        '\u{3D0}' => '\u{3B2}',
+        '\u{3D1}' => '\u{3B8}',
+        '\u{3D5}' => '\u{3C6}',
+        '\u{3D6}' => '\u{3C0}',
+        '\u{3F0}' => '\u{3BA}',
+        '\u{3F1}' => '\u{3C1}',
+        '\u{3F5}' => '\u{3B5}',
+        '\u{1C80}' => '\u{432}',
+        '\u{1C81}' => '\u{434}',
+        '\u{1C82}' => '\u{43E}',
+        '\u{1C83}' => '\u{441}',
+        '\u{1C84}' | '\u{1C85}' => '\u{442}',
+        '\u{1C86}' => '\u{44A}',
+        '\u{1C87}' => '\u{463}',
+        '\u{1C88}' => '\u{A64B}',
+        '\u{1E9B}' => '\u{1E61}',
+        _ => {
+            let mut lower = c.to_lowercase();
+            match (lower.next(), lower.next()) {
+                (Some(lower), None) => lower,
+                _ => c,
+            }
+        }
+    }
+}
+
+/// Returns `true` if `a` and `b` are equal under Unicode simple case folding.
+pub fn names_match(a: &str, b: &str) -> bool {
+    a.chars().map(fold_case).eq(b.chars().map(fold_case))
+}
@@ -9,4 +9,25 @@ impl From<u32> for Cluster {
     }
 }
 
-// TODO: Implement any useful helper methods on `Cluster`.
+impl Cluster {
+    /// The raw cluster number.
+    pub fn number(&self) -> u32 {
+        self.0
+    }
+
+    /// Returns `true` if this cluster number can refer to a data cluster.
+    /// Cluster numbers `0` and `1` are reserved.
+    pub fn is_data(&self) -> bool {
+        self.0 >= 2
+    }
+
+    /// The zero-based index of this cluster in the data region.
+    ///
+    /// # Panics
+    ///
+    /// Panics if this is not a data cluster.
+    pub fn data_index(&self) -> u64 {
+        assert!(self.is_data(), "cluster {} is not a data cluster", self.0);
+        (self.0 - 2) as u64
+    }
+}
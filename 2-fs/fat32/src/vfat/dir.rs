@@ -1,34 +1,74 @@
 use std::ffi::OsStr;
 use std::char::decode_utf16;
-use std::borrow::Cow;
-use std::io;
+use std::{io, mem};
+use std::cmp::min;
 
 use traits;
 use util::VecExt;
-use vfat::{VFat, Shared, File, Cluster, Entry};
+use vfat::{VFat, Shared, File, Cluster, Entry, Error, CodePage};
+use vfat::charset::names_match;
 use vfat::{Metadata, Attributes, Timestamp, Time, Date};
 
 #[derive(Debug)]
 pub struct Dir {
-    // FIXME: Fill me in.
+    pub(crate) vfat: Shared<VFat>,
+    pub(crate) first_cluster: Cluster,
+    pub(crate) name: String,
+    pub(crate) metadata: Metadata,
+    /// The location of this directory's entry in its parent. `None` for the
+    /// root directory.
+    pub(crate) location: Option<EntryLocation>,
+}
+
+/// The location of a regular directory entry inside of its parent directory.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub(crate) struct EntryLocation {
+    /// The first cluster of the parent directory.
+    pub dir: Cluster,
+    /// The byte offset of the regular entry from the start of `dir`.
+    pub offset: u64,
+    /// The number of LFN entries immediately preceding the regular entry.
+    pub lfn_entries: usize,
 }
 
 #[repr(C, packed)]
 #[derive(Copy, Clone)]
 pub struct VFatRegularDirEntry {
-    // FIXME: Fill me in.
+    name: [u8; 8],
+    extension: [u8; 3],
+    attributes: Attributes,
+    reserved_nt: u8,
+    created_tenths: u8,
+    created_time: Time,
+    created_date: Date,
+    accessed_date: Date,
+    cluster_high: u16,
+    modified_time: Time,
+    modified_date: Date,
+    cluster_low: u16,
+    file_size: u32,
 }
 
 #[repr(C, packed)]
 #[derive(Copy, Clone)]
 pub struct VFatLfnDirEntry {
-    // FIXME: Fill me in.
+    sequence: u8,
+    name_1: [u16; 5],
+    attributes: Attributes,
+    entry_type: u8,
+    checksum: u8,
+    name_2: [u16; 6],
+    first_cluster: u16,
+    name_3: [u16; 2],
 }
 
 #[repr(C, packed)]
 #[derive(Copy, Clone)]
 pub struct VFatUnknownDirEntry {
-    // FIXME: Fill me in.
+    id: u8,
+    reserved_1: [u8; 10],
+    attributes: Attributes,
+    reserved_2: [u8; 20],
 }
 
 pub union VFatDirEntry {
@@ -37,9 +77,746 @@ pub union VFatDirEntry {
     long_filename: VFatLfnDirEntry,
 }
 
+/// The first byte of a directory entry marking the end of the directory.
+const END_OF_DIR: u8 = 0x00;
+
+/// The first byte of a directory entry marking it as deleted/unused.
+const DELETED: u8 = 0xE5;
+
+/// Returns the checksum of the 11-byte short name `name` that is stored in
+/// every LFN entry belonging to that short name.
+pub(crate) fn short_name_checksum(name: &[u8; 11]) -> u8 {
+    name.iter().fold(0u8, |sum, &b| {
+        ((sum & 1) << 7).wrapping_add(sum >> 1).wrapping_add(b)
+    })
+}
+
+/// Characters that may not appear in a long file name.
+const INVALID_LFN_CHARS: &str = "\"*/:<>?\\|";
+
+/// Characters, in addition to `INVALID_LFN_CHARS`, that may not appear in a
+/// short name.
+const INVALID_SHORT_CHARS: &str = "+,;=[] .";
+
+/// Returns an error of `InvalidInput` if `name` can't be stored as the long
+/// file name of a directory entry.
+pub(crate) fn validate_name(name: &str) -> io::Result<()> {
+    let invalid = name.is_empty()
+        || name == "." || name == ".."
+        || name.encode_utf16().count() > 255
+        || name.chars().any(|c| (c as u32) < 0x20 || INVALID_LFN_CHARS.contains(c));
+
+    match invalid {
+        true => Err(io::Error::new(io::ErrorKind::InvalidInput, "invalid file name")),
+        false => Ok(()),
+    }
+}
+
+/// The flag in a regular entry's NT reserved byte marking the name part of its
+/// short name as lowercase.
+const NT_LOWERCASE_NAME: u8 = 0x08;
+
+/// The flag in a regular entry's NT reserved byte marking the extension of
+/// its short name as lowercase.
+const NT_LOWERCASE_EXTENSION: u8 = 0x10;
+
+/// The number of `~N` numeric tails tried before switching to tails that
+/// include a hash of the long name, as Windows does.
+const NUMERIC_TAILS: u32 = 4;
+
+/// Returns the raw short name and NT case flags for `name` if `name` can be
+/// stored as a short name alone: an 8 character name and optional 3
+/// character extension of valid short name characters, each part entirely
+/// uppercase or entirely lowercase.
+fn plain_short_name(name: &str) -> Option<([u8; 11], u8)> {
+    let (base, extension) = match name.rfind('.') {
+        Some(i) => (&name[..i], &name[i + 1..]),
+        None => (name, ""),
+    };
+
+    let valid = |part: &str, max: usize| {
+        part.len() <= max && part.chars().all(|c| {
+            c.is_ascii() && c > ' ' && !INVALID_LFN_CHARS.contains(c)
+                && !INVALID_SHORT_CHARS.contains(c)
+        })
+    };
+
+    if base.is_empty() || !valid(base, 8) || !valid(extension, 3)
+        || (name.ends_with('.') && extension.is_empty()) {
+        return None;
+    }
+
+    // Only a part that is entirely lowercase can be flagged as such.
+    let case_flag = |part: &str, flag: u8| -> Option<u8> {
+        let lower = part.chars().any(|c| c.is_ascii_lowercase());
+        let upper = part.chars().any(|c| c.is_ascii_uppercase());
+        match (lower, upper) {
+            (true, true) => None,
+            (true, false) => Some(flag),
+            (false, _) => Some(0),
+        }
+    };
+
+    let flags = case_flag(base, NT_LOWERCASE_NAME)? | case_flag(extension, NT_LOWERCASE_EXTENSION)?;
+    let raw = raw_short_name(base.to_ascii_uppercase().as_bytes(),
+                             extension.to_ascii_uppercase().as_bytes());
+    Some((raw, flags))
+}
+
+/// Returns the upper-cased 8.3 basis name for `name`, encoded in `code_page`,
+/// as the name and extension parts, and whether anything other than case was
+/// lost in the conversion. Characters that are invalid in a short name or
+/// missing from `code_page` are replaced with `_`; spaces and periods other
+/// than the extension separator are removed.
+fn short_name_basis(name: &str, code_page: &CodePage) -> (Vec<u8>, Vec<u8>, bool) {
+    let mut lossy = false;
+    let mut to_short = |part: &str, max: usize| -> Vec<u8> {
+        let mut short = Vec::new();
+        for c in part.chars() {
+            if c == ' ' || c == '.' {
+                lossy = true;
+            } else if short.len() == max {
+                lossy = true;
+                break;
+            } else if c.is_ascii() && !INVALID_SHORT_CHARS.contains(c) {
+                short.push(c.to_ascii_uppercase() as u8);
+            } else {
+                let mut upper = c.to_uppercase();
+                let byte = match (upper.next(), upper.next()) {
+                    (Some(upper), None) if !upper.is_ascii() => code_page.encode(upper),
+                    _ => None,
+                };
+
+                lossy |= byte.is_none();
+                short.push(byte.unwrap_or(b'_'));
+            }
+        }
+
+        short
+    };
+
+    let trimmed = name.trim_start_matches('.');
+    let (base, extension) = match trimmed.rfind('.') {
+        Some(i) => (to_short(&trimmed[..i], 8), to_short(&trimmed[i + 1..], 3)),
+        None => (to_short(trimmed, 8), Vec::new()),
+    };
+
+    (base, extension, lossy || trimmed.len() != name.len())
+}
+
+/// Builds the raw 11-byte short name from `name` and `extension`, padding
+/// both with spaces. A leading `0xE5`, which marks deleted entries, is stored
+/// as `0x05`.
+fn raw_short_name(name: &[u8], extension: &[u8]) -> [u8; 11] {
+    let mut raw = [b' '; 11];
+    raw[..name.len()].copy_from_slice(name);
+    raw[8..8 + extension.len()].copy_from_slice(extension);
+    if raw[0] == DELETED {
+        raw[0] = 0x05;
+    }
+
+    raw
+}
+
+/// Returns a 16-bit hash of the long name `name`, used in place of most of
+/// the basis name once the plain numeric tails are exhausted.
+fn long_name_hash(name: &str) -> u16 {
+    let checksum = name.encode_utf16().fold(0u16, |sum, unit| {
+        sum.wrapping_mul(0x25).wrapping_add(unit)
+    });
+
+    let temp = (checksum as i32).wrapping_mul(314159269).wrapping_abs();
+    let quotient = ((temp as u32 as u64 * 1152921497) >> 60) as i32;
+    let checksum = temp.wrapping_sub(quotient.wrapping_mul(1000000007)) as u16;
+
+    // The nibbles are reversed so that the fastest changing one comes first.
+    (checksum & 0xF000) >> 12 | (checksum & 0x0F00) >> 4
+        | (checksum & 0x00F0) << 4 | (checksum & 0x000F) << 12
+}
+
+/// Generates a short name alias, encoded in `code_page`, for the long name
+/// `name` that doesn't collide with any name in `existing`.
+///
+/// As in Windows, the upper-cased basis name is used as is if nothing but
+/// case was lost in creating it. Otherwise, the basis is given a numeric tail
+/// `~1` through `~4`. If all of those are taken, the first two characters of
+/// the basis are followed by a hash of `name` and a numeric tail.
+fn generate_short_name(
+    name: &str,
+    existing: &[[u8; 11]],
+    code_page: &CodePage
+) -> io::Result<[u8; 11]> {
+    let (mut basis, extension, lossy) = short_name_basis(name, code_page);
+    if basis.is_empty() {
+        basis.push(b'_');
+    }
+
+    let plain = raw_short_name(&basis, &extension);
+    if !lossy && !existing.contains(&plain) {
+        return Ok(plain);
+    }
+
+    let with_tail = |prefix: &[u8], n: u32| {
+        let tail = format!("~{}", n);
+        let keep = min(prefix.len(), 8 - tail.len());
+        let mut short = prefix[..keep].to_vec();
+        short.extend_from_slice(tail.as_bytes());
+        raw_short_name(&short, &extension)
+    };
+
+    for n in 1..NUMERIC_TAILS + 1 {
+        let raw = with_tail(&basis, n);
+        if !existing.contains(&raw) {
+            return Ok(raw);
+        }
+    }
+
+    let mut prefix = basis[..min(basis.len(), 2)].to_vec();
+    prefix.extend_from_slice(format!("{:04X}", long_name_hash(name)).as_bytes());
+    for n in 1..1_000_000u32 {
+        let raw = with_tail(&prefix, n);
+        if !existing.contains(&raw) {
+            return Ok(raw);
+        }
+    }
+
+    Err(io::Error::new(io::ErrorKind::AlreadyExists, "no short name available"))
+}
+
+impl VFatRegularDirEntry {
+    /// Returns a new entry with the raw short name `name`, the attributes
+    /// `attributes`, and first cluster `cluster`. All other fields are zero.
+    pub(crate) fn new(name: [u8; 11], attributes: Attributes, cluster: Cluster) -> VFatRegularDirEntry {
+        let mut entry = VFatRegularDirEntry::from_bytes(&[0u8; 32]);
+        entry.set_raw_name(name);
+        entry.attributes = attributes;
+        entry.set_cluster(cluster);
+        entry
+    }
+
+    /// Reads a regular directory entry from the 32 bytes in `bytes`.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `bytes` is shorter than a directory entry.
+    pub(crate) fn from_bytes(bytes: &[u8]) -> VFatRegularDirEntry {
+        let mut raw = [0u8; 32];
+        raw.copy_from_slice(&bytes[..32]);
+        unsafe { mem::transmute(raw) }
+    }
+
+    /// The on-disk bytes of this entry.
+    pub(crate) fn to_bytes(&self) -> [u8; 32] {
+        unsafe { mem::transmute(*self) }
+    }
+
+    /// The 8.3 name of this entry as stored on disk: 8 bytes of name followed
+    /// by 3 bytes of extension, both padded with spaces.
+    pub(crate) fn raw_name(&self) -> [u8; 11] {
+        let mut raw = [0u8; 11];
+        raw[..8].copy_from_slice(&self.name);
+        raw[8..].copy_from_slice(&self.extension);
+        raw
+    }
+
+    pub(crate) fn set_raw_name(&mut self, raw: [u8; 11]) {
+        self.name.copy_from_slice(&raw[..8]);
+        self.extension.copy_from_slice(&raw[8..]);
+    }
+
+    /// Sets the NT flags that mark the name and extension of the short name as
+    /// lowercase, clearing the other flags in the NT reserved byte.
+    fn set_case_flags(&mut self, flags: u8) {
+        self.reserved_nt = flags & (NT_LOWERCASE_NAME | NT_LOWERCASE_EXTENSION);
+    }
+
+    /// The 8.3 name of this entry formatted as `NAME.EXT` and decoded from
+    /// `code_page`. Parts flagged as lowercase in the NT reserved byte are
+    /// lowercased.
+    pub(crate) fn short_name(&self, code_page: &CodePage) -> String {
+        let mut name = self.name;
+        if name[0] == 0x05 {
+            name[0] = DELETED;
+        }
+
+        let trim = |bytes: &[u8], lowercase: bool| -> String {
+            let end = bytes.iter().rposition(|&b| b != b' ').map_or(0, |i| i + 1);
+            let part = code_page.decode_bytes(&bytes[..end]);
+            match lowercase {
+                true => part.to_ascii_lowercase(),
+                false => part,
+            }
+        };
+
+        let name = trim(&name, self.reserved_nt & NT_LOWERCASE_NAME != 0);
+        let extension = trim(&self.extension, self.reserved_nt & NT_LOWERCASE_EXTENSION != 0);
+        match extension.is_empty() {
+            true => name,
+            false => format!("{}.{}", name, extension),
+        }
+    }
+
+    pub(crate) fn cluster(&self) -> Cluster {
+        Cluster::from(((self.cluster_high as u32) << 16) | self.cluster_low as u32)
+    }
+
+    pub(crate) fn set_cluster(&mut self, cluster: Cluster) {
+        self.cluster_high = (cluster.number() >> 16) as u16;
+        self.cluster_low = cluster.number() as u16;
+    }
+
+    pub(crate) fn size(&self) -> u32 {
+        self.file_size
+    }
+
+    pub(crate) fn set_size(&mut self, size: u32) {
+        self.file_size = size;
+    }
+
+    pub(crate) fn set_accessed_date(&mut self, date: Date) {
+        self.accessed_date = date;
+    }
+
+    pub(crate) fn metadata(&self) -> Metadata {
+        Metadata {
+            attributes: self.attributes,
+            created: Timestamp {
+                date: self.created_date,
+                time: self.created_time,
+                increment_10ms: self.created_tenths,
+            },
+            accessed: Timestamp { date: self.accessed_date, ..Timestamp::default() },
+            modified: Timestamp {
+                date: self.modified_date,
+                time: self.modified_time,
+                increment_10ms: 0,
+            },
+        }
+    }
+}
+
+impl VFatLfnDirEntry {
+    /// Returns the raw LFN entries, in on-disk order, that store the long file
+    /// name `name` for the short name with checksum `checksum`.
+    fn entries_for(name: &str, checksum: u8) -> Vec<[u8; 32]> {
+        let mut units: Vec<u16> = name.encode_utf16().collect();
+        if units.len() % 13 != 0 {
+            units.push(0x0000);
+        }
+
+        while units.len() % 13 != 0 {
+            units.push(0xFFFF);
+        }
+
+        let count = units.len() / 13;
+        (0..count).rev().map(|i| {
+            let part = &units[i * 13..(i + 1) * 13];
+            let mut entry = VFatLfnDirEntry {
+                sequence: (i + 1) as u8 | if i + 1 == count { 0x40 } else { 0 },
+                name_1: [0; 5],
+                attributes: Attributes::new(Attributes::LFN),
+                entry_type: 0,
+                checksum: checksum,
+                name_2: [0; 6],
+                first_cluster: 0,
+                name_3: [0; 2],
+            };
+
+            let (mut name_1, mut name_2, mut name_3) = ([0u16; 5], [0u16; 6], [0u16; 2]);
+            name_1.copy_from_slice(&part[..5]);
+            name_2.copy_from_slice(&part[5..11]);
+            name_3.copy_from_slice(&part[11..]);
+            entry.name_1 = name_1;
+            entry.name_2 = name_2;
+            entry.name_3 = name_3;
+            unsafe { mem::transmute(entry) }
+        }).collect()
+    }
+
+    /// The UTF-16 code units of this entry's portion of the long file name.
+    fn name_units(&self) -> [u16; 13] {
+        let (name_1, name_2, name_3) = (self.name_1, self.name_2, self.name_3);
+        let mut units = [0u16; 13];
+        units[..5].copy_from_slice(&name_1);
+        units[5..11].copy_from_slice(&name_2);
+        units[11..].copy_from_slice(&name_3);
+        units
+    }
+}
+
+/// Iterator over the entries of a `Dir`.
+///
+/// Entries are decoded one cluster at a time; only the cluster being decoded
+/// is held in memory. An error, such as a broken cluster chain or an entry
+/// referring to a cluster outside of the volume, is yielded once as
+/// `Some(Err(..))`, after which iteration ends. `try_next()` returns the same
+/// error as `Err(..)`.
+pub struct EntryIter {
+    vfat: Shared<VFat>,
+    dir: Cluster,
+    root: Cluster,
+    /// The entries of the cluster being decoded. The fixed root directory
+    /// region of a FAT12 or FAT16 volume is decoded as a single block.
+    entries: Vec<VFatDirEntry>,
+    /// The index of the next entry to decode in `entries`.
+    index: usize,
+    /// The index in the directory of the first entry in `entries`.
+    base: usize,
+    /// The cluster holding `entries`, or `dir` for the fixed root directory.
+    cluster: Cluster,
+    /// The cluster to read once `entries` is exhausted.
+    next_cluster: Option<Cluster>,
+    /// The code page of short names.
+    code_page: CodePage,
+    /// The number of clusters of the directory read so far.
+    clusters_read: u64,
+}
+
+/// A regular directory entry as stored on disk, as returned by
+/// `EntryIter::next_raw()`.
+pub(crate) struct RawEntry {
+    pub regular: VFatRegularDirEntry,
+    /// The entry's long file name, or its short name if it has no valid long
+    /// file name.
+    pub name: String,
+    pub location: EntryLocation,
+    /// The number of LFN entries directly preceding the regular entry whose
+    /// checksum does not match its short name.
+    pub orphaned_lfn_entries: usize,
+}
+
+impl EntryIter {
+    /// Returns an iterator over the entries of the directory starting at
+    /// `dir`. Nothing is read until the first entry is requested, except for
+    /// the fixed root directory region.
+    fn new(vfat: Shared<VFat>, dir: Cluster) -> io::Result<EntryIter> {
+        let (root, fixed_root, code_page) = {
+            let vfat = vfat.borrow();
+            (vfat.root_dir_cluster(), vfat.is_fixed_root(dir), *vfat.code_page())
+        };
+
+        let mut iter = EntryIter {
+            vfat: vfat,
+            dir: dir,
+            root: root,
+            entries: Vec::new(),
+            index: 0,
+            base: 0,
+            cluster: dir,
+            next_cluster: Some(dir),
+            code_page: code_page,
+            clusters_read: 0,
+        };
+
+        if fixed_root {
+            let mut data = Vec::new();
+            iter.vfat.borrow_mut().read_chain(dir, &mut data)?;
+            iter.entries = entries_from(data);
+            iter.next_cluster = None;
+        }
+
+        Ok(iter)
+    }
+
+    /// Ends the iteration.
+    fn finish(&mut self) {
+        self.base += self.entries.len();
+        self.entries = Vec::new();
+        self.index = 0;
+        self.next_cluster = None;
+    }
+
+    /// Makes `entries[index]` the next undecoded entry of the directory,
+    /// reading the next cluster of the directory if needed. Returns `false`
+    /// at the end of the directory's chain.
+    fn fill(&mut self) -> io::Result<bool> {
+        while self.index == self.entries.len() {
+            let cluster = match self.next_cluster {
+                Some(cluster) => cluster,
+                None => return Ok(false),
+            };
+
+            let mut vfat = self.vfat.borrow_mut();
+            let mut data = vec![0u8; vfat.bytes_per_cluster()];
+            vfat.read_cluster(cluster, 0, &mut data)?;
+            self.next_cluster = vfat.next_in_chain(cluster, self.clusters_read)?;
+            self.clusters_read += 1;
+
+            self.base += self.entries.len();
+            self.entries = entries_from(data);
+            self.index = 0;
+            self.cluster = cluster;
+        }
+
+        Ok(true)
+    }
+
+    /// Builds an `Entry` for the raw entry `raw`.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error of `InvalidData` carrying `Error::CorruptEntry` if
+    /// `raw` refers to a cluster outside of the volume or is a directory other
+    /// than `..` without a cluster.
+    fn entry(&self, raw: RawEntry) -> io::Result<Entry> {
+        let metadata = raw.regular.metadata();
+        let cluster = raw.regular.cluster();
+        let in_volume = cluster.number() < self.vfat.borrow().cluster_count() + 2;
+        let is_dot = raw.name == "." || raw.name == "..";
+        if !in_volume || (metadata.attributes.directory() && !cluster.is_data() && !is_dot) {
+            let (sector, _) = self.vfat.borrow_mut().dir_position(self.dir, raw.location.offset)?;
+            return Err(Error::CorruptEntry { sector: sector, cluster: self.cluster.number() }.into());
+        }
+
+        if metadata.attributes.directory() {
+            let first_cluster = match cluster.is_data() {
+                true => cluster,
+                false => self.root,
+            };
+
+            Ok(Entry::Dir(Dir {
+                vfat: self.vfat.clone(),
+                first_cluster: first_cluster,
+                name: raw.name,
+                metadata: metadata,
+                location: Some(raw.location),
+            }))
+        } else {
+            Ok(Entry::File(File::new(
+                self.vfat.clone(),
+                match cluster.is_data() { true => Some(cluster), false => None },
+                raw.name,
+                metadata,
+                raw.regular.size() as u64,
+                raw.location,
+            )))
+        }
+    }
+
+    /// Returns the next entry in the directory, `None` at its end, or the
+    /// error that ends the iteration.
+    pub fn try_next(&mut self) -> io::Result<Option<Entry>> {
+        let result = match self.try_next_raw() {
+            Ok(Some(raw)) => self.entry(raw).map(Some),
+            other => other.map(|_| None),
+        };
+
+        if result.is_err() {
+            self.finish();
+        }
+
+        result
+    }
+
+    /// Returns the next regular entry in the directory without interpreting
+    /// it. Volume labels and deleted entries are skipped.
+    pub(crate) fn try_next_raw(&mut self) -> io::Result<Option<RawEntry>> {
+        let mut lfn = [0u16; 13 * 20];
+        let mut lfn_len = 0;
+        let mut lfn_entries = 0;
+        let mut lfn_checksum = None;
+
+        while self.fill()? {
+            let index = self.index;
+            self.index += 1;
+
+            let unknown = unsafe { self.entries[index].unknown };
+            match unknown.id {
+                END_OF_DIR => {
+                    self.finish();
+                    return Ok(None);
+                }
+                DELETED => {
+                    lfn_len = 0;
+                    lfn_entries = 0;
+                    continue;
+                }
+                _ => {}
+            }
+
+            if unknown.attributes.lfn() {
+                let lfn_entry = unsafe { self.entries[index].long_filename };
+                let sequence = (lfn_entry.sequence & 0x1F) as usize;
+                if sequence == 0 || sequence > 20 {
+                    lfn_len = 0;
+                    lfn_entries = 0;
+                    continue;
+                }
+
+                if lfn_entry.sequence & 0x40 != 0 || lfn_entries == 0 {
+                    lfn_len = sequence * 13;
+                    lfn_entries = 0;
+                    lfn_checksum = Some(lfn_entry.checksum);
+                } else if lfn_checksum != Some(lfn_entry.checksum) {
+                    // Every entry of a long file name carries the same checksum.
+                    lfn_checksum = None;
+                }
+
+                let start = (sequence - 1) * 13;
+                lfn[start..start + 13].copy_from_slice(&lfn_entry.name_units());
+                lfn_entries += 1;
+                continue;
+            }
+
+            let regular = unsafe { self.entries[index].regular };
+            if regular.attributes.volume_id() {
+                lfn_len = 0;
+                lfn_entries = 0;
+                continue;
+            }
+
+            let valid_lfn = lfn_len > 0
+                && lfn_checksum == Some(short_name_checksum(&regular.raw_name()));
+            let name = match valid_lfn {
+                true => {
+                    let units = &lfn[..lfn_len];
+                    let end = units.iter()
+                        .position(|&u| u == 0x0000 || u == 0xFFFF)
+                        .unwrap_or(units.len());
+                    // Unpaired surrogates, which Windows allows, are replaced.
+                    decode_utf16(units[..end].iter().cloned())
+                        .map(|c| c.unwrap_or(::std::char::REPLACEMENT_CHARACTER))
+                        .collect()
+                }
+                false => regular.short_name(&self.code_page),
+            };
+
+            let location = EntryLocation {
+                dir: self.dir,
+                offset: ((self.base + index) * mem::size_of::<VFatDirEntry>()) as u64,
+                lfn_entries: match valid_lfn { true => lfn_entries, false => 0 },
+            };
+
+            return Ok(Some(RawEntry {
+                regular: regular,
+                name: name,
+                location: location,
+                orphaned_lfn_entries: match valid_lfn { true => 0, false => lfn_entries },
+            }));
+        }
+
+        Ok(None)
+    }
+}
+
+impl Iterator for EntryIter {
+    type Item = io::Result<Entry>;
+
+    /// Returns the next entry in the directory. An error reading or decoding
+    /// the directory is returned once and ends the iteration.
+    fn next(&mut self) -> Option<io::Result<Entry>> {
+        self.try_next().transpose()
+    }
+}
+
+/// Reinterprets the raw directory data `data` as directory entries, dropping
+/// any trailing partial entry.
+fn entries_from(mut data: Vec<u8>) -> Vec<VFatDirEntry> {
+    let entry_size = mem::size_of::<VFatDirEntry>();
+    let whole_entries = data.len() - data.len() % entry_size;
+    data.truncate(whole_entries);
+    data.shrink_to_fit();
+    unsafe { data.cast() }
+}
+
+/// Returns the raw entries of the directory starting at `dir`, which is found
+/// at `path`, each with its absolute path. The `.` and `..` entries are
+/// skipped. `dir` is cluster 0 for the fixed root directory region.
+pub(crate) fn raw_children(
+    vfat: &Shared<VFat>,
+    dir: Cluster,
+    path: &str
+) -> io::Result<Vec<(String, RawEntry)>> {
+    let mut entries = EntryIter::new(vfat.clone(), dir)?;
+    let mut children = Vec::new();
+    while let Some(raw) = entries.try_next_raw()? {
+        if raw.name == "." || raw.name == ".." {
+            continue;
+        }
+
+        let child_path = match path {
+            "/" => format!("/{}", raw.name),
+            _ => format!("{}/{}", path, raw.name),
+        };
+        children.push((child_path, raw));
+    }
+
+    Ok(children)
+}
+
 impl Dir {
+    /// Returns the root directory of the file system `vfat`.
+    pub(crate) fn root(vfat: Shared<VFat>) -> Dir {
+        let first_cluster = vfat.borrow().root_dir_cluster();
+        Dir {
+            vfat: vfat,
+            first_cluster: first_cluster,
+            name: String::from("/"),
+            metadata: Metadata {
+                attributes: Attributes::new(Attributes::DIRECTORY),
+                ..Metadata::default()
+            },
+            location: None,
+        }
+    }
+
+    /// The raw short names of every entry in this directory.
+    fn short_names(&self) -> io::Result<Vec<[u8; 11]>> {
+        let mut entries = EntryIter::new(self.vfat.clone(), self.first_cluster)?;
+        let mut names = Vec::new();
+        while let Some(raw) = entries.try_next_raw()? {
+            names.push(raw.regular.raw_name());
+        }
+
+        Ok(names)
+    }
+
+    /// Adds an entry named `name` to this directory. If `name` is a valid
+    /// short name in a single case and no other entry has that short name, it
+    /// is stored as the short name of `regular` alone. Otherwise the short
+    /// name of `regular` is replaced with a generated alias for `name`, and
+    /// LFN entries storing `name` are written before it. Returns the location
+    /// of the new regular entry.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error of `InvalidInput` if `name` is not a valid file name.
+    pub(crate) fn insert(
+        &self,
+        name: &str,
+        mut regular: VFatRegularDirEntry
+    ) -> io::Result<EntryLocation> {
+        validate_name(name)?;
+
+        let existing = self.short_names()?;
+        let mut raw = match plain_short_name(name) {
+            Some((short_name, flags)) if !existing.contains(&short_name) => {
+                regular.set_raw_name(short_name);
+                regular.set_case_flags(flags);
+                Vec::new()
+            }
+            _ => {
+                let code_page = *self.vfat.borrow().code_page();
+                let short_name = generate_short_name(name, &existing, &code_page)?;
+                regular.set_raw_name(short_name);
+                regular.set_case_flags(0);
+                VFatLfnDirEntry::entries_for(name, short_name_checksum(&short_name))
+            }
+        };
+
+        let lfn_entries = raw.len();
+        raw.push(regular.to_bytes());
+
+        let offset = self.vfat.borrow_mut().insert_dir_entries(self.first_cluster, &raw)?;
+        Ok(EntryLocation {
+            dir: self.first_cluster,
+            offset: offset,
+            lfn_entries: lfn_entries,
+        })
+    }
+
     /// Finds the entry named `name` in `self` and returns it. Comparison is
-    /// case-insensitive.
+    /// case-insensitive, using Unicode simple case folding.
     ///
     /// # Errors
     ///
@@ -48,9 +825,31 @@ impl Dir {
     ///
     /// If `name` contains invalid UTF-8 characters, an error of `InvalidInput`
     /// is returned.
+    ///
+    /// Errors reading the directory are returned as is.
     pub fn find<P: AsRef<OsStr>>(&self, name: P) -> io::Result<Entry> {
-        unimplemented!("Dir::find()")
+        use traits::{Dir, Entry};
+
+        let name = name.as_ref().to_str().ok_or_else(|| {
+            io::Error::new(io::ErrorKind::InvalidInput, "name is not valid UTF-8")
+        })?;
+
+        let mut entries = self.entries()?;
+        while let Some(entry) = entries.try_next()? {
+            if names_match(entry.name(), name) {
+                return Ok(entry);
+            }
+        }
+
+        Err(io::Error::new(io::ErrorKind::NotFound, "entry not found"))
     }
 }
 
-// FIXME: Implement `trait::Dir` for `Dir`.
+impl traits::Dir for Dir {
+    type Entry = Entry;
+    type Iter = EntryIter;
+
+    fn entries(&self) -> io::Result<EntryIter> {
+        EntryIter::new(self.vfat.clone(), self.first_cluster)
+    }
+}
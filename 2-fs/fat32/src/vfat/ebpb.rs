@@ -1,11 +1,68 @@
-use std::fmt;
+use std::{fmt, mem};
 
 use traits::BlockDevice;
-use vfat::Error;
+use vfat::{Error, FatType};
+
+/// The FAT32 extended BIOS parameter block fields following the common BPB.
+#[repr(C, packed)]
+#[derive(Copy, Clone)]
+pub struct Fat32Extension {
+    pub sectors_per_fat: u32,
+    pub flags: u16,
+    pub version: u16,
+    pub root_cluster: u32,
+    pub fsinfo_sector: u16,
+    pub backup_boot_sector: u16,
+    reserved: [u8; 12],
+    pub drive_number: u8,
+    reserved_flags: u8,
+    pub signature: u8,
+    pub volume_id: u32,
+    pub volume_label: [u8; 11],
+    pub system_id: [u8; 8],
+    boot_code: [u8; 420],
+}
+
+/// The FAT12/FAT16 extended BIOS parameter block fields following the common
+/// BPB.
+#[repr(C, packed)]
+#[derive(Copy, Clone)]
+pub struct Fat16Extension {
+    pub drive_number: u8,
+    reserved_flags: u8,
+    pub signature: u8,
+    pub volume_id: u32,
+    pub volume_label: [u8; 11],
+    pub system_id: [u8; 8],
+    boot_code: [u8; 448],
+}
+
+/// The extended BPB, whose layout depends on the FAT type.
+#[repr(C)]
+#[derive(Copy, Clone)]
+pub union Extension {
+    pub fat32: Fat32Extension,
+    pub fat16: Fat16Extension,
+}
 
 #[repr(C, packed)]
 pub struct BiosParameterBlock {
-    // FIXME: Fill me in.
+    jump: [u8; 3],
+    oem_id: [u8; 8],
+    pub bytes_per_sector: u16,
+    pub sectors_per_cluster: u8,
+    pub reserved_sectors: u16,
+    pub num_fats: u8,
+    pub max_dir_entries: u16,
+    pub total_logical_sectors: u16,
+    pub media_descriptor: u8,
+    pub sectors_per_fat_16: u16,
+    pub sectors_per_track: u16,
+    pub num_heads: u16,
+    pub hidden_sectors: u32,
+    pub total_logical_sectors_32: u32,
+    pub extension: Extension,
+    bootable_signature: [u8; 2],
 }
 
 impl BiosParameterBlock {
@@ -19,12 +76,193 @@ impl BiosParameterBlock {
         mut device: T,
         sector: u64
     ) -> Result<BiosParameterBlock, Error> {
-        unimplemented!("BiosParameterBlock::from()")
+        let mut buf = [0u8; 512];
+        device.read_sector(sector, &mut buf)?;
+
+        let ebpb: BiosParameterBlock = unsafe { mem::transmute(buf) };
+        if ebpb.bootable_signature != [0x55, 0xAA] {
+            return Err(Error::BadSignature);
+        }
+
+        Ok(ebpb)
+    }
+
+    /// Returns `true` if the common BPB fields describe a plausible FAT volume.
+    /// This is used to tell a partitionless volume's boot sector apart from an
+    /// MBR.
+    pub fn is_plausible(&self) -> bool {
+        let bytes_per_sector = self.bytes_per_sector;
+        (self.jump[0] == 0xEB || self.jump[0] == 0xE9)
+            && bytes_per_sector.is_power_of_two()
+            && bytes_per_sector >= 512 && bytes_per_sector <= 4096
+            && self.sectors_per_cluster.is_power_of_two()
+            && self.reserved_sectors != 0
+            && self.num_fats != 0
+    }
+
+    /// Checks the geometry of this BPB, read from sector `sector`, and that
+    /// its FAT type is supported.
+    ///
+    /// # Errors
+    ///
+    /// Returns `BadBytesPerSector`, `BadSectorsPerCluster`, `BadFatCount`, or
+    /// `BadActiveFat` for an invalid geometry field, and `UnsupportedFatType`
+    /// for a FAT32 volume whose version isn't 0.0 or whose BPB lacks the FAT32
+    /// fields.
+    pub fn validate(&self, sector: u64) -> Result<(), Error> {
+        let bytes_per_sector = self.bytes_per_sector;
+        if !bytes_per_sector.is_power_of_two() || bytes_per_sector < 512
+            || bytes_per_sector > 4096 {
+            return Err(Error::BadBytesPerSector { sector: sector, value: bytes_per_sector });
+        }
+
+        if !self.sectors_per_cluster.is_power_of_two() {
+            return Err(Error::BadSectorsPerCluster {
+                sector: sector,
+                value: self.sectors_per_cluster,
+            });
+        }
+
+        if self.num_fats == 0 {
+            return Err(Error::BadFatCount { sector: sector, value: self.num_fats });
+        }
+
+        if let Some(active) = self.active_fat() {
+            if active >= self.num_fats {
+                return Err(Error::BadActiveFat { sector: sector, value: active });
+            }
+        }
+
+        let fat_type = self.fat_type();
+        let supported = match fat_type {
+            FatType::Fat32 => self.sectors_per_fat_16 == 0
+                && unsafe { self.extension.fat32.version } == 0,
+            _ => true,
+        };
+
+        match supported {
+            true => Ok(()),
+            false => Err(Error::UnsupportedFatType { sector: sector, fat_type: fat_type }),
+        }
+    }
+
+    /// The only FAT copy in use when FAT mirroring is disabled in the FAT32
+    /// extended flags, or `None` if every copy is kept up to date.
+    pub fn active_fat(&self) -> Option<u8> {
+        let flags = self.fat32()?.flags;
+        match flags & 0x80 {
+            0 => None,
+            _ => Some((flags & 0x0F) as u8),
+        }
+    }
+
+    /// The total number of logical sectors in the volume.
+    pub fn total_sectors(&self) -> u32 {
+        match self.total_logical_sectors {
+            0 => self.total_logical_sectors_32,
+            n => n as u32,
+        }
+    }
+
+    /// The number of sectors occupied by a single FAT.
+    pub fn sectors_per_fat(&self) -> u32 {
+        match self.sectors_per_fat_16 {
+            0 => unsafe { self.extension.fat32.sectors_per_fat },
+            n => n as u32,
+        }
+    }
+
+    /// The number of sectors occupied by the fixed root directory region. This
+    /// is always zero on FAT32.
+    pub fn root_dir_sectors(&self) -> u32 {
+        let bytes_per_sector = self.bytes_per_sector as u32;
+        let root_bytes = self.max_dir_entries as u32 * 32;
+        (root_bytes + bytes_per_sector - 1).checked_div(bytes_per_sector).unwrap_or(0)
+    }
+
+    /// The number of clusters in the data region.
+    pub fn cluster_count(&self) -> u32 {
+        let metadata_sectors = self.reserved_sectors as u32
+            + self.num_fats as u32 * self.sectors_per_fat()
+            + self.root_dir_sectors();
+        self.total_sectors().saturating_sub(metadata_sectors)
+            .checked_div(self.sectors_per_cluster as u32)
+            .unwrap_or(0)
+    }
+
+    /// The FAT type of the volume.
+    ///
+    /// The type is determined by the number of clusters in the data region as
+    /// prescribed by the FAT specification. As in Linux, a BPB with a zero
+    /// 16-bit FAT size can only be FAT32 and is treated as such regardless of
+    /// its cluster count.
+    pub fn fat_type(&self) -> FatType {
+        if self.sectors_per_fat_16 == 0 {
+            return FatType::Fat32;
+        }
+
+        match self.cluster_count() {
+            0..=4084 => FatType::Fat12,
+            4085..=65524 => FatType::Fat16,
+            _ => FatType::Fat32,
+        }
+    }
+
+    /// The FAT32 extension fields, or `None` if this is not a FAT32 volume.
+    pub fn fat32(&self) -> Option<&Fat32Extension> {
+        match self.fat_type() {
+            FatType::Fat32 => Some(unsafe { &self.extension.fat32 }),
+            _ => None,
+        }
+    }
+
+    /// The FAT12/FAT16 extension fields, or `None` if this is a FAT32 volume.
+    pub fn fat16(&self) -> Option<&Fat16Extension> {
+        match self.fat_type() {
+            FatType::Fat32 => None,
+            _ => Some(unsafe { &self.extension.fat16 }),
+        }
+    }
+
+    /// The volume ID (serial number).
+    pub fn volume_id(&self) -> u32 {
+        match self.fat16() {
+            Some(fat16) => fat16.volume_id,
+            None => unsafe { self.extension.fat32.volume_id },
+        }
+    }
+
+    /// The volume label.
+    pub fn volume_label(&self) -> [u8; 11] {
+        match self.fat16() {
+            Some(fat16) => fat16.volume_label,
+            None => unsafe { self.extension.fat32.volume_label },
+        }
     }
 }
 
 impl fmt::Debug for BiosParameterBlock {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        unimplemented!("BiosParameterBlock::debug()")
+        let mut s = f.debug_struct("BiosParameterBlock");
+        s.field("oem_id", &String::from_utf8_lossy(&self.oem_id))
+            .field("bytes_per_sector", &{ self.bytes_per_sector })
+            .field("sectors_per_cluster", &self.sectors_per_cluster)
+            .field("reserved_sectors", &{ self.reserved_sectors })
+            .field("num_fats", &self.num_fats)
+            .field("max_dir_entries", &{ self.max_dir_entries })
+            .field("total_sectors", &self.total_sectors())
+            .field("sectors_per_fat", &self.sectors_per_fat())
+            .field("fat_type", &self.fat_type());
+
+        if let Some(fat32) = self.fat32() {
+            s.field("flags", &{ fat32.flags })
+                .field("root_cluster", &{ fat32.root_cluster })
+                .field("fsinfo_sector", &{ fat32.fsinfo_sector })
+                .field("backup_boot_sector", &{ fat32.backup_boot_sector });
+        }
+
+        s.field("volume_id", &self.volume_id())
+            .field("volume_label", &String::from_utf8_lossy(&self.volume_label()))
+            .finish()
     }
 }
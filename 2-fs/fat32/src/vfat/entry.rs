@@ -1,13 +1,68 @@
 use traits;
-use vfat::{File, Dir, Metadata, Cluster};
+use vfat::{File, Dir, Metadata};
+use vfat::dir::EntryLocation;
 
-// TODO: You may need to change this definition.
 #[derive(Debug)]
 pub enum Entry {
     File(File),
     Dir(Dir)
 }
 
-// TODO: Implement any useful helper methods on `Entry`.
+impl Entry {
+    /// The location of this entry in its parent directory. `None` for the
+    /// root directory.
+    pub(crate) fn location(&self) -> Option<EntryLocation> {
+        match *self {
+            Entry::File(ref file) => Some(file.location),
+            Entry::Dir(ref dir) => dir.location,
+        }
+    }
+}
+
+impl traits::Entry for Entry {
+    type File = File;
+    type Dir = Dir;
+    type Metadata = Metadata;
+
+    fn name(&self) -> &str {
+        match *self {
+            Entry::File(ref file) => &file.name,
+            Entry::Dir(ref dir) => &dir.name,
+        }
+    }
+
+    fn metadata(&self) -> &Metadata {
+        match *self {
+            Entry::File(ref file) => &file.metadata,
+            Entry::Dir(ref dir) => &dir.metadata,
+        }
+    }
 
-// FIXME: Implement `traits::Entry` for `Entry`.
+    fn as_file(&self) -> Option<&File> {
+        match *self {
+            Entry::File(ref file) => Some(file),
+            Entry::Dir(_) => None,
+        }
+    }
+
+    fn as_dir(&self) -> Option<&Dir> {
+        match *self {
+            Entry::File(_) => None,
+            Entry::Dir(ref dir) => Some(dir),
+        }
+    }
+
+    fn into_file(self) -> Option<File> {
+        match self {
+            Entry::File(file) => Some(file),
+            Entry::Dir(_) => None,
+        }
+    }
+
+    fn into_dir(self) -> Option<Dir> {
+        match self {
+            Entry::File(_) => None,
+            Entry::Dir(dir) => Some(dir),
+        }
+    }
+}
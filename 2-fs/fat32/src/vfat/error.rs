@@ -1,13 +1,94 @@
-use std::io;
+use std::{io, fmt, error};
 
 use mbr;
+use gpt;
+use vfat::FatType;
 
 #[derive(Debug)]
 pub enum Error {
     Mbr(mbr::Error),
+    Gpt(gpt::Error),
     Io(io::Error),
     BadSignature,
-    NotFound
+    NotFound,
+    /// The BPB in sector `sector` has a bytes per sector value that is not a
+    /// power of two between 512 and 4096.
+    BadBytesPerSector { sector: u64, value: u16 },
+    /// The BPB in sector `sector` has a sectors per cluster value that is not
+    /// a non-zero power of two.
+    BadSectorsPerCluster { sector: u64, value: u8 },
+    /// The BPB in sector `sector` describes a volume without a FAT.
+    BadFatCount { sector: u64, value: u8 },
+    /// The BPB in sector `sector` disables FAT mirroring in favor of the FAT
+    /// copy `value`, which doesn't exist.
+    BadActiveFat { sector: u64, value: u8 },
+    /// The BPB in sector `sector` describes a volume of type `fat_type` that
+    /// can't be mounted: a FAT32 volume of a version other than 0.0, or one
+    /// with too many clusters for FAT16 but without the FAT32 BPB fields.
+    UnsupportedFatType { sector: u64, fat_type: FatType },
+    /// The directory entry in sector `sector`, which is part of the directory
+    /// cluster `cluster`, refers to a cluster outside of the data region or is
+    /// a directory without a cluster. For the fixed root directory region of
+    /// FAT12 and FAT16 volumes, `cluster` is 0.
+    CorruptEntry { sector: u64, cluster: u32 },
+    /// A cluster chain refers to cluster `.0`, which is outside of the data
+    /// region.
+    ClusterOutOfRange(u32),
+    /// The cluster chain continues into cluster `.0`, which is marked bad.
+    BadCluster(u32),
+    /// The cluster chain continues into cluster `.0`, whose FAT entry is
+    /// reserved or free.
+    ReservedCluster(u32),
+    /// The cluster chain is longer than the volume has clusters, so it loops.
+    /// Cluster `.0` was reached once the chain exceeded that length.
+    ChainCycle(u32),
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Error::Mbr(ref error) => write!(f, "invalid MBR: {:?}", error),
+            Error::Gpt(ref error) => write!(f, "invalid GPT: {:?}", error),
+            Error::Io(ref error) => write!(f, "I/O error: {}", error),
+            Error::BadSignature => write!(f, "bad signature"),
+            Error::NotFound => write!(f, "no FAT partition found"),
+            Error::BadBytesPerSector { sector, value } => {
+                write!(f, "BPB in sector {} has invalid bytes per sector {}", sector, value)
+            }
+            Error::BadSectorsPerCluster { sector, value } => {
+                write!(f, "BPB in sector {} has invalid sectors per cluster {}", sector, value)
+            }
+            Error::BadFatCount { sector, value } => {
+                write!(f, "BPB in sector {} has invalid FAT count {}", sector, value)
+            }
+            Error::BadActiveFat { sector, value } => {
+                write!(f, "BPB in sector {} has invalid active FAT {}", sector, value)
+            }
+            Error::UnsupportedFatType { sector, fat_type } => {
+                write!(f, "BPB in sector {} describes an unsupported {:?} volume", sector, fat_type)
+            }
+            Error::CorruptEntry { sector, cluster } => {
+                write!(f, "corrupt directory entry in sector {} (cluster {})", sector, cluster)
+            }
+            Error::ClusterOutOfRange(cluster) => {
+                write!(f, "cluster {} is outside of the data region", cluster)
+            }
+            Error::BadCluster(cluster) => write!(f, "cluster chain reaches bad cluster {}", cluster),
+            Error::ReservedCluster(cluster) => {
+                write!(f, "cluster chain reaches reserved or free cluster {}", cluster)
+            }
+            Error::ChainCycle(cluster) => write!(f, "cluster chain loops at cluster {}", cluster),
+        }
+    }
+}
+
+impl error::Error for Error {
+    fn source(&self) -> Option<&(dyn error::Error + 'static)> {
+        match *self {
+            Error::Io(ref error) => Some(error),
+            _ => None,
+        }
+    }
 }
 
 impl From<mbr::Error> for Error {
@@ -16,8 +97,28 @@ impl From<mbr::Error> for Error {
     }
 }
 
+impl From<gpt::Error> for Error {
+    fn from(error: gpt::Error) -> Error {
+        Error::Gpt(error)
+    }
+}
+
 impl From<io::Error> for Error {
     fn from(error: io::Error) -> Error {
         Error::Io(error)
     }
 }
+
+/// Converts a `vfat::Error` into an `io::Error` without losing information:
+/// an `Io` error is unwrapped, and any other error becomes the inner error of
+/// an `io::Error` of `NotFound` or `InvalidData`, from which it can be
+/// recovered with `io::Error::into_inner()` and `downcast()`.
+impl From<Error> for io::Error {
+    fn from(error: Error) -> io::Error {
+        match error {
+            Error::Io(error) => error,
+            Error::NotFound => io::Error::new(io::ErrorKind::NotFound, error),
+            error => io::Error::new(io::ErrorKind::InvalidData, error),
+        }
+    }
+}
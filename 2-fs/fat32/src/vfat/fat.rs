@@ -19,20 +19,59 @@ pub enum Status {
     Eoc(u32)
 }
 
+/// The width of the entries in a file allocation table.
+#[derive(Debug, PartialEq, Eq, Copy, Clone)]
+pub enum FatType {
+    Fat12,
+    Fat16,
+    Fat32
+}
+
+impl FatType {
+    /// The mask of the bits of an entry that hold a cluster number.
+    pub fn mask(&self) -> u32 {
+        match *self {
+            FatType::Fat12 => 0xFFF,
+            FatType::Fat16 => 0xFFFF,
+            FatType::Fat32 => 0x0FFFFFFF,
+        }
+    }
+}
+
 #[repr(C, packed)]
 pub struct FatEntry(pub u32);
 
 impl FatEntry {
+    /// Returns the FAT entry for the raw on-disk value `raw` of a FAT of type
+    /// `fat_type`. FAT12 and FAT16 reserved, bad, and end-of-chain markers are
+    /// widened to their FAT32 equivalents so that `status()` applies to every
+    /// FAT type.
+    pub fn from_raw(fat_type: FatType, raw: u32) -> FatEntry {
+        let raw = raw & fat_type.mask();
+        match fat_type {
+            FatType::Fat12 if raw >= 0xFF0 => FatEntry(raw | 0x0FFFF000),
+            FatType::Fat16 if raw >= 0xFFF0 => FatEntry(raw | 0x0FFF0000),
+            _ => FatEntry(raw),
+        }
+    }
+
     /// Returns the `Status` of the FAT entry `self`.
     pub fn status(&self) -> Status {
-        unimplemented!("FatEntry::status()")
+        match self.0 & !(0xF << 28) {
+            0x0000000 => Free,
+            0x0000001 => Reserved,
+            n @ 0x0000002..=0xFFFFFEF => Data(Cluster::from(n)),
+            0xFFFFFF0..=0xFFFFFF6 => Reserved,
+            0xFFFFFF7 => Bad,
+            n => Eoc(n),
+        }
     }
 }
 
 impl fmt::Debug for FatEntry {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         f.debug_struct("FatEntry")
-            .field("value", &self.0)
+            .field("value", &{ self.0 })
             .field("status", &self.status())
             .finish()
     }
@@ -1,15 +1,418 @@
-use std::cmp::{min, max};
+use std::cmp::min;
 use std::io::{self, SeekFrom};
+use std::path::Path;
 
-use traits;
+use traits::{self, FileSystem};
 use vfat::{VFat, Shared, Cluster, Metadata};
+use vfat::dir::EntryLocation;
 
 #[derive(Debug)]
 pub struct File {
-    // FIXME: Fill me in.
+    pub(crate) vfat: Shared<VFat>,
+    /// The first cluster of the file's chain. `None` for an empty file that
+    /// has never been written to.
+    pub(crate) first_cluster: Option<Cluster>,
+    pub(crate) name: String,
+    pub(crate) metadata: Metadata,
+    pub(crate) size: u64,
+    /// The location of this file's entry in its parent directory.
+    pub(crate) location: EntryLocation,
+    pos: u64,
+    /// The runs of consecutive clusters that make up the part of the chain
+    /// walked so far, as the index in the chain and the cluster each run
+    /// starts at and its length. Clusters inside a run are found without
+    /// reading the FAT.
+    extents: Vec<(u64, Cluster, u64)>,
+    /// Whether the size or first cluster changed since the last `sync()`.
+    dirty: bool,
+    /// Whether the access date has been updated through this `File`.
+    accessed: bool,
+    /// Whether every write is made at the end of the file.
+    append: bool,
 }
 
-// FIXME: Implement `traits::File` (and its supertraits) for `File`.
+impl File {
+    pub(crate) fn new(
+        vfat: Shared<VFat>,
+        first_cluster: Option<Cluster>,
+        name: String,
+        metadata: Metadata,
+        size: u64,
+        location: EntryLocation
+    ) -> File {
+        File {
+            vfat: vfat,
+            first_cluster: first_cluster,
+            name: name,
+            metadata: metadata,
+            size: size,
+            location: location,
+            pos: 0,
+            extents: Vec::new(),
+            dirty: false,
+            accessed: false,
+            append: false,
+        }
+    }
+
+    /// Opens the file at `path` in append mode: every write is made at the
+    /// end of the file, regardless of the position. Reads and seeks are
+    /// unaffected.
+    ///
+    /// # Errors
+    ///
+    /// Returns the errors of `FileSystem::open_file`.
+    pub fn open_append<P: AsRef<Path>>(vfat: &Shared<VFat>, path: P) -> io::Result<File> {
+        let mut file = vfat.open_file(path)?;
+        file.append = true;
+        Ok(file)
+    }
+
+    /// Writes `size` and `first_cluster` to the file's directory entry.
+    fn write_entry(
+        &self,
+        vfat: &mut VFat,
+        size: u64,
+        first_cluster: Option<Cluster>
+    ) -> io::Result<()> {
+        let mut entry = vfat.read_dir_entry(self.location)?;
+        entry.set_size(size as u32);
+        entry.set_cluster(first_cluster.unwrap_or(Cluster::from(0)));
+        vfat.write_dir_entry(self.location, &entry)
+    }
+
+    /// Sets the access date in this file's entry to the current date if the
+    /// volume is mounted writable with `update_access_date` and a clock. This
+    /// is done at most once per `File`.
+    fn update_access_date(&mut self, vfat: &mut VFat) -> io::Result<()> {
+        let options = *vfat.options();
+        let clock = match options.clock {
+            Some(clock) if options.update_access_date && !options.read_only => clock,
+            _ => return Ok(()),
+        };
+
+        if !self.accessed {
+            let date = clock().date;
+            if self.metadata.accessed.date != date {
+                let mut entry = vfat.read_dir_entry(self.location)?;
+                entry.set_accessed_date(date);
+                vfat.write_dir_entry(self.location, &entry)?;
+                self.metadata.accessed.date = date;
+            }
+
+            self.accessed = true;
+        }
+
+        Ok(())
+    }
+
+    /// The number of clusters at the start of the chain covered by `extents`.
+    fn known_clusters(&self) -> u64 {
+        self.extents.last().map_or(0, |&(index, _, len)| index + len)
+    }
+
+    /// Records that cluster `cluster` is the `index`th cluster in the chain,
+    /// which must be the first cluster past the known ones.
+    fn record(&mut self, index: u64, cluster: Cluster) {
+        debug_assert_eq!(index, self.known_clusters());
+        match self.extents.last_mut() {
+            Some(&mut (_, start, ref mut len))
+                if start.number() as u64 + *len == cluster.number() as u64 => *len += 1,
+            _ => self.extents.push((index, cluster, 1)),
+        }
+    }
+
+    /// Forgets every known cluster at index `index` or later in the chain.
+    fn forget_from(&mut self, index: u64) {
+        self.extents.retain(|&(start, _, _)| start < index);
+        if let Some(&mut (start, _, ref mut len)) = self.extents.last_mut() {
+            *len = min(*len, index - start);
+        }
+    }
+
+    /// Returns the `index`th cluster in this file's chain, walking forward from
+    /// the last known cluster if `index` is past it.
+    ///
+    /// If the chain ends before `index` and `allocate` is `true`, clusters are
+    /// allocated and appended to the chain until it reaches `index`. Otherwise
+    /// `None` is returned.
+    fn cluster_at(
+        &mut self,
+        vfat: &mut VFat,
+        index: u64,
+        allocate: bool
+    ) -> io::Result<Option<Cluster>> {
+        if self.extents.is_empty() {
+            let first = match self.first_cluster {
+                Some(cluster) => cluster,
+                None if allocate => {
+                    let cluster = vfat.alloc_cluster(None)?;
+                    self.first_cluster = Some(cluster);
+                    self.dirty = true;
+                    cluster
+                }
+                None => return Ok(None),
+            };
+
+            self.record(0, first);
+        }
+
+        let known = self.known_clusters();
+        if index < known {
+            let &(start, cluster, _) = self.extents.iter().rev()
+                .find(|extent| extent.0 <= index)
+                .expect("first extent starts at index 0");
+            return Ok(Some(Cluster::from(cluster.number() + (index - start) as u32)));
+        }
+
+        let (start, first, len) = *self.extents.last().expect("known clusters");
+        let mut cluster = Cluster::from(first.number() + (len - 1) as u32);
+        for i in start + len - 1..index {
+            cluster = match vfat.next_in_chain(cluster, i)? {
+                Some(next) => next,
+                None if allocate => vfat.alloc_cluster(Some(cluster))?,
+                None => return Ok(None),
+            };
+
+            self.record(i + 1, cluster);
+        }
+
+        Ok(Some(cluster))
+    }
+
+    /// Reserves clusters for the first `bytes` bytes of the file without
+    /// changing its size, so that later writes up to that point need not
+    /// allocate. The clusters added to the chain are taken from a single run
+    /// of free clusters if one is large enough, and otherwise from the largest
+    /// runs available. Reserved clusters are zeroed.
+    ///
+    /// A new first cluster is written to the file's entry at once, so the
+    /// reserved chain is never left unreferenced. The reservation is not
+    /// recorded anywhere else: `check::check()` reports reserved clusters
+    /// past the end of the file as a size mismatch and, when repairing, frees
+    /// them, so preallocation does not survive a repairing check.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error of `InvalidInput` if `bytes` exceeds 4GiB and of
+    /// `Other`, without reserving anything, if there aren't enough free
+    /// clusters.
+    pub fn preallocate(&mut self, bytes: u64) -> io::Result<()> {
+        if bytes > u32::MAX as u64 {
+            return Err(io::Error::new(io::ErrorKind::InvalidInput,
+                                      "file size would exceed 4GiB"));
+        }
+
+        let vfat = self.vfat.clone();
+        let mut vfat = vfat.borrow_mut();
+        let cluster_size = vfat.bytes_per_cluster() as u64;
+        let wanted = (bytes + cluster_size - 1) / cluster_size;
+
+        // Walk to the end of the chain to find its length.
+        let mut clusters = 0;
+        while clusters < wanted && self.cluster_at(&mut vfat, clusters, false)?.is_some() {
+            clusters += 1;
+        }
+
+        if clusters >= wanted {
+            return Ok(());
+        }
+
+        let last = match clusters {
+            0 => None,
+            _ => self.cluster_at(&mut vfat, clusters - 1, false)?,
+        };
+
+        for (start, len) in vfat.alloc_runs(last, (wanted - clusters) as u32)? {
+            for number in start.number()..start.number() + len {
+                let index = self.known_clusters();
+                self.record(index, Cluster::from(number));
+            }
+        }
+
+        if self.first_cluster.is_none() {
+            self.first_cluster = self.extents.first().map(|extent| extent.1);
+            self.write_entry(&mut vfat, self.size, self.first_cluster)?;
+        }
+
+        Ok(())
+    }
+}
+
+impl traits::File for File {
+    /// Writes the file's size and first cluster to its directory entry and
+    /// flushes every dirty sector to the disk.
+    fn sync(&mut self) -> io::Result<()> {
+        let vfat = self.vfat.clone();
+        let mut vfat = vfat.borrow_mut();
+        if self.dirty {
+            self.write_entry(&mut vfat, self.size, self.first_cluster)?;
+            self.dirty = false;
+        }
+
+        vfat.flush()
+    }
+
+    fn size(&self) -> u64 {
+        self.size
+    }
+
+    /// Truncates or extends the file to `size` bytes.
+    ///
+    /// Extending the file allocates zeroed clusters and zeroes the rest of its
+    /// last cluster; the new size is persisted on `sync()`. Truncating the
+    /// file writes the new size to the directory entry, then marks the new
+    /// last cluster as the end of the chain, then frees the clusters past the
+    /// end, flushing each step to the device before the next. A crash leaves
+    /// at most clusters past the end of the file or unreferenced ones, which
+    /// `check::check()` repairs, but never an entry referring to free ones.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error of `InvalidInput` if `size` exceeds 4GiB.
+    fn set_len(&mut self, size: u64) -> io::Result<()> {
+        if size > u32::MAX as u64 {
+            return Err(io::Error::new(io::ErrorKind::InvalidInput,
+                                      "file size would exceed 4GiB"));
+        }
+
+        let vfat = self.vfat.clone();
+        let mut vfat = vfat.borrow_mut();
+        let cluster_size = vfat.bytes_per_cluster() as u64;
+
+        if size > self.size {
+            // Bytes past the old end of the last cluster may be stale.
+            let (index, offset) = (self.size / cluster_size, self.size % cluster_size);
+            if offset != 0 {
+                if let Some(cluster) = self.cluster_at(&mut vfat, index, false)? {
+                    let end = min(size, (index + 1) * cluster_size);
+                    let zeroes = vec![0u8; (end - self.size) as usize];
+                    vfat.write_cluster(cluster, offset as usize, &zeroes)?;
+                }
+            }
+
+            self.cluster_at(&mut vfat, (size - 1) / cluster_size, true)?;
+            self.size = size;
+            self.dirty = true;
+        } else if size < self.size {
+            let clusters = (size + cluster_size - 1) / cluster_size;
+            let (first, last) = match clusters {
+                0 => (None, None),
+                _ => (self.first_cluster, self.cluster_at(&mut vfat, clusters - 1, false)?),
+            };
+
+            self.write_entry(&mut vfat, size, first)?;
+            vfat.flush()?;
+
+            let old_first = self.first_cluster;
+            self.first_cluster = first;
+            self.size = size;
+            self.pos = min(self.pos, size);
+            self.forget_from(clusters);
+            self.dirty = false;
+
+            let tail = match last {
+                Some(last) => {
+                    let tail = vfat.next_in_chain(last, clusters - 1)?;
+                    vfat.set_fat_entry(last, 0x0FFFFFFF)?;
+                    vfat.flush()?;
+                    tail
+                }
+                None if clusters == 0 => old_first,
+                None => None,
+            };
+
+            if let Some(tail) = tail {
+                vfat.free_chain(tail)?;
+                vfat.flush()?;
+            }
+        }
+
+        Ok(())
+    }
+}
+
+impl io::Read for File {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let amount = min(buf.len() as u64, self.size.saturating_sub(self.pos)) as usize;
+        if amount == 0 {
+            return Ok(0);
+        }
+
+        let vfat = self.vfat.clone();
+        let mut vfat = vfat.borrow_mut();
+        let cluster_size = vfat.bytes_per_cluster() as u64;
+
+        let mut read = 0;
+        while read < amount {
+            let cluster = self.cluster_at(&mut vfat, self.pos / cluster_size, false)?
+                .ok_or_else(|| io::Error::new(io::ErrorKind::UnexpectedEof,
+                                              "cluster chain is shorter than file"))?;
+
+            let offset = (self.pos % cluster_size) as usize;
+            let n = vfat.read_cluster(cluster, offset, &mut buf[read..amount])?;
+            read += n;
+            self.pos += n as u64;
+        }
+
+        self.update_access_date(&mut vfat)?;
+
+        Ok(read)
+    }
+}
+
+impl io::Write for File {
+    /// Writes `buf` at the current position, growing the file and its cluster
+    /// chain as needed. The new size is persisted on `sync()`.
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        if buf.is_empty() {
+            return Ok(0);
+        }
+
+        if self.append {
+            self.pos = self.size;
+        }
+
+        if self.pos + buf.len() as u64 > u32::MAX as u64 {
+            return Err(io::Error::new(io::ErrorKind::InvalidInput,
+                                      "file size would exceed 4GiB"));
+        }
+
+        let vfat = self.vfat.clone();
+        let mut vfat = vfat.borrow_mut();
+        let cluster_size = vfat.bytes_per_cluster() as u64;
+
+        let mut written = 0;
+        while written < buf.len() {
+            let offset = (self.pos % cluster_size) as usize;
+            let result = self.cluster_at(&mut vfat, self.pos / cluster_size, true)
+                .and_then(|cluster| {
+                    let cluster = cluster.expect("cluster allocated");
+                    vfat.write_cluster(cluster, offset, &buf[written..])
+                });
+
+            // Report a partial write rather than losing the bytes written.
+            let n = match result {
+                Ok(n) => n,
+                Err(_) if written > 0 => break,
+                Err(e) => return Err(e),
+            };
+
+            written += n;
+            self.pos += n as u64;
+            if self.pos > self.size {
+                self.size = self.pos;
+                self.dirty = true;
+            }
+        }
+
+        Ok(written)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        traits::File::sync(self)
+    }
+}
 
 impl io::Seek for File {
     /// Seek to offset `pos` in the file.
@@ -26,6 +429,18 @@ impl io::Seek for File {
     /// Seeking before the start of a file or beyond the end of the file results
     /// in an `InvalidInput` error.
     fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
-        unimplemented!("File::seek()")
+        let new_pos = match pos {
+            SeekFrom::Start(offset) => offset as i64,
+            SeekFrom::End(offset) => self.size as i64 + offset,
+            SeekFrom::Current(offset) => self.pos as i64 + offset,
+        };
+
+        if new_pos < 0 || new_pos as u64 > self.size {
+            return Err(io::Error::new(io::ErrorKind::InvalidInput,
+                                      "seek outside of file bounds"));
+        }
+
+        self.pos = new_pos as u64;
+        Ok(self.pos)
     }
 }
@@ -0,0 +1,102 @@
+use std::{fmt, mem};
+
+use traits::BlockDevice;
+use vfat::Error;
+
+/// The signature at the start of the FSInfo structure.
+const LEAD_SIGNATURE: u32 = 0x41615252;
+/// The signature preceding the free cluster count.
+const STRUCT_SIGNATURE: u32 = 0x61417272;
+/// The signature at the end of the FSInfo structure.
+const TRAIL_SIGNATURE: u32 = 0xAA550000;
+/// The value of a field whose value is not known.
+const UNKNOWN: u32 = 0xFFFFFFFF;
+
+/// The FAT32 FSInfo structure, which caches the number of free clusters and
+/// where to start looking for one. Both values are only hints.
+#[repr(C, packed)]
+#[derive(Copy, Clone)]
+pub struct FsInfo {
+    lead_signature: u32,
+    reserved_1: [u8; 480],
+    struct_signature: u32,
+    free_count: u32,
+    next_free: u32,
+    reserved_2: [u8; 12],
+    trail_signature: u32,
+}
+
+impl FsInfo {
+    /// Returns a new FSInfo structure with the hints `free_count` and
+    /// `next_free`.
+    pub fn new(free_count: Option<u32>, next_free: Option<u32>) -> FsInfo {
+        FsInfo {
+            lead_signature: LEAD_SIGNATURE,
+            reserved_1: [0; 480],
+            struct_signature: STRUCT_SIGNATURE,
+            free_count: free_count.unwrap_or(UNKNOWN),
+            next_free: next_free.unwrap_or(UNKNOWN),
+            reserved_2: [0; 12],
+            trail_signature: TRAIL_SIGNATURE,
+        }
+    }
+
+    /// Reads the FSInfo structure from sector `sector` of device `device`.
+    ///
+    /// # Errors
+    ///
+    /// If the lead, struct, or trail signature is invalid, returns an error of
+    /// `BadSignature`.
+    pub fn from<T: BlockDevice>(mut device: T, sector: u64) -> Result<FsInfo, Error> {
+        let mut buf = [0u8; 512];
+        device.read_sector(sector, &mut buf)?;
+
+        let fsinfo: FsInfo = unsafe { mem::transmute(buf) };
+        if fsinfo.lead_signature != LEAD_SIGNATURE
+            || fsinfo.struct_signature != STRUCT_SIGNATURE
+            || fsinfo.trail_signature != TRAIL_SIGNATURE {
+            return Err(Error::BadSignature);
+        }
+
+        Ok(fsinfo)
+    }
+
+    /// The raw on-disk bytes of this structure.
+    pub fn to_bytes(&self) -> [u8; 512] {
+        unsafe { mem::transmute(*self) }
+    }
+
+    /// The last known number of free clusters, or `None` if it is unknown.
+    pub fn free_count(&self) -> Option<u32> {
+        match self.free_count {
+            UNKNOWN => None,
+            n => Some(n),
+        }
+    }
+
+    pub fn set_free_count(&mut self, free_count: Option<u32>) {
+        self.free_count = free_count.unwrap_or(UNKNOWN);
+    }
+
+    /// The cluster at which to start looking for a free cluster, or `None` if
+    /// it is unknown.
+    pub fn next_free(&self) -> Option<u32> {
+        match self.next_free {
+            UNKNOWN => None,
+            n => Some(n),
+        }
+    }
+
+    pub fn set_next_free(&mut self, next_free: Option<u32>) {
+        self.next_free = next_free.unwrap_or(UNKNOWN);
+    }
+}
+
+impl fmt::Debug for FsInfo {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("FsInfo")
+            .field("free_count", &self.free_count())
+            .field("next_free", &self.next_free())
+            .finish()
+    }
+}
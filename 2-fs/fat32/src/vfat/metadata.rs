@@ -12,26 +12,306 @@ pub struct Date(u16);
 #[derive(Default, Debug, Copy, Clone, PartialEq, Eq)]
 pub struct Time(u16);
 
+impl Date {
+    /// Returns the date with the raw on-disk value `raw`: the year since 1980
+    /// in bits 9-15, the month in bits 5-8, and the day in bits 0-4.
+    pub fn from_raw(raw: u16) -> Date {
+        Date(raw)
+    }
+
+    /// Returns the date `day` `month` `year`, or `None` if it is not a valid
+    /// date between 1980 and 2107.
+    pub fn new(year: usize, month: u8, day: u8) -> Option<Date> {
+        if year < 1980 || year > 2107 || month < 1 || month > 12 {
+            return None;
+        }
+
+        if day < 1 || day > days_in_month(year, month) {
+            return None;
+        }
+
+        Some(Date((((year - 1980) as u16) << 9) | ((month as u16) << 5) | day as u16))
+    }
+
+    /// The raw on-disk value of the date.
+    pub fn raw(&self) -> u16 {
+        self.0
+    }
+}
+
+impl Time {
+    /// Returns the time with the raw on-disk value `raw`: the hour in bits
+    /// 11-15, the minute in bits 5-10, and the second divided by two in bits
+    /// 0-4.
+    pub fn from_raw(raw: u16) -> Time {
+        Time(raw)
+    }
+
+    /// Returns the time `hour`:`minute`:`second`, rounded down to an even
+    /// second, or `None` if any field is out of range.
+    pub fn new(hour: u8, minute: u8, second: u8) -> Option<Time> {
+        if hour > 23 || minute > 59 || second > 59 {
+            return None;
+        }
+
+        Some(Time(((hour as u16) << 11) | ((minute as u16) << 5) | (second as u16 / 2)))
+    }
+
+    /// The raw on-disk value of the time.
+    pub fn raw(&self) -> u16 {
+        self.0
+    }
+}
+
+fn is_leap_year(year: usize) -> bool {
+    (year % 4 == 0 && year % 100 != 0) || year % 400 == 0
+}
+
+fn days_in_year(year: usize) -> u64 {
+    if is_leap_year(year) { 366 } else { 365 }
+}
+
+fn days_in_month(year: usize, month: u8) -> u8 {
+    match month {
+        2 if is_leap_year(year) => 29,
+        2 => 28,
+        4 | 6 | 9 | 11 => 30,
+        _ => 31,
+    }
+}
+
+/// The number of days from 1970-01-01 to the first day of `year`.
+fn days_before_year(year: usize) -> u64 {
+    (1970..year).map(days_in_year).sum()
+}
+
 /// File attributes as represented in FAT32 on-disk structures.
 #[repr(C, packed)]
 #[derive(Default, Debug, Copy, Clone, PartialEq, Eq)]
 pub struct Attributes(u8);
 
+impl Attributes {
+    pub const READ_ONLY: u8 = 0x01;
+    pub const HIDDEN: u8 = 0x02;
+    pub const SYSTEM: u8 = 0x04;
+    pub const VOLUME_ID: u8 = 0x08;
+    pub const DIRECTORY: u8 = 0x10;
+    pub const ARCHIVE: u8 = 0x20;
+    pub const LFN: u8 = Self::READ_ONLY | Self::HIDDEN | Self::SYSTEM | Self::VOLUME_ID;
+
+    /// Returns `Attributes` with the raw attribute bits `bits`.
+    pub fn new(bits: u8) -> Attributes {
+        Attributes(bits)
+    }
+
+    /// The raw attribute bits.
+    pub fn bits(&self) -> u8 {
+        self.0
+    }
+
+    /// Returns `true` if every bit in `flags` is set.
+    pub fn contains(&self, flags: u8) -> bool {
+        self.0 & flags == flags
+    }
+
+    pub fn read_only(&self) -> bool {
+        self.contains(Self::READ_ONLY)
+    }
+
+    pub fn hidden(&self) -> bool {
+        self.contains(Self::HIDDEN)
+    }
+
+    pub fn volume_id(&self) -> bool {
+        self.contains(Self::VOLUME_ID)
+    }
+
+    pub fn directory(&self) -> bool {
+        self.contains(Self::DIRECTORY)
+    }
+
+    /// Returns `true` if these attributes mark a long file name entry.
+    pub fn lfn(&self) -> bool {
+        self.0 & 0x3F == Self::LFN
+    }
+}
+
 /// A structure containing a date and time.
+///
+/// FAT has no notion of time zones: timestamps are in whatever local time the
+/// writer used, and conversions to and from Unix time treat them as UTC.
 #[derive(Default, Copy, Clone, Debug, PartialEq, Eq)]
 pub struct Timestamp {
     pub date: Date,
-    pub time: Time
+    pub time: Time,
+    /// Hundredths of a second added to `time`, from 0 through 199. Only
+    /// creation times are stored with this resolution; it is always 0 for
+    /// other timestamps, and is only set by `with_increment_10ms()`.
+    pub increment_10ms: u8,
+}
+
+impl Timestamp {
+    /// The number of seconds from the Unix epoch to the FAT epoch, 1980-01-01.
+    const FAT_EPOCH: u64 = 315_532_800;
+
+    /// The number of seconds from the Unix epoch to the end of 2107, the last
+    /// year a FAT date can hold.
+    const FAT_END: u64 = 4_354_819_200;
+
+    /// Returns the timestamp for the calendar date and time `year`-`month`-
+    /// `day` `hour`:`minute`:`second`, or `None` if any field is out of range
+    /// or the date is not between 1980 and 2107. An odd `second` is rounded
+    /// down, as write and access times can only hold even seconds.
+    pub fn new(
+        year: usize,
+        month: u8,
+        day: u8,
+        hour: u8,
+        minute: u8,
+        second: u8
+    ) -> Option<Timestamp> {
+        Some(Timestamp {
+            date: Date::new(year, month, day)?,
+            time: Time::new(hour, minute, second)?,
+            increment_10ms: 0,
+        })
+    }
+
+    /// Returns this timestamp with `increment` hundredths of a second added
+    /// to `time`, as stored in creation times, or `None` if `increment` is
+    /// greater than 199.
+    pub fn with_increment_10ms(self, increment: u8) -> Option<Timestamp> {
+        if increment > 199 {
+            return None;
+        }
+
+        Some(Timestamp { increment_10ms: increment, ..self })
+    }
+
+    /// Returns the timestamp `seconds` seconds after the Unix epoch, rounded
+    /// down to an even second, or `None` if that is before 1980 or after 2107.
+    /// A creation time keeps an odd second with `with_increment_10ms(100)`.
+    pub fn from_unix(seconds: u64) -> Option<Timestamp> {
+        if seconds < Timestamp::FAT_EPOCH || seconds >= Timestamp::FAT_END {
+            return None;
+        }
+
+        let (mut days, time) = (seconds / 86400, seconds % 86400);
+        let mut year = 1980;
+        days -= Timestamp::FAT_EPOCH / 86400;
+        while days >= days_in_year(year) {
+            days -= days_in_year(year);
+            year += 1;
+        }
+
+        let mut month = 1;
+        while days >= days_in_month(year, month) as u64 {
+            days -= days_in_month(year, month) as u64;
+            month += 1;
+        }
+
+        Timestamp::new(year, month, days as u8 + 1, (time / 3600) as u8,
+                       (time / 60 % 60) as u8, (time % 60) as u8)
+    }
+
+    /// The number of whole seconds from the Unix epoch to this timestamp, or
+    /// `None` if any of its fields is out of range, as may be the case for
+    /// timestamps read from disk.
+    pub fn to_unix(&self) -> Option<u64> {
+        use traits::Timestamp;
+
+        let (year, month, day) = (self.year(), self.month(), self.day());
+        if Date::new(year, month, day).is_none() || self.increment_10ms > 199 {
+            return None;
+        }
+
+        if self.hour() > 23 || self.minute() > 59 || self.second() > 59 {
+            return None;
+        }
+
+        let days = days_before_year(year)
+            + (1..month).map(|m| days_in_month(year, m) as u64).sum::<u64>()
+            + day as u64 - 1;
+        Some(days * 86400 + self.hour() as u64 * 3600 + self.minute() as u64 * 60
+             + self.second() as u64)
+    }
 }
 
 /// Metadata for a directory entry.
 #[derive(Default, Debug, Clone)]
 pub struct Metadata {
-    // FIXME: Fill me in.
+    pub attributes: Attributes,
+    pub created: Timestamp,
+    pub accessed: Timestamp,
+    pub modified: Timestamp,
 }
 
-// FIXME: Implement `traits::Timestamp` for `Timestamp`.
+impl traits::Timestamp for Timestamp {
+    fn year(&self) -> usize {
+        (self.date.0 >> 9) as usize + 1980
+    }
+
+    fn month(&self) -> u8 {
+        ((self.date.0 >> 5) & 0b1111) as u8
+    }
 
-// FIXME: Implement `traits::Metadata` for `Metadata`.
+    fn day(&self) -> u8 {
+        (self.date.0 & 0b11111) as u8
+    }
 
-// FIXME: Implement `fmt::Display` (to your liking) for `Metadata`.
+    fn hour(&self) -> u8 {
+        (self.time.0 >> 11) as u8
+    }
+
+    fn minute(&self) -> u8 {
+        ((self.time.0 >> 5) & 0b111111) as u8
+    }
+
+    fn second(&self) -> u8 {
+        (self.time.0 & 0b11111) as u8 * 2 + self.increment_10ms / 100
+    }
+}
+
+impl traits::Metadata for Metadata {
+    type Timestamp = Timestamp;
+
+    fn read_only(&self) -> bool {
+        self.attributes.read_only()
+    }
+
+    fn hidden(&self) -> bool {
+        self.attributes.hidden()
+    }
+
+    fn created(&self) -> Self::Timestamp {
+        self.created
+    }
+
+    fn accessed(&self) -> Self::Timestamp {
+        self.accessed
+    }
+
+    fn modified(&self) -> Self::Timestamp {
+        self.modified
+    }
+}
+
+impl fmt::Display for Timestamp {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        use traits::Timestamp;
+        write!(f, "{:02}/{:02}/{} {:02}:{:02}:{:02}",
+               self.month(), self.day(), self.year(),
+               self.hour(), self.minute(), self.second())
+    }
+}
+
+impl fmt::Display for Metadata {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let flag = |set: bool, c: char| if set { c } else { '-' };
+        write!(f, "{}{}{} {} {}",
+               flag(self.attributes.directory(), 'd'),
+               flag(self.attributes.read_only(), 'r'),
+               flag(self.attributes.hidden(), 'h'),
+               self.created, self.modified)
+    }
+}
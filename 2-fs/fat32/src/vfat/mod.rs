@@ -2,23 +2,29 @@ pub(crate) mod file;
 pub(crate) mod dir;
 pub(crate) mod vfat;
 pub(crate) mod ebpb;
+pub(crate) mod fsinfo;
 pub(crate) mod error;
 pub(crate) mod cluster;
 pub(crate) mod fat;
 pub(crate) mod entry;
 pub(crate) mod metadata;
 pub(crate) mod cache;
+pub(crate) mod charset;
 pub(crate) mod shared;
 
 pub use self::ebpb::BiosParameterBlock;
+pub use self::fsinfo::FsInfo;
 pub use self::file::File;
 pub use self::dir::Dir;
 pub use self::error::Error;
-pub use self::vfat::VFat;
+pub use self::vfat::{VFat, MountOptions};
 pub use self::entry::Entry;
 pub use self::metadata::{Metadata, Attributes, Date, Time, Timestamp};
 pub use self::shared::Shared;
+pub use self::fat::FatType;
+pub use self::cache::{CacheStats, DEFAULT_CACHE_CAPACITY, DEFAULT_READ_AHEAD};
+pub use self::charset::{CodePage, CP437};
 
-pub(crate) use self::cache::{CachedDevice, Partition};
+pub(crate) use self::cache::CachedDevice;
 pub(crate) use self::fat::{Status, FatEntry};
 pub(crate) use self::cluster::Cluster;
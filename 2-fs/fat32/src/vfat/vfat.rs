@@ -1,84 +1,1319 @@
 use std::io;
-use std::path::Path;
+use std::path::{Path, Component};
 use std::mem::size_of;
 use std::cmp::min;
 
-use util::SliceExt;
 use mbr::MasterBootRecord;
-use vfat::{Shared, Cluster, File, Dir, Entry, FatEntry, Error, Status};
-use vfat::{BiosParameterBlock, CachedDevice, Partition};
+use gpt::GuidPartitionTable;
+use vfat::{Shared, Cluster, File, Dir, Entry, FatEntry, FatType, Error, Status, Attributes};
+use vfat::Timestamp;
+use vfat::{BiosParameterBlock, FsInfo, CachedDevice, CacheStats, CodePage, CP437};
+use vfat::dir::{EntryLocation, VFatRegularDirEntry};
 use traits::{FileSystem, BlockDevice};
+use partition::PartitionDevice;
+
+/// Options controlling how `VFat::with_options()` mounts a volume.
+#[derive(Debug, Default, Copy, Clone)]
+pub struct MountOptions {
+    /// Reject every modification of the volume with `PermissionDenied`.
+    pub read_only: bool,
+    /// Set a file's access date when it is first read through a `File`. The
+    /// date is taken from `clock`; without one, access dates are left as is.
+    pub update_access_date: bool,
+    /// Honor the clean shutdown bit in FAT[1] of FAT16 and FAT32 volumes:
+    /// record whether the volume was cleanly unmounted, mark it as in use
+    /// while it is mounted writable, and mark it clean on `unmount()`.
+    pub check_dirty_flag: bool,
+    /// Returns the current date and time.
+    pub clock: Option<fn() -> Timestamp>,
+}
 
 #[derive(Debug)]
 pub struct VFat {
     device: CachedDevice,
+    fat_type: FatType,
     bytes_per_sector: u16,
     sectors_per_cluster: u8,
     sectors_per_fat: u32,
+    num_fats: u8,
+    /// The only FAT copy read and written when mirroring is disabled, or
+    /// `None` if every copy is written.
+    active_fat: Option<u8>,
     fat_start_sector: u64,
+    root_dir_start_sector: u64,
+    root_dir_sectors: u64,
     data_start_sector: u64,
     root_dir_cluster: Cluster,
+    cluster_count: u32,
+    /// The sector and contents of the FSInfo structure of a FAT32 volume, if
+    /// the volume has a valid one.
+    fsinfo: Option<(u64, FsInfo)>,
+    /// The number of free clusters, or `None` until it is known.
+    free_clusters: Option<u32>,
+    /// The cluster at which the search for a free cluster starts.
+    next_free: Cluster,
+    /// Whether `free_clusters` or `next_free` changed since the FSInfo
+    /// structure was last written.
+    fsinfo_dirty: bool,
+    /// The code page of short names.
+    code_page: CodePage,
+    options: MountOptions,
+    /// Whether the clean shutdown bit was clear when the volume was mounted.
+    was_dirty: bool,
+    /// Whether the BPB was read from the backup boot sector.
+    used_backup_boot_sector: bool,
 }
 
+/// The sector of a FAT32 volume holding the backup boot sector.
+const BACKUP_BOOT_SECTOR: u64 = 6;
+
 impl VFat {
-    pub fn from<T>(mut device: T) -> Result<Shared<VFat>, Error>
+    /// Mounts the FAT volume on `device` with the default `MountOptions`.
+    pub fn from<T>(device: T) -> Result<Shared<VFat>, Error>
+        where T: BlockDevice + 'static
+    {
+        VFat::with_options(device, MountOptions::default())
+    }
+
+    /// Mounts the FAT volume on `device` with the options `options`.
+    ///
+    /// # Errors
+    ///
+    /// In addition to the errors of reading the volume, returns an error if
+    /// `options.check_dirty_flag` is set and the volume can't be marked as in
+    /// use.
+    pub fn with_options<T>(mut device: T, options: MountOptions) -> Result<Shared<VFat>, Error>
         where T: BlockDevice + 'static
     {
-        unimplemented!("VFat::from()")
-    }
-
-    // TODO: The following methods may be useful here:
-    //
-    //  * A method to read from an offset of a cluster into a buffer.
-    //
-    //    fn read_cluster(
-    //        &mut self,
-    //        cluster: Cluster,
-    //        offset: usize,
-    //        buf: &mut [u8]
-    //    ) -> io::Result<usize>;
-    //
-    //  * A method to read all of the clusters chained from a starting cluster
-    //    into a vector.
-    //
-    //    fn read_chain(
-    //        &mut self,
-    //        start: Cluster,
-    //        buf: &mut Vec<u8>
-    //    ) -> io::Result<usize>;
-    //
-    //  * A method to return a reference to a `FatEntry` for a cluster where the
-    //    reference points directly into a cached sector.
-    //
-    //    fn fat_entry(&mut self, cluster: Cluster) -> io::Result<&FatEntry>;
+        let start = VFat::volume_start(&mut device)?;
+        let (ebpb, used_backup_boot_sector) = VFat::read_bpb(&mut device, start)?;
+
+        let fat_type = ebpb.fat_type();
+        let bytes_per_sector = ebpb.bytes_per_sector;
+        let sectors_per_fat = ebpb.sectors_per_fat();
+        let fat_start_sector = ebpb.reserved_sectors as u64;
+        let root_dir_start_sector = fat_start_sector
+            + ebpb.num_fats as u64 * sectors_per_fat as u64;
+        let root_dir_sectors = ebpb.root_dir_sectors() as u64;
+        let data_start_sector = root_dir_start_sector + root_dir_sectors;
+
+        let entry_bits = match fat_type {
+            FatType::Fat12 => 12,
+            FatType::Fat16 => 16,
+            FatType::Fat32 => 32,
+        };
+        let fat_entries = sectors_per_fat as u64 * bytes_per_sector as u64 * 8 / entry_bits;
+        let cluster_count = min(ebpb.cluster_count() as u64, fat_entries.saturating_sub(2));
+
+        let root_dir_cluster = match ebpb.fat32() {
+            Some(fat32) => Cluster::from(fat32.root_cluster),
+            None => Cluster::from(0),
+        };
+
+        // Sectors are numbered from the start of the volume from here on.
+        let (logical_size, physical_size) = (bytes_per_sector as u64, device.sector_size());
+        if logical_size < physical_size || logical_size % physical_size != 0 {
+            return Err(Error::BadBytesPerSector { sector: start, value: bytes_per_sector });
+        }
+
+        let sectors = ebpb.total_sectors() as u64 * (logical_size / physical_size);
+        let device = PartitionDevice::with_sector_size(device, start, sectors, logical_size);
+        let mut device = CachedDevice::new(device);
+        let fsinfo = match ebpb.fat32() {
+            Some(fat32) if fat32.fsinfo_sector != 0 && fat32.fsinfo_sector != 0xFFFF => {
+                let sector = fat32.fsinfo_sector as u64;
+                FsInfo::from(&mut device, sector).ok().map(|fsinfo| (sector, fsinfo))
+            }
+            _ => None,
+        };
+
+        // The FSInfo values are only hints; ones that can't be right are
+        // treated as unknown.
+        let cluster_count = cluster_count as u32;
+        let hints = fsinfo.map(|(_, fsinfo)| (fsinfo.free_count(), fsinfo.next_free()));
+        let free_clusters = hints.and_then(|(free, _)| free)
+            .filter(|&free| free <= cluster_count);
+        let next_free = hints.and_then(|(_, next)| next)
+            .filter(|&next| next >= 2 && next < cluster_count + 2)
+            .unwrap_or(2);
+
+        let mut vfat = VFat {
+            device: device,
+            fat_type: fat_type,
+            bytes_per_sector: bytes_per_sector,
+            sectors_per_cluster: ebpb.sectors_per_cluster,
+            sectors_per_fat: sectors_per_fat,
+            num_fats: ebpb.num_fats,
+            active_fat: ebpb.active_fat(),
+            fat_start_sector: fat_start_sector,
+            root_dir_start_sector: root_dir_start_sector,
+            root_dir_sectors: root_dir_sectors,
+            data_start_sector: data_start_sector,
+            root_dir_cluster: root_dir_cluster,
+            cluster_count: cluster_count,
+            fsinfo: fsinfo,
+            free_clusters: free_clusters,
+            next_free: Cluster::from(next_free),
+            fsinfo_dirty: false,
+            code_page: CP437,
+            options: options,
+            was_dirty: false,
+            used_backup_boot_sector: used_backup_boot_sector,
+        };
+
+        if options.check_dirty_flag {
+            if let Some(clean_bit) = vfat.clean_shutdown_bit() {
+                let fat_1 = vfat.fat_entry(Cluster::from(1))?.0;
+                vfat.was_dirty = fat_1 & clean_bit == 0;
+                if !options.read_only {
+                    vfat.write_fat_entry(Cluster::from(1), fat_1 & !clean_bit)?;
+                    vfat.device.flush()?;
+                }
+            }
+        }
+
+        Ok(Shared::new(vfat))
+    }
+
+    /// Reads and validates the BPB of the volume starting at sector `start`.
+    /// If the boot sector's signature is bad, the BPB of a FAT32 volume is
+    /// read from its backup boot sector instead. Returns the BPB and whether
+    /// it was read from the backup.
+    ///
+    /// # Errors
+    ///
+    /// Returns `BadSignature` if neither boot sector has a valid signature,
+    /// and the errors of `BiosParameterBlock::validate()`.
+    fn read_bpb<T: BlockDevice>(
+        device: &mut T,
+        start: u64
+    ) -> Result<(BiosParameterBlock, bool), Error> {
+        match BiosParameterBlock::from(&mut *device, start) {
+            Ok(ebpb) => {
+                ebpb.validate(start)?;
+                Ok((ebpb, false))
+            }
+            Err(Error::BadSignature) => {
+                let backup = start + BACKUP_BOOT_SECTOR;
+                let ebpb = BiosParameterBlock::from(&mut *device, backup)?;
+                match ebpb.fat32().is_some() && ebpb.validate(backup).is_ok() {
+                    true => Ok((ebpb, true)),
+                    false => Err(Error::BadSignature),
+                }
+            }
+            Err(e) => Err(e),
+        }
+    }
+
+    /// Returns `true` if the primary boot sector's signature was bad, so the
+    /// volume was mounted from its backup boot sector. The primary boot
+    /// sector is left as is.
+    pub fn used_backup_boot_sector(&self) -> bool {
+        self.used_backup_boot_sector
+    }
+
+    /// The only FAT copy in use if FAT mirroring is disabled, or `None` if
+    /// every FAT write is mirrored to all copies.
+    pub fn active_fat(&self) -> Option<u8> {
+        self.active_fat
+    }
+
+    /// The bit of FAT[1] that is set when the volume was cleanly unmounted, or
+    /// `None` on FAT12, which has no such bit.
+    fn clean_shutdown_bit(&self) -> Option<u32> {
+        match self.fat_type {
+            FatType::Fat12 => None,
+            FatType::Fat16 => Some(0x8000),
+            FatType::Fat32 => Some(0x08000000),
+        }
+    }
+
+    /// The options the volume was mounted with.
+    pub fn options(&self) -> &MountOptions {
+        &self.options
+    }
+
+    /// Returns `true` if the volume was mounted with `check_dirty_flag` and
+    /// its clean shutdown bit was clear, meaning it wasn't cleanly unmounted
+    /// and may need checking.
+    pub fn was_dirty(&self) -> bool {
+        self.was_dirty
+    }
+
+    /// Writes every modified sector back to the disk and, for a volume
+    /// mounted with `check_dirty_flag`, marks it as cleanly unmounted. The
+    /// volume should not be modified afterwards.
+    pub fn unmount(&mut self) -> io::Result<()> {
+        self.flush()?;
+        if self.options.check_dirty_flag && !self.options.read_only {
+            if let Some(clean_bit) = self.clean_shutdown_bit() {
+                let fat_1 = self.fat_entry(Cluster::from(1))?.0;
+                self.write_fat_entry(Cluster::from(1), fat_1 | clean_bit)?;
+                self.device.flush()?;
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Returns the cached sector `sector` for writing.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error of `PermissionDenied` if the volume is mounted
+    /// read-only.
+    fn sector_mut(&mut self, sector: u64) -> io::Result<&mut [u8]> {
+        if self.options.read_only {
+            return Err(io::Error::new(io::ErrorKind::PermissionDenied,
+                                      "volume is mounted read-only"));
+        }
+
+        self.device.get_mut(sector)
+    }
+
+    /// Returns `true` if sector `sector` of `device` is the boot sector of a
+    /// FAT volume: it holds a plausible BPB or, if its signature is bad, the
+    /// volume's backup boot sector is intact.
+    pub(crate) fn is_boot_sector<T: BlockDevice>(device: &mut T, sector: u64) -> bool {
+        match BiosParameterBlock::from(&mut *device, sector) {
+            Ok(ebpb) => ebpb.is_plausible(),
+            Err(Error::BadSignature) => VFat::read_bpb(device, sector).is_ok(),
+            Err(_) => false,
+        }
+    }
+
+    /// Returns the first sector of the FAT volume on `device`.
+    ///
+    /// A device whose first sector is a FAT boot sector, as on floppy-style
+    /// images, has no partition table and the volume starts at sector 0. So
+    /// does one whose first sector is damaged but whose backup boot sector is
+    /// intact. Otherwise, the volume is the first FAT partition, primary or
+    /// logical, in the MBR or, for a protective MBR, the first Basic Data or
+    /// EFI System partition in the GPT that holds a FAT volume.
+    fn volume_start<T: BlockDevice>(device: &mut T) -> Result<u64, Error> {
+        if VFat::is_boot_sector(&mut *device, 0) {
+            return Ok(0);
+        }
+
+        let mbr = MasterBootRecord::from(&mut *device)?;
+        if mbr.is_protective() {
+            let gpt = GuidPartitionTable::from(&mut *device)?;
+            let partition = gpt.first_fat(device)?.ok_or(Error::NotFound)?;
+            return Ok(partition.first_lba);
+        }
+
+        for partition in mbr.all_partitions(device) {
+            let partition = partition?;
+            if partition.is_fat() {
+                return Ok(partition.relative_sector as u64);
+            }
+        }
+
+        Err(Error::NotFound)
+    }
+
+    /// The OEM code page in which short names are stored. This is `CP437`
+    /// unless changed with `set_code_page()`.
+    pub fn code_page(&self) -> &CodePage {
+        &self.code_page
+    }
+
+    /// Sets the OEM code page in which short names are read and written.
+    pub fn set_code_page(&mut self, code_page: CodePage) {
+        self.code_page = code_page;
+    }
+
+    /// The FAT type of this volume.
+    pub fn fat_type(&self) -> FatType {
+        self.fat_type
+    }
+
+    /// The total number of data clusters in the volume.
+    pub fn total_clusters(&self) -> u32 {
+        self.cluster_count
+    }
+
+    /// The number of free data clusters in the volume. The count is taken from
+    /// the FSInfo structure when it is known and is otherwise computed by
+    /// scanning the FAT once; it is kept current as clusters are allocated and
+    /// freed.
+    pub fn free_clusters(&mut self) -> io::Result<u32> {
+        if let Some(free) = self.free_clusters {
+            return Ok(free);
+        }
+
+        let mut free = 0;
+        for number in 2..self.cluster_count + 2 {
+            if self.fat_entry(Cluster::from(number))?.status() == Status::Free {
+                free += 1;
+            }
+        }
+
+        self.free_clusters = Some(free);
+        self.fsinfo_dirty = !self.options.read_only;
+        Ok(free)
+    }
+
+    /// Returns `true` if `dir` refers to the fixed root directory region of a
+    /// FAT12 or FAT16 volume rather than to a cluster chain.
+    pub(crate) fn is_fixed_root(&self, dir: Cluster) -> bool {
+        self.fat_type != FatType::Fat32 && !dir.is_data()
+    }
+
+    /// The first cluster of the root directory.
+    pub(crate) fn root_dir_cluster(&self) -> Cluster {
+        self.root_dir_cluster
+    }
+
+    /// The number of data clusters in the volume. Data clusters are numbered
+    /// from `2` through `cluster_count() + 1`.
+    pub(crate) fn cluster_count(&self) -> u32 {
+        self.cluster_count
+    }
+
+    /// The number of copies of the FAT on the volume.
+    pub(crate) fn num_fats(&self) -> u8 {
+        self.num_fats
+    }
+
+    /// The number of sectors in each copy of the FAT.
+    pub(crate) fn sectors_per_fat(&self) -> u32 {
+        self.sectors_per_fat
+    }
+
+    /// Returns sector `index` of the FAT copy `fat`. Every other method reads
+    /// only the active copy.
+    pub(crate) fn fat_sector(&mut self, fat: u8, index: u32) -> io::Result<&[u8]> {
+        let sector = self.fat_copy_start_sector(fat) + index as u64;
+        self.device.get(sector)
+    }
+
+    /// Returns a mutable reference to sector `index` of the FAT copy `fat`.
+    pub(crate) fn fat_sector_mut(&mut self, fat: u8, index: u32) -> io::Result<&mut [u8]> {
+        let sector = self.fat_copy_start_sector(fat) + index as u64;
+        self.sector_mut(sector)
+    }
+
+    /// Copies every sector of the first FAT that differs in another copy over
+    /// that copy. Does nothing if mirroring is disabled.
+    pub(crate) fn mirror_fats(&mut self) -> io::Result<()> {
+        if self.active_fat.is_some() {
+            return Ok(());
+        }
+
+        for fat in 1..self.num_fats() {
+            for index in 0..self.sectors_per_fat() {
+                let first = self.fat_sector(0, index)?.to_vec();
+                if self.fat_sector(fat, index)? != &first[..] {
+                    self.fat_sector_mut(fat, index)?.copy_from_slice(&first);
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    /// The first sector of the FAT copy `fat`.
+    fn fat_copy_start_sector(&self, fat: u8) -> u64 {
+        assert!(fat < self.num_fats, "FAT copy {} does not exist", fat);
+        self.fat_start_sector + fat as u64 * self.sectors_per_fat as u64
+    }
+
+    /// The size of a cluster in bytes.
+    pub(crate) fn bytes_per_cluster(&self) -> usize {
+        self.bytes_per_sector as usize * self.sectors_per_cluster as usize
+    }
+
+    /// The first sector of the data cluster `cluster`.
+    fn cluster_start_sector(&self, cluster: Cluster) -> u64 {
+        self.data_start_sector + cluster.data_index() * self.sectors_per_cluster as u64
+    }
+
+    /// Reads from `offset` bytes into the cluster `cluster` into `buf`. Reading
+    /// stops at the end of the cluster or when `buf` is full, whichever comes
+    /// first. Returns the number of bytes read.
+    pub(crate) fn read_cluster(
+        &mut self,
+        cluster: Cluster,
+        offset: usize,
+        buf: &mut [u8]
+    ) -> io::Result<usize> {
+        self.check_cluster(cluster)?;
+        let sector_size = self.bytes_per_sector as usize;
+        let amount = min(buf.len(), self.bytes_per_cluster().saturating_sub(offset));
+        let start_sector = self.cluster_start_sector(cluster);
+
+        let mut read = 0;
+        while read < amount {
+            let position = offset + read;
+            let sector = self.device.get(start_sector + (position / sector_size) as u64)?;
+            let sector_offset = position % sector_size;
+            let n = min(amount - read, sector_size - sector_offset);
+            buf[read..read + n].copy_from_slice(&sector[sector_offset..sector_offset + n]);
+            read += n;
+        }
+
+        Ok(read)
+    }
+
+    /// Writes `buf` into the cluster `cluster` starting at `offset` bytes into
+    /// the cluster. Writing stops at the end of the cluster or when `buf` is
+    /// exhausted, whichever comes first. Returns the number of bytes written.
+    pub(crate) fn write_cluster(
+        &mut self,
+        cluster: Cluster,
+        offset: usize,
+        buf: &[u8]
+    ) -> io::Result<usize> {
+        self.check_cluster(cluster)?;
+        let sector_size = self.bytes_per_sector as usize;
+        let amount = min(buf.len(), self.bytes_per_cluster().saturating_sub(offset));
+        let start_sector = self.cluster_start_sector(cluster);
+
+        let mut written = 0;
+        while written < amount {
+            let position = offset + written;
+            let sector = self.sector_mut(start_sector + (position / sector_size) as u64)?;
+            let sector_offset = position % sector_size;
+            let n = min(amount - written, sector_size - sector_offset);
+            sector[sector_offset..sector_offset + n].copy_from_slice(&buf[written..written + n]);
+            written += n;
+        }
+
+        Ok(written)
+    }
+
+    /// Reads all of the clusters chained from `start` into `buf`, returning the
+    /// number of bytes read.
+    pub(crate) fn read_chain(
+        &mut self,
+        start: Cluster,
+        buf: &mut Vec<u8>
+    ) -> io::Result<usize> {
+        if self.is_fixed_root(start) {
+            let sector_size = self.bytes_per_sector as usize;
+            for i in 0..self.root_dir_sectors {
+                let sector = self.device.get(self.root_dir_start_sector + i)?;
+                buf.extend_from_slice(&sector[..sector_size]);
+            }
+
+            return Ok(self.root_dir_sectors as usize * sector_size);
+        }
+
+        let cluster_size = self.bytes_per_cluster();
+        let mut cluster = Some(start);
+        let mut read = 0;
+        let mut index = 0;
+        while let Some(current) = cluster {
+            let buf_start = buf.len();
+            buf.resize(buf_start + cluster_size, 0);
+            read += self.read_cluster(current, 0, &mut buf[buf_start..])?;
+            cluster = self.next_in_chain(current, index)?;
+            index += 1;
+        }
+
+        Ok(read)
+    }
+
+    /// Returns the FAT entry for `cluster`.
+    pub(crate) fn fat_entry(&mut self, cluster: Cluster) -> io::Result<FatEntry> {
+        let offset = self.fat_entry_offset(cluster);
+        let mut raw = [0u8; 4];
+        let raw = match self.fat_type {
+            FatType::Fat12 => {
+                self.read_fat_bytes(offset, &mut raw[..2])?;
+                let pair = u16::from_le_bytes([raw[0], raw[1]]) as u32;
+                match cluster.number() % 2 {
+                    0 => pair & 0xFFF,
+                    _ => pair >> 4,
+                }
+            }
+            FatType::Fat16 => {
+                self.read_fat_bytes(offset, &mut raw[..2])?;
+                u16::from_le_bytes([raw[0], raw[1]]) as u32
+            }
+            FatType::Fat32 => {
+                self.read_fat_bytes(offset, &mut raw)?;
+                u32::from_le_bytes(raw)
+            }
+        };
+
+        Ok(FatEntry::from_raw(self.fat_type, raw))
+    }
+
+    /// Sets the FAT entry for `cluster` to `value`. `value` is truncated to the
+    /// width of the volume's FAT entries; the reserved high 4 bits of FAT32
+    /// entries are preserved. The count of free clusters is kept current.
+    pub(crate) fn set_fat_entry(&mut self, cluster: Cluster, value: u32) -> io::Result<()> {
+        let was_free = self.fat_entry(cluster)?.status() == Status::Free;
+        self.write_fat_entry(cluster, value)?;
+
+        let is_free = value & self.fat_type.mask() == 0;
+        if let Some(ref mut free) = self.free_clusters {
+            match (was_free, is_free) {
+                (true, false) => *free = free.saturating_sub(1),
+                (false, true) => *free += 1,
+                _ => return Ok(()),
+            }
+
+            self.fsinfo_dirty = true;
+        }
+
+        Ok(())
+    }
+
+    /// Writes `value` into the FAT entry for `cluster` without maintaining the
+    /// count of free clusters.
+    fn write_fat_entry(&mut self, cluster: Cluster, value: u32) -> io::Result<()> {
+        let offset = self.fat_entry_offset(cluster);
+        let value = value & self.fat_type.mask();
+        let mut raw = [0u8; 4];
+        match self.fat_type {
+            FatType::Fat12 => {
+                self.read_fat_bytes(offset, &mut raw[..2])?;
+                let pair = u16::from_le_bytes([raw[0], raw[1]]);
+                let pair = match cluster.number() % 2 {
+                    0 => (pair & 0xF000) | value as u16,
+                    _ => (pair & 0x000F) | (value << 4) as u16,
+                };
+                self.write_fat_bytes(offset, &pair.to_le_bytes())
+            }
+            FatType::Fat16 => {
+                self.write_fat_bytes(offset, &(value as u16).to_le_bytes())
+            }
+            FatType::Fat32 => {
+                self.read_fat_bytes(offset, &mut raw)?;
+                let old = u32::from_le_bytes(raw);
+                let new = (old & !self.fat_type.mask()) | value;
+                self.write_fat_bytes(offset, &new.to_le_bytes())
+            }
+        }
+    }
+
+    /// The byte offset of the FAT entry for `cluster` from the start of the
+    /// FAT.
+    fn fat_entry_offset(&self, cluster: Cluster) -> u64 {
+        let number = cluster.number() as u64;
+        match self.fat_type {
+            FatType::Fat12 => number + number / 2,
+            FatType::Fat16 => number * 2,
+            FatType::Fat32 => number * size_of::<FatEntry>() as u64,
+        }
+    }
+
+    /// Reads `buf.len()` bytes starting at byte `offset` of the active FAT:
+    /// the first copy unless mirroring is disabled.
+    fn read_fat_bytes(&mut self, offset: u64, buf: &mut [u8]) -> io::Result<()> {
+        let sector_size = self.bytes_per_sector as u64;
+        let start = self.fat_copy_start_sector(self.active_fat.unwrap_or(0));
+        for (i, byte) in buf.iter_mut().enumerate() {
+            let position = offset + i as u64;
+            let sector = self.device.get(start + position / sector_size)?;
+            *byte = sector[(position % sector_size) as usize];
+        }
+
+        Ok(())
+    }
+
+    /// Writes `buf` starting at byte `offset` of every FAT copy, or only of
+    /// the active copy if mirroring is disabled.
+    fn write_fat_bytes(&mut self, offset: u64, buf: &[u8]) -> io::Result<()> {
+        let sector_size = self.bytes_per_sector as u64;
+        let fats = match self.active_fat {
+            Some(active) => active..active + 1,
+            None => 0..self.num_fats,
+        };
+
+        for fat in fats {
+            let start = self.fat_copy_start_sector(fat);
+            for (i, &byte) in buf.iter().enumerate() {
+                let position = offset + i as u64;
+                let sector = self.sector_mut(start + position / sector_size)?;
+                sector[(position % sector_size) as usize] = byte;
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Returns an error of `InvalidData` carrying `Error::ClusterOutOfRange`
+    /// if `cluster` is not a data cluster of this volume.
+    fn check_cluster(&self, cluster: Cluster) -> io::Result<()> {
+        match cluster.is_data() && cluster.number() < self.cluster_count + 2 {
+            true => Ok(()),
+            false => Err(Error::ClusterOutOfRange(cluster.number()).into()),
+        }
+    }
+
+    /// Returns the cluster following `cluster` in its chain, or `None` if
+    /// `cluster` is the last cluster in the chain.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error of `InvalidData` carrying a `vfat::Error` that names
+    /// the offending cluster if `cluster` or the cluster following it is
+    /// outside of the data region, or if the FAT entry for `cluster` marks it
+    /// bad, reserved, or free.
+    pub(crate) fn next_cluster(&mut self, cluster: Cluster) -> io::Result<Option<Cluster>> {
+        self.check_cluster(cluster)?;
+        match self.fat_entry(cluster)?.status() {
+            Status::Data(next) => self.check_cluster(next).map(|_| Some(next)),
+            Status::Eoc(_) => Ok(None),
+            Status::Bad => Err(Error::BadCluster(cluster.number()).into()),
+            Status::Free | Status::Reserved => {
+                Err(Error::ReservedCluster(cluster.number()).into())
+            }
+        }
+    }
+
+    /// Returns the cluster following `cluster`, the `index`th cluster of its
+    /// chain, like `next_cluster()`.
+    ///
+    /// # Errors
+    ///
+    /// In addition to the errors of `next_cluster()`, returns an error of
+    /// `InvalidData` carrying `Error::ChainCycle` if the chain would become
+    /// longer than the volume has clusters, which means it loops.
+    pub(crate) fn next_in_chain(
+        &mut self,
+        cluster: Cluster,
+        index: u64
+    ) -> io::Result<Option<Cluster>> {
+        match self.next_cluster(cluster)? {
+            Some(next) if index + 1 >= self.cluster_count as u64 => {
+                Err(Error::ChainCycle(next.number()).into())
+            }
+            next => Ok(next),
+        }
+    }
+
+    /// Allocates a free cluster, marks it as the end of its chain, and zeroes
+    /// its contents. If `prev` is `Some`, the new cluster is linked after
+    /// `prev`.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error of `Other` if there are no free clusters.
+    pub(crate) fn alloc_cluster(&mut self, prev: Option<Cluster>) -> io::Result<Cluster> {
+        let hint = self.next_free.number();
+        let mut free = None;
+        for number in (hint..self.cluster_count + 2).chain(2..hint) {
+            let cluster = Cluster::from(number);
+            if self.fat_entry(cluster)?.status() == Status::Free {
+                free = Some(cluster);
+                break;
+            }
+        }
+
+        let cluster = free.ok_or_else(|| {
+            io::Error::new(io::ErrorKind::Other, "no free clusters")
+        })?;
+
+        self.set_fat_entry(cluster, 0x0FFFFFFF)?;
+        self.next_free = match cluster.number() + 1 {
+            next if next < self.cluster_count + 2 => Cluster::from(next),
+            _ => Cluster::from(2),
+        };
+        self.fsinfo_dirty = true;
+
+        if let Some(prev) = prev {
+            self.set_fat_entry(prev, cluster.number())?;
+        }
+
+        let zeroes = vec![0u8; self.bytes_per_cluster()];
+        self.write_cluster(cluster, 0, &zeroes)?;
+        Ok(cluster)
+    }
+
+    /// Returns every run of consecutive free clusters as its first cluster and
+    /// length, in cluster order.
+    pub(crate) fn free_runs(&mut self) -> io::Result<Vec<(Cluster, u32)>> {
+        let mut runs: Vec<(Cluster, u32)> = Vec::new();
+        for number in 2..self.cluster_count + 2 {
+            let cluster = Cluster::from(number);
+            if self.fat_entry(cluster)?.status() != Status::Free {
+                continue;
+            }
+
+            match runs.last_mut() {
+                Some(&mut (start, ref mut len)) if start.number() + *len == number => *len += 1,
+                _ => runs.push((cluster, 1)),
+            }
+        }
+
+        Ok(runs)
+    }
+
+    /// Allocates `count` clusters, zeroes them, and links them into a chain
+    /// after `prev`, if it is `Some`. Returns the runs of consecutive clusters
+    /// allocated, in chain order.
+    ///
+    /// The smallest free run that holds all `count` clusters is used. If there
+    /// is none, the largest free runs are used, largest first.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error of `Other`, without allocating anything, if fewer than
+    /// `count` clusters are free.
+    pub(crate) fn alloc_runs(
+        &mut self,
+        prev: Option<Cluster>,
+        count: u32
+    ) -> io::Result<Vec<(Cluster, u32)>> {
+        let mut free = self.free_runs()?;
+        let fit = free.iter().filter(|run| run.1 >= count).min_by_key(|run| run.1).cloned();
+        let runs = match fit {
+            Some((start, _)) => vec![(start, count)],
+            None => {
+                free.sort_by(|a, b| b.1.cmp(&a.1).then(a.0.cmp(&b.0)));
+                let mut runs = Vec::new();
+                let mut remaining = count;
+                for (start, len) in free {
+                    if remaining == 0 {
+                        break;
+                    }
+
+                    runs.push((start, min(len, remaining)));
+                    remaining -= min(len, remaining);
+                }
+
+                if remaining > 0 {
+                    return Err(io::Error::new(io::ErrorKind::Other, "not enough free clusters"));
+                }
+
+                runs
+            }
+        };
+
+        let zeroes = vec![0u8; self.bytes_per_cluster()];
+        let mut prev = prev;
+        for &(start, len) in &runs {
+            for number in start.number()..start.number() + len {
+                let cluster = Cluster::from(number);
+                self.set_fat_entry(cluster, 0x0FFFFFFF)?;
+                if let Some(prev) = prev {
+                    self.set_fat_entry(prev, number)?;
+                }
+
+                self.write_cluster(cluster, 0, &zeroes)?;
+                prev = Some(cluster);
+            }
+        }
+
+        if let Some(last) = prev {
+            self.next_free = match last.number() + 1 {
+                next if next < self.cluster_count + 2 => Cluster::from(next),
+                _ => Cluster::from(2),
+            };
+            self.fsinfo_dirty = true;
+        }
+
+        Ok(runs)
+    }
+
+    /// Returns the `index`th cluster of the chain starting at `start`.
+    fn chain_cluster(&mut self, start: Cluster, index: u64) -> io::Result<Cluster> {
+        let mut cluster = start;
+        for i in 0..index {
+            cluster = self.next_in_chain(cluster, i)?.ok_or_else(|| {
+                io::Error::new(io::ErrorKind::UnexpectedEof, "cluster chain too short")
+            })?;
+        }
+
+        Ok(cluster)
+    }
+
+    /// Frees every cluster in the chain starting at `start`.
+    ///
+    /// # Errors
+    ///
+    /// If the chain is malformed, the clusters before the fault are freed and
+    /// the error of `next_cluster()` is returned. A chain that loops is freed
+    /// until the walk returns to a cluster it already freed.
+    pub(crate) fn free_chain(&mut self, start: Cluster) -> io::Result<()> {
+        let mut cluster = Some(start);
+        while let Some(current) = cluster {
+            cluster = self.next_cluster(current)?;
+            self.set_fat_entry(current, 0)?;
+        }
+
+        Ok(())
+    }
+
+    /// Returns every cluster of the chain starting at `start`, in order.
+    ///
+    /// # Errors
+    ///
+    /// Returns the errors of `next_in_chain()` if the chain is malformed.
+    pub(crate) fn chain(&mut self, start: Cluster) -> io::Result<Vec<Cluster>> {
+        let mut clusters = vec![start];
+        let mut cluster = start;
+        while let Some(next) = self.next_in_chain(cluster, clusters.len() as u64 - 1)? {
+            clusters.push(next);
+            cluster = next;
+        }
+
+        Ok(clusters)
+    }
+
+    /// Returns the last cluster of the chain starting at `start`.
+    fn last_cluster(&mut self, start: Cluster) -> io::Result<Cluster> {
+        let mut cluster = start;
+        let mut index = 0;
+        while let Some(next) = self.next_in_chain(cluster, index)? {
+            cluster = next;
+            index += 1;
+        }
+
+        Ok(cluster)
+    }
+
+    /// Maps the byte `offset` into the directory `dir` to the sector holding
+    /// that byte and the offset inside of that sector. `dir` is either the
+    /// first cluster of a directory's chain or, on FAT12 and FAT16, cluster 0
+    /// for the fixed root directory region.
+    pub(crate) fn dir_position(&mut self, dir: Cluster, offset: u64) -> io::Result<(u64, usize)> {
+        let sector_size = self.bytes_per_sector as u64;
+        if self.is_fixed_root(dir) {
+            if offset >= self.root_dir_sectors * sector_size {
+                return Err(io::Error::new(io::ErrorKind::UnexpectedEof,
+                                          "offset past end of root directory"));
+            }
+
+            let sector = self.root_dir_start_sector + offset / sector_size;
+            return Ok((sector, (offset % sector_size) as usize));
+        }
+
+        let cluster_size = self.bytes_per_cluster() as u64;
+        let cluster = self.chain_cluster(dir, offset / cluster_size)?;
+        let cluster_offset = offset % cluster_size;
+        let sector = self.cluster_start_sector(cluster) + cluster_offset / sector_size;
+        Ok((sector, (cluster_offset % sector_size) as usize))
+    }
+
+    /// Reads the regular directory entry at `location`.
+    pub(crate) fn read_dir_entry(
+        &mut self,
+        location: EntryLocation
+    ) -> io::Result<VFatRegularDirEntry> {
+        let (sector, offset) = self.dir_position(location.dir, location.offset)?;
+        let sector = self.device.get(sector)?;
+        Ok(VFatRegularDirEntry::from_bytes(&sector[offset..]))
+    }
+
+    /// Overwrites the regular directory entry at `location` with `entry`.
+    pub(crate) fn write_dir_entry(
+        &mut self,
+        location: EntryLocation,
+        entry: &VFatRegularDirEntry
+    ) -> io::Result<()> {
+        self.write_raw_dir_entry(location.dir, location.offset, &entry.to_bytes())
+    }
+
+    /// Writes the raw 32-byte entry `raw` at byte `offset` of directory `dir`.
+    pub(crate) fn write_raw_dir_entry(&mut self, dir: Cluster, offset: u64, raw: &[u8]) -> io::Result<()> {
+        let (sector, sector_offset) = self.dir_position(dir, offset)?;
+        let sector = self.sector_mut(sector)?;
+        sector[sector_offset..sector_offset + raw.len()].copy_from_slice(raw);
+        Ok(())
+    }
+
+    /// Writes the raw directory entries `entries` into the first run of
+    /// consecutive free slots in the directory `dir` that is large enough to
+    /// hold them, growing the directory if there is no such run. Returns the
+    /// byte offset of the last entry written.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error of `Other` if `dir` is the fixed root directory of a
+    /// FAT12 or FAT16 volume and it has no room for the entries.
+    pub(crate) fn insert_dir_entries(
+        &mut self,
+        dir: Cluster,
+        entries: &[[u8; 32]]
+    ) -> io::Result<u64> {
+        let entry_size = size_of::<[u8; 32]>();
+        let mut data = Vec::new();
+        self.read_chain(dir, &mut data)?;
+
+        let mut run_start = data.len();
+        let mut run_length = 0;
+        for (i, raw) in data.chunks(entry_size).enumerate() {
+            if run_length == entries.len() {
+                break;
+            }
+
+            if raw[0] == 0x00 || raw[0] == 0xE5 {
+                if run_length == 0 {
+                    run_start = i * entry_size;
+                }
+
+                run_length += 1;
+            } else {
+                run_start = data.len();
+                run_length = 0;
+            }
+        }
+
+        if run_length < entries.len() {
+            if self.is_fixed_root(dir) {
+                return Err(io::Error::new(io::ErrorKind::Other, "root directory is full"));
+            }
+
+            let needed = (entries.len() * entry_size) as u64;
+            let mut available = (data.len() - run_start) as u64;
+            let mut last = self.last_cluster(dir)?;
+            while available < needed {
+                last = self.alloc_cluster(Some(last))?;
+                available += self.bytes_per_cluster() as u64;
+            }
+        }
+
+        for (i, entry) in entries.iter().enumerate() {
+            self.write_raw_dir_entry(dir, (run_start + i * entry_size) as u64, entry)?;
+        }
+
+        Ok((run_start + (entries.len() - 1) * entry_size) as u64)
+    }
+
+    /// Marks the regular entry at `location` and its LFN entries as deleted.
+    pub(crate) fn delete_dir_entries(&mut self, location: EntryLocation) -> io::Result<()> {
+        let entry_size = size_of::<[u8; 32]>() as u64;
+        let mut offset = location.offset - location.lfn_entries as u64 * entry_size;
+        while offset <= location.offset {
+            self.write_raw_dir_entry(location.dir, offset, &[0xE5])?;
+            offset += entry_size;
+        }
+
+        Ok(())
+    }
+
+    /// Writes every modified sector back to the disk, updating the FSInfo
+    /// structure first.
+    pub fn flush(&mut self) -> io::Result<()> {
+        if self.fsinfo_dirty {
+            if let Some((sector, mut fsinfo)) = self.fsinfo {
+                fsinfo.set_free_count(self.free_clusters);
+                fsinfo.set_next_free(Some(self.next_free.number()));
+                self.sector_mut(sector)?[..512].copy_from_slice(&fsinfo.to_bytes());
+                self.fsinfo = Some((sector, fsinfo));
+            }
+
+            self.fsinfo_dirty = false;
+        }
+
+        self.device.flush()
+    }
+
+    /// The sector cache's hit, miss, eviction, and write-back counters.
+    pub fn cache_stats(&self) -> CacheStats {
+        self.device.stats()
+    }
+
+    /// Sets the number of sectors the sector cache may hold, writing back and
+    /// evicting sectors if it currently holds more.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `capacity` is zero.
+    pub fn set_cache_capacity(&mut self, capacity: usize) -> io::Result<()> {
+        self.device.set_capacity(capacity)
+    }
+
+    /// Sets the number of sectors read ahead on sequential access. Zero
+    /// disables read-ahead.
+    pub fn set_read_ahead(&mut self, sectors: usize) {
+        self.device.set_read_ahead(sectors)
+    }
 }
 
 impl<'a> FileSystem for &'a Shared<VFat> {
-    type File = ::traits::Dummy;
-    type Dir = ::traits::Dummy;
-    type Entry = ::traits::Dummy;
+    type File = File;
+    type Dir = Dir;
+    type Entry = Entry;
 
     fn open<P: AsRef<Path>>(self, path: P) -> io::Result<Self::Entry> {
-        unimplemented!("FileSystem::open()")
+        use traits::Entry;
+
+        let path = path.as_ref();
+        if !path.is_absolute() {
+            return Err(io::Error::new(io::ErrorKind::InvalidInput,
+                                      "path is not absolute"));
+        }
+
+        let mut names = Vec::new();
+        for component in path.components() {
+            match component {
+                Component::Normal(name) => names.push(name),
+                Component::ParentDir => { names.pop(); }
+                _ => continue,
+            }
+        }
+
+        let mut entry = ::vfat::Entry::Dir(Dir::root(self.clone()));
+        for (i, name) in names.iter().enumerate() {
+            let dir = entry.into_dir().ok_or_else(|| {
+                io::Error::new(io::ErrorKind::InvalidInput, "not a directory")
+            })?;
+
+            entry = match dir.find(name) {
+                Err(ref e) if e.kind() == io::ErrorKind::NotFound && i + 1 < names.len() => {
+                    return Err(io::Error::new(io::ErrorKind::InvalidInput,
+                                              "parent directory not found"));
+                }
+                result => result?,
+            };
+        }
+
+        Ok(entry)
     }
 
-    fn create_file<P: AsRef<Path>>(self, _path: P) -> io::Result<Self::File> {
-        unimplemented!("read only file system")
+    fn create_file<P: AsRef<Path>>(self, path: P) -> io::Result<Self::File> {
+        let (dir, name) = open_parent(self, path.as_ref())?;
+        ensure_absent(&dir, &name)?;
+
+        let archive = Attributes::new(Attributes::ARCHIVE);
+        let regular = VFatRegularDirEntry::new([b' '; 11], archive, Cluster::from(0));
+        let location = dir.insert(&name, regular)?;
+        self.borrow_mut().flush()?;
+
+        Ok(File::new(self.clone(), None, name, regular.metadata(), 0, location))
     }
 
-    fn create_dir<P>(self, _path: P, _parents: bool) -> io::Result<Self::Dir>
+    fn create_dir<P>(self, path: P, parents: bool) -> io::Result<Self::Dir>
         where P: AsRef<Path>
     {
-        unimplemented!("read only file system")
+        let path = path.as_ref();
+        if parents {
+            let mut ancestors: Vec<&Path> = path.ancestors().skip(1).collect();
+            ancestors.reverse();
+            for ancestor in ancestors.into_iter().skip(1) {
+                match self.open(ancestor) {
+                    Ok(ref entry) if ::traits::Entry::is_dir(entry) => continue,
+                    Ok(_) => return Err(io::Error::new(io::ErrorKind::InvalidInput,
+                                                       "not a directory")),
+                    Err(ref e) if e.kind() == io::ErrorKind::NotFound => {
+                        self.create_dir(ancestor, false)?;
+                    }
+                    Err(e) => return Err(e),
+                }
+            }
+        }
+
+        let (parent, name) = open_parent(self, path)?;
+        ensure_absent(&parent, &name)?;
+
+        let directory = Attributes::new(Attributes::DIRECTORY);
+        let cluster = self.borrow_mut().alloc_cluster(None)?;
+        let parent_cluster = match parent.location {
+            Some(_) => parent.first_cluster,
+            None => Cluster::from(0),
+        };
+
+        let dot = VFatRegularDirEntry::new(*b".          ", directory, cluster);
+        let dot_dot = VFatRegularDirEntry::new(*b"..         ", directory, parent_cluster);
+        let written = self.borrow_mut()
+            .insert_dir_entries(cluster, &[dot.to_bytes(), dot_dot.to_bytes()]);
+
+        // Nothing refers to the new cluster until the parent's entry is
+        // written, so it is freed again if that fails.
+        let regular = VFatRegularDirEntry::new([b' '; 11], directory, cluster);
+        let location = match written.and_then(|_| parent.insert(&name, regular)) {
+            Ok(location) => location,
+            Err(e) => {
+                self.borrow_mut().free_chain(cluster)?;
+                return Err(e);
+            }
+        };
+        self.borrow_mut().flush()?;
+
+        Ok(Dir {
+            vfat: self.clone(),
+            first_cluster: cluster,
+            name: name,
+            metadata: regular.metadata(),
+            location: Some(location),
+        })
     }
 
-    fn rename<P, Q>(self, _from: P, _to: Q) -> io::Result<()>
+    fn rename<P, Q>(self, from: P, to: Q) -> io::Result<()>
         where P: AsRef<Path>, Q: AsRef<Path>
     {
-        unimplemented!("read only file system")
+        let (from, to) = (from.as_ref(), to.as_ref());
+        if !from.is_absolute() || !to.is_absolute() {
+            return Err(io::Error::new(io::ErrorKind::InvalidInput,
+                                      "path is not absolute"));
+        }
+
+        let entry = self.open(from)?;
+        let old_location = entry.location().ok_or_else(|| {
+            io::Error::new(io::ErrorKind::InvalidInput, "cannot rename the root directory")
+        })?;
+
+        let (parent, name) = open_parent(self, to)?;
+        match parent.find(&name) {
+            Ok(ref existing) if existing.location() == Some(old_location) => {
+                if ::traits::Entry::name(&entry) == name {
+                    return Ok(());
+                }
+            }
+            Ok(_) => return Err(io::Error::new(io::ErrorKind::AlreadyExists,
+                                               "entry already exists")),
+            Err(ref e) if e.kind() == io::ErrorKind::NotFound => {}
+            Err(e) => return Err(e),
+        }
+
+        let moved_dir = match entry {
+            ::vfat::Entry::Dir(ref dir) => {
+                if is_within(&parent, dir.first_cluster)? {
+                    return Err(io::Error::new(io::ErrorKind::InvalidInput,
+                                              "cannot move a directory into itself"));
+                }
+
+                Some(dir.first_cluster)
+            }
+            ::vfat::Entry::File(_) => None,
+        };
+
+        let regular = self.borrow_mut().read_dir_entry(old_location)?;
+        parent.insert(&name, regular)?;
+
+        let mut vfat = self.borrow_mut();
+        vfat.delete_dir_entries(old_location)?;
+        if let Some(cluster) = moved_dir {
+            if old_location.dir != parent.first_cluster {
+                let dot_dot = EntryLocation { dir: cluster, offset: 32, lfn_entries: 0 };
+                let mut entry = vfat.read_dir_entry(dot_dot)?;
+                entry.set_cluster(match parent.location {
+                    Some(_) => parent.first_cluster,
+                    None => Cluster::from(0),
+                });
+                vfat.write_dir_entry(dot_dot, &entry)?;
+            }
+        }
+
+        vfat.flush()
     }
 
-    fn remove<P: AsRef<Path>>(self, _path: P, _children: bool) -> io::Result<()> {
-        unimplemented!("read only file system")
+    fn remove<P: AsRef<Path>>(self, path: P, children: bool) -> io::Result<()> {
+        let path = path.as_ref();
+        if !path.is_absolute() {
+            return Err(io::Error::new(io::ErrorKind::InvalidInput,
+                                      "path is not absolute"));
+        }
+
+        let entry = self.open(path)?;
+        let location = entry.location().ok_or_else(|| {
+            io::Error::new(io::ErrorKind::InvalidInput, "cannot remove the root directory")
+        })?;
+
+        if let ::vfat::Entry::Dir(ref dir) = entry {
+            if !children {
+                return Err(io::Error::new(io::ErrorKind::Other, "entry is a directory"));
+            }
+
+            remove_children(dir)?;
+        }
+
+        let mut vfat = self.borrow_mut();
+        if let Some(cluster) = first_cluster(&entry) {
+            vfat.free_chain(cluster)?;
+        }
+
+        vfat.delete_dir_entries(location)?;
+        vfat.flush()
+    }
+}
+
+/// Returns the first cluster of `entry`'s chain, if it has one.
+fn first_cluster(entry: &Entry) -> Option<Cluster> {
+    match *entry {
+        Entry::File(ref file) => file.first_cluster,
+        Entry::Dir(ref dir) => Some(dir.first_cluster),
+    }
+}
+
+/// Frees the clusters of every entry below `dir`. The entries of `dir` itself
+/// are left untouched as they are freed with `dir`'s chain.
+fn remove_children(dir: &Dir) -> io::Result<()> {
+    use traits::{Dir, Entry};
+
+    let mut entries = dir.entries()?;
+    while let Some(entry) = entries.try_next()? {
+        if entry.name() == "." || entry.name() == ".." {
+            continue;
+        }
+
+        if let Some(dir) = entry.as_dir() {
+            remove_children(dir)?;
+        }
+
+        if let Some(cluster) = first_cluster(&entry) {
+            dir.vfat.borrow_mut().free_chain(cluster)?;
+        }
+    }
+
+    Ok(())
+}
+
+/// Returns `true` if `dir` is the directory whose chain starts at `cluster` or
+/// is below it, following the `..` entries from `dir` up to the root.
+fn is_within(dir: &Dir, cluster: Cluster) -> io::Result<bool> {
+    if dir.location.is_none() {
+        return Ok(false);
+    }
+
+    let mut vfat = dir.vfat.borrow_mut();
+    let root = vfat.root_dir_cluster();
+    let mut current = dir.first_cluster;
+    // A corrupt `..` entry could form a loop; no chain of directories is
+    // longer than the number of clusters.
+    for _ in 0..vfat.cluster_count() {
+        if current == cluster {
+            return Ok(true);
+        }
+
+        let dot_dot = EntryLocation { dir: current, offset: 32, lfn_entries: 0 };
+        current = vfat.read_dir_entry(dot_dot)?.cluster();
+        if !current.is_data() || current == root {
+            return Ok(false);
+        }
+    }
+
+    Ok(false)
+}
+
+/// Opens the parent directory of the absolute path `path` and returns it with
+/// the final component of `path`.
+///
+/// # Errors
+///
+/// Returns an error of `InvalidInput` if `path` is not absolute, has no final
+/// component, or if its parent is not an existing directory.
+fn open_parent(vfat: &Shared<VFat>, path: &Path) -> io::Result<(Dir, String)> {
+    let invalid = |msg| io::Error::new(io::ErrorKind::InvalidInput, msg);
+    if !path.is_absolute() {
+        return Err(invalid("path is not absolute"));
+    }
+
+    let name = path.file_name()
+        .and_then(|name| name.to_str())
+        .ok_or_else(|| invalid("path has no valid final component"))?;
+    let parent = path.parent().ok_or_else(|| invalid("path has no parent"))?;
+
+    match vfat.open_dir(parent) {
+        Ok(dir) => Ok((dir, name.to_string())),
+        Err(ref e) if e.kind() == io::ErrorKind::NotFound
+            || e.kind() == io::ErrorKind::Other => Err(invalid("parent is not a directory")),
+        Err(e) => Err(e),
+    }
+}
+
+/// Returns an error of `AlreadyExists` if `dir` has an entry named `name`.
+fn ensure_absent(dir: &Dir, name: &str) -> io::Result<()> {
+    match dir.find(name) {
+        Ok(_) => Err(io::Error::new(io::ErrorKind::AlreadyExists, "entry already exists")),
+        Err(ref e) if e.kind() == io::ErrorKind::NotFound => Ok(()),
+        Err(e) => Err(e),
     }
 }
@@ -0,0 +1,210 @@
+use std::fmt;
+use std::io;
+use std::path::{Path, PathBuf};
+
+use traits::{Dir, Entry, FileSystem, Metadata};
+
+/// The order in which `Walk` yields a directory relative to its contents.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Order {
+    /// A directory is yielded before its contents.
+    Pre,
+    /// A directory is yielded after its contents, as needed to remove a tree.
+    Post,
+}
+
+/// An entry found by `Walk`.
+#[derive(Debug)]
+pub struct WalkEntry<E: Entry> {
+    /// The absolute path of the entry.
+    pub path: PathBuf,
+    /// The number of directories between the entry and the walk's starting
+    /// directory: its direct children have depth 1.
+    pub depth: usize,
+    pub entry: E,
+}
+
+/// A directory being walked.
+struct Frame<D: Dir> {
+    entries: D::Iter,
+    path: PathBuf,
+    depth: usize,
+    /// The entry for the directory, held until its contents have been
+    /// yielded when walking in post-order.
+    dir: Option<WalkEntry<D::Entry>>,
+}
+
+/// An iterator over every entry below a directory, descending into
+/// subdirectories depth-first. The `.` and `..` entries are never yielded.
+///
+/// Each directory is read when the walk reaches it; an error opening or
+/// reading one is yielded in place of its remaining contents and the walk
+/// continues with its next sibling.
+pub struct Walk<D: Dir> {
+    stack: Vec<Frame<D>>,
+    order: Order,
+    max_depth: Option<usize>,
+    skip_hidden: bool,
+    filter: Option<Box<dyn FnMut(&WalkEntry<D::Entry>) -> bool>>,
+}
+
+/// Returns a pre-order walk of the entries below the directory at `path`.
+///
+/// # Errors
+///
+/// Returns the error of `FileSystem::open_dir` if the directory can't be
+/// opened or read.
+pub fn walk<F: FileSystem, P: AsRef<Path>>(fs: F, path: P) -> io::Result<Walk<F::Dir>> {
+    let path = path.as_ref();
+    Walk::new(fs.open_dir(path)?, path)
+}
+
+impl<D: Dir> Walk<D> where D::Entry: Entry<Dir = D> {
+    /// Returns a pre-order walk of the entries below `dir`, which is at
+    /// `path`.
+    ///
+    /// # Errors
+    ///
+    /// Returns the error of `Dir::entries` if `dir` can't be read.
+    pub fn new<P: AsRef<Path>>(dir: D, path: P) -> io::Result<Walk<D>> {
+        Ok(Walk {
+            stack: vec![Frame {
+                entries: dir.entries()?,
+                path: path.as_ref().to_path_buf(),
+                depth: 0,
+                dir: None,
+            }],
+            order: Order::Pre,
+            max_depth: None,
+            skip_hidden: false,
+            filter: None,
+        })
+    }
+
+    /// Sets the order in which directories are yielded relative to their
+    /// contents.
+    pub fn order(mut self, order: Order) -> Walk<D> {
+        self.order = order;
+        self
+    }
+
+    /// Yields entries no deeper than `depth`. Directories at that depth are
+    /// yielded but not read; a depth of 0 yields nothing.
+    pub fn max_depth(mut self, depth: usize) -> Walk<D> {
+        self.max_depth = Some(depth);
+        self
+    }
+
+    /// Skips entries whose metadata marks them as hidden, along with
+    /// everything below them.
+    pub fn skip_hidden(mut self, skip: bool) -> Walk<D> {
+        self.skip_hidden = skip;
+        self
+    }
+
+    /// Skips entries for which `filter` returns `false`, along with everything
+    /// below them.
+    pub fn filter<F>(mut self, filter: F) -> Walk<D>
+        where F: FnMut(&WalkEntry<D::Entry>) -> bool + 'static
+    {
+        self.filter = Some(Box::new(filter));
+        self
+    }
+
+    /// Returns the next entry of the directory on top of the stack that
+    /// should be yielded, or an error reading it, or `None` if it has no more.
+    fn next_child(&mut self) -> Option<io::Result<WalkEntry<D::Entry>>> {
+        let frame = self.stack.last_mut()?;
+        while let Some(entry) = frame.entries.next() {
+            let entry = match entry {
+                Ok(entry) => entry,
+                Err(e) => return Some(Err(e)),
+            };
+
+            if entry.name() == "." || entry.name() == ".." {
+                continue;
+            }
+
+            if self.skip_hidden && entry.metadata().hidden() {
+                continue;
+            }
+
+            let child = WalkEntry {
+                path: frame.path.join(entry.name()),
+                depth: frame.depth + 1,
+                entry: entry,
+            };
+
+            if self.filter.as_mut().map_or(true, |filter| filter(&child)) {
+                return Some(Ok(child));
+            }
+        }
+
+        None
+    }
+}
+
+impl<D: Dir> Iterator for Walk<D> where D::Entry: Entry<Dir = D> {
+    type Item = io::Result<WalkEntry<D::Entry>>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.max_depth == Some(0) {
+            return None;
+        }
+
+        while !self.stack.is_empty() {
+            let child = match self.next_child() {
+                Some(Ok(child)) => child,
+                Some(Err(e)) => return Some(Err(e)),
+                None => {
+                    match self.stack.pop().and_then(|frame| frame.dir) {
+                        Some(dir) => return Some(Ok(dir)),
+                        None => continue,
+                    }
+                }
+            };
+
+            if self.max_depth.map_or(false, |max| child.depth >= max) {
+                return Some(Ok(child));
+            }
+
+            let entries = match child.entry.as_dir().map(|dir| dir.entries()) {
+                None => return Some(Ok(child)),
+                Some(Err(e)) => return Some(Err(e)),
+                Some(Ok(entries)) => entries,
+            };
+
+            let mut frame = Frame {
+                entries: entries,
+                path: child.path.clone(),
+                depth: child.depth,
+                dir: None,
+            };
+
+            match self.order {
+                Order::Pre => {
+                    self.stack.push(frame);
+                    return Some(Ok(child));
+                }
+                Order::Post => {
+                    frame.dir = Some(child);
+                    self.stack.push(frame);
+                }
+            }
+        }
+
+        None
+    }
+}
+
+impl<D: Dir> fmt::Debug for Walk<D> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("Walk")
+            .field("depth", &self.stack.len())
+            .field("order", &self.order)
+            .field("max_depth", &self.max_depth)
+            .field("skip_hidden", &self.skip_hidden)
+            .field("filter", &self.filter.is_some())
+            .finish()
+    }
+}